@@ -37,5 +37,5 @@ if [ -z "$RUST_LOG" ]; then
 fi
 
 # bunyan --color have problem with the progress bar
-# cargo run --bin face-recognition-trainer-cli -- --client-type compreface --client-mode recognize | bunyan --color
-cargo run --bin face-recognition-trainer-cli -- --client-type compreface --client-mode train
\ No newline at end of file
+# cargo run --bin face-recognition-trainer-cli -- recognize --client-type compreface | bunyan --color
+cargo run --bin face-recognition-trainer-cli -- train --client-type compreface
\ No newline at end of file
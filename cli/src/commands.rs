@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use shared_api::{rollback, Command, Configuration, FaceCollection, FacesCommand, SubjectsCommand};
+
+/// run the subjects, faces or rollback command of the configuration, and print its result
+pub async fn run(
+    config: &Configuration,
+    collection: Arc<dyn FaceCollection>,
+) -> anyhow::Result<()> {
+    match config.command.clone() {
+        Command::Subjects { action, .. } => match action {
+            SubjectsCommand::List => {
+                for subject in collection.list_subjects().await? {
+                    println!("{}", subject);
+                }
+            }
+            SubjectsCommand::Add { subject } => {
+                collection.add_subject(&subject).await?;
+                println!("added subject: {}", subject);
+            }
+            SubjectsCommand::Rename { subject, new_name } => {
+                collection.rename_subject(&subject, &new_name).await?;
+                println!("renamed subject: {} to: {}", subject, new_name);
+            }
+            SubjectsCommand::Delete { subject } => {
+                collection.delete_subject(&subject).await?;
+                println!("deleted subject: {} with all its faces", subject);
+            }
+        },
+        Command::Faces { action, .. } => match action {
+            FacesCommand::List {
+                subject,
+                page,
+                size,
+            } => {
+                let faces_page = collection
+                    .list_faces(subject.as_deref(), page, size)
+                    .await?;
+                for face in faces_page.faces.iter() {
+                    println!("{} {}", face.image_id, face.subject);
+                }
+                println!(
+                    "page {} of {}, {} faces in total",
+                    faces_page.page_number + 1,
+                    faces_page.total_pages,
+                    faces_page.total_elements
+                );
+            }
+            FacesCommand::Delete { image_id } => {
+                let face = collection.delete_face(&image_id).await?;
+                println!(
+                    "deleted face: {} of subject: {}",
+                    face.image_id, face.subject
+                );
+            }
+            FacesCommand::Purge { subject } => {
+                let deleted = collection.delete_subject_faces(&subject).await?;
+                println!("deleted {} faces of subject: {}", deleted, subject);
+            }
+        },
+        Command::Rollback { run, .. } => {
+            let result = rollback(
+                collection.as_ref(),
+                &config.training_manifest,
+                &config.backend_id(),
+                &run,
+            )
+            .await?;
+            println!("deleted {} faces of run: {}", result.deleted, run);
+            if result.failed > 0 {
+                anyhow::bail!(
+                    "failed to delete {} faces of run: {}, run the rollback again to retry",
+                    result.failed,
+                    run
+                );
+            }
+        }
+        _ => unreachable!("only the subjects, faces and rollback commands use the collection"),
+    }
+    Ok(())
+}
@@ -1,17 +1,18 @@
+mod commands;
+
 use std::path::PathBuf;
 
-use anyhow::bail;
-use compreface_api::{recognize, train};
+use anyhow::{anyhow, bail};
 use dotenv::dotenv;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use shared_api::{
-    ClientMode, Configuration, ErrorBehavior, ErrorConfiguration, FaceProcessingResult,
-    FailureFace, PostRecognizeStrategy, ProcessProgress, ProgressReporter,
-};
-use tokio::{
-    fs::File,
-    task::{self, JoinHandle},
+    run_augment, run_evaluate, run_experiment, run_recognize, run_sync, run_train, run_verify,
+    write_comparison, write_evaluation, write_report, write_verification, BackendRegistry,
+    ClientMode, ClientType, Command, ConfigCommand, Configuration, ErrorBehavior,
+    ErrorConfiguration, Evaluation, FaceProcessingResult, FailureFace, MissedFace,
+    PostRecognizeStrategy, ProcessProgress, ProgressReporter,
 };
+use tokio::task::{self, JoinHandle};
 use tracing::{debug, error, info, warn};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
@@ -31,6 +32,79 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     info!(app_name, "starting");
 
     let config = Configuration::get()?;
+    let backend_registry = BackendRegistry::new()
+        .register(ClientType::Compreface, compreface_api::create_backend)
+        .register(ClientType::DoubleTake, double_take_api::create_backend)
+        .register(ClientType::Mock, mock_api::create_backend)
+        .register(ClientType::Local, local_api::create_backend)
+        .register_collection(ClientType::Compreface, compreface_api::create_collection)
+        .register_verifier(ClientType::Compreface, compreface_api::create_verifier)
+        .register_verifier(ClientType::Local, local_api::create_verifier);
+
+    // the config command prints the configuration, without running anything
+    if let Command::Config {
+        action: ConfigCommand::Show { .. },
+    } = config.command
+    {
+        print!("{}", config.effective);
+        return Ok(());
+    }
+    // the augment command writes the variants of the dataset, without any backend
+    if let Command::Augment { ref options, .. } = config.command {
+        let result = run_augment(&config, options).await?;
+        for missed_face in result.missed_faces.iter() {
+            println!(
+                "missed file: {}, reason: {}",
+                missed_face.path.display(),
+                missed_face.reason
+            );
+        }
+        println!("{} under: {}", result, options.output.display());
+        return Ok(());
+    }
+    // the experiment command runs its variants with their own configurations
+    if let Command::Experiment(ref options) = config.command {
+        let comparison = run_experiment(options, &backend_registry).await?;
+        if let Some(ref output) = options.output {
+            write_comparison(&comparison, output, options.format).await?;
+        }
+        print!("{}", comparison);
+        println!("finished");
+        return Ok(());
+    }
+    // a management command runs on its own, without processing the dataset
+    if matches!(
+        config.command,
+        Command::Subjects { .. } | Command::Faces { .. } | Command::Rollback { .. }
+    ) {
+        let collection = backend_registry.create_collection(&config)?;
+        commands::run(&config, collection).await?;
+        return Ok(());
+    }
+    // the verify mode compares pairs of images, without the trained subjects
+    if config.client_mode == ClientMode::Verify {
+        let verifier = backend_registry.create_verifier(&config)?;
+        let result = run_verify(&config, verifier).await?;
+        if let Some(ref verification_path) = config.verification.verification_output {
+            write_verification(
+                &result,
+                verification_path,
+                config.verification.verification_format,
+            )
+            .await?;
+        }
+        println!("{}", result);
+        println!("finished");
+        return Ok(());
+    }
+    let backend = backend_registry.create(&config)?;
+    // the sync mode compares the dataset with the remote collection
+    let collection = match config.client_mode {
+        ClientMode::Sync => Some(backend_registry.create_collection(&config)?),
+        _ => None,
+    };
+    // the faces of a run can only be rolled back by the client types that manage their faces
+    let supports_rollback = backend_registry.create_collection(&config).is_ok();
 
     let multi_progress_bar = MultiProgress::new();
     // represents the total files & folders progress bar
@@ -58,14 +132,58 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let (tx_train_progress, mut rx_train_progress) = tokio::sync::mpsc::channel(2);
     let (tx_recognize_progress, mut rx_recognize_progress) = tokio::sync::mpsc::channel(2);
 
-    let client_mode = config.client_mode.clone();
+    let client_mode = config.client_mode;
     let error_configuration = config.error_configuration.clone();
     // spawn the async task that will run the logic, let the ui get the updates while the long process is running
     let long_task = task::spawn(async move {
-        let result = match config.client_mode {
-            ClientMode::Train => train(&config, tx_train_progress.clone()).await?,
-            ClientMode::Recognize => recognize(&config, tx_recognize_progress.clone()).await?,
+        let (result, summary) = match config.client_mode {
+            ClientMode::Train => {
+                let result = run_train(&config, backend, tx_train_progress.clone()).await?;
+                // the run id is only useful when the service returned the trained image ids
+                let summary = (supports_rollback && !result.trained_faces.is_empty()).then(|| {
+                    let mut summary = format!(
+                        "run id: {}, delete its faces with: rollback --run {}",
+                        config.run_id(),
+                        config.run_id()
+                    );
+                    if result.untracked_count > 0 {
+                        summary.push_str(&format!(
+                            "\n{} trained faces have no image id, the rollback can not delete them",
+                            result.untracked_count
+                        ));
+                    }
+                    summary
+                });
+                (result, summary)
+            }
+            ClientMode::Recognize => (
+                run_recognize(&config, backend, tx_recognize_progress.clone()).await?,
+                None,
+            ),
+            ClientMode::Evaluate => {
+                let result = run_evaluate(&config, backend, tx_recognize_progress.clone()).await?;
+                let evaluation = Evaluation::new(&result.samples, &config.evaluation);
+                if let Some(ref evaluation_path) = config.evaluation.evaluation_output {
+                    write_evaluation(
+                        &evaluation,
+                        evaluation_path,
+                        config.evaluation.evaluation_format,
+                    )
+                    .await?;
+                }
+                (result, Some(evaluation.to_string()))
+            }
+            ClientMode::Sync => {
+                let collection = collection.ok_or(anyhow!("missing face collection"))?;
+                let (plan, result) =
+                    run_sync(&config, backend, collection, tx_train_progress.clone()).await?;
+                (result, Some(plan.to_string()))
+            }
+            ClientMode::Verify => unreachable!("the verify mode returns before the dataset run"),
         };
+        if let Some(ref report_path) = config.report {
+            write_report(&result, report_path, config.report_format).await?;
+        }
         tx_recognize_progress
             .send(ProgressReporter::AccumulatedStructedMessage(result.clone()))
             .await?;
@@ -75,13 +193,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 result
             )))
             .await?;
-        Ok::<_, anyhow::Error>(())
+        Ok::<_, anyhow::Error>((result, summary))
     });
 
     // wait for notifications on the rx channel
     let reporting_task = task::spawn(async move {
         match client_mode {
-            ClientMode::Train => {
+            ClientMode::Train | ClientMode::Sync => {
                 while let Some(progress_report) = rx_train_progress.recv().await {
                     on_progress(
                         progress_report,
@@ -92,7 +210,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .await;
                 }
             }
-            ClientMode::Recognize => {
+            ClientMode::Recognize | ClientMode::Evaluate | ClientMode::Verify => {
                 while let Some(progress_report) = rx_recognize_progress.recv().await {
                     on_progress(
                         progress_report,
@@ -109,7 +227,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     });
 
     match tokio::try_join!(flatten(long_task), flatten(reporting_task)) {
-        Ok(_) => debug!("Both tasks succeeded"),
+        Ok(((result, summary), _)) => {
+            debug!("Both tasks succeeded");
+            if matches!(client_mode, ClientMode::Recognize | ClientMode::Evaluate) {
+                print_recognition_breakdown(&result);
+            }
+            if let Some(summary) = summary {
+                println!("{}", summary);
+            }
+        }
         Err(e) => {
             error!("One of the tasks failed: {}", e);
             eprintln!("One of the tasks failed: {}", e);
@@ -120,6 +246,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// print the accuracy of each subject and the confusion matrix of the top predictions
+fn print_recognition_breakdown(result: &FaceProcessingResult) {
+    println!("Per subject accuracy:");
+    for (subject, summary) in result.subjects.iter() {
+        println!("{}: {}", subject, summary);
+    }
+    if !result.confusion_matrix.is_empty() {
+        print!("{}", result.confusion_matrix);
+    }
+}
+
 async fn write_failures(
     config: &ErrorConfiguration,
     result: FaceProcessingResult,
@@ -139,11 +276,11 @@ async fn write_failures(
 
 async fn write_all_missing_faces(
     config: &ErrorConfiguration,
-    files: Vec<std::path::PathBuf>,
+    faces: Vec<MissedFace>,
 ) -> Result<(), anyhow::Error> {
     let sub_folder = PathBuf::from(config.output_dir.as_ref().unwrap()).join("missed_faces");
     tokio::fs::create_dir_all(&sub_folder).await?;
-    for path in files {
+    for MissedFace { path, .. } in faces {
         let person_folder = sub_folder.join(path.parent().unwrap().file_stem().unwrap());
         tokio::fs::create_dir_all(&person_folder).await?;
         let file_name = path.file_name().unwrap().to_str().unwrap();
@@ -260,7 +397,7 @@ async fn on_progress(
         }
         ProgressReporter::PartialStructedMessage(result) => {
             // write the missing and failures files to the file
-            let _ = write_failures(&error_configuration, result)
+            let _ = write_failures(error_configuration, result)
             .await.inspect_err(|e| {
                 warn!("Failed to write the missing and failures files, but the process continue. error: {}", e);
             });
@@ -270,5 +407,7 @@ async fn on_progress(
             accumulated_progress_bar.set_position(message.get_success_count() as u64);
             accumulated_progress_bar.abandon();
         }
+        // the trained files are recorded by the run, the batch progress is reported by the other messages
+        ProgressReporter::Trained(..) => {}
     }
 }
@@ -0,0 +1,54 @@
+use std::{fs, path::Path, process::Command};
+
+use image::{Rgb, RgbImage};
+use tempfile::tempdir;
+
+fn write_image(path: &Path) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    RgbImage::from_fn(32, 24, |x, y| Rgb([(x * 8) as u8, (y * 10) as u8, 90]))
+        .save(path)
+        .unwrap();
+}
+
+#[test]
+fn test_augment_mirrors_the_dataset_without_a_backend() {
+    let dir = tempdir().unwrap();
+    let dataset = dir.path().join("dataset");
+    write_image(&dataset.join("alice").join("1.png"));
+    write_image(&dataset.join("bob").join("side").join("1.png"));
+    fs::write(dataset.join("bob").join("2.jpg"), "not an image").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_face-recognition-trainer-cli"))
+        .current_dir(dir.path())
+        .env_clear()
+        .args([
+            "augment",
+            "--dataset-path",
+            "dataset",
+            "--output",
+            "augmented",
+            "--transforms",
+            "flip,rotate=10,noise=0.02",
+            "--variants",
+            "2",
+            "--seed",
+            "42",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let augmented = dir.path().join("augmented");
+    for path in [
+        "alice/1_aug_1.png",
+        "alice/1_aug_2.png",
+        "bob/side/1_aug_1.png",
+        "bob/side/1_aug_2.png",
+    ] {
+        assert!(augmented.join(path).exists(), "{}", path);
+    }
+    assert!(!augmented.join("bob").join("2_aug_1.jpg").exists());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("augmented 2 images into 4 variants, missed: 1"));
+    assert!(stdout.contains("bob/2.jpg"));
+}
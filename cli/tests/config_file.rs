@@ -0,0 +1,103 @@
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Output},
+};
+
+use tempfile::tempdir;
+
+const CONFIG_FILE: &str = r#"
+dataset-path = "dataset"
+report = "report.json"
+concurrency = 2
+
+[profiles.mock]
+client-type = "mock"
+mock-fixture = "fixture.json"
+"#;
+
+/// run the cli in the given directory with the given environment variables only
+fn output(dir: &Path, envs: &[(&str, &str)], args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_face-recognition-trainer-cli"))
+        .current_dir(dir)
+        .env_clear()
+        .envs(envs.iter().copied())
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// run the cli and expect it to succeed
+fn run(dir: &Path, envs: &[(&str, &str)], args: &[&str]) -> Output {
+    let output = output(dir, envs, args);
+    assert!(output.status.success(), "{:?}", output);
+    output
+}
+
+#[test]
+fn test_profile_of_the_configuration_file() {
+    let dir = tempdir().unwrap();
+    for file in ["alice/1.jpg", "bob/1.jpg"] {
+        let path = dir.path().join("dataset").join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, file).unwrap();
+    }
+    fs::write(dir.path().join("fixture.json"), "{}").unwrap();
+    fs::write(dir.path().join("face-trainer.toml"), CONFIG_FILE).unwrap();
+
+    run(dir.path(), &[("FACE_TRAINER_PROFILE", "mock")], &["train"]);
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(dir.path().join("report.json")).unwrap()).unwrap();
+    assert_eq!(report["total_count"], 2);
+    assert_eq!(report["success_count"], 2);
+
+    let output = run(
+        dir.path(),
+        &[
+            ("COMPREFACE_API_KEY", "0f3cb33e-fbdf-4fb7-aea5-f293deeb339d"),
+            ("CONCURRENCY", "3"),
+        ],
+        &[
+            "--profile",
+            "mock",
+            "config",
+            "show",
+            "train",
+            "--report",
+            "other.json",
+        ],
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let value = |name: &str| {
+        stdout
+            .lines()
+            .find(|line| line.split_whitespace().next() == Some(name))
+            .and_then(|line| line.split(" = ").nth(1))
+            .unwrap()
+            .to_string()
+    };
+    assert_eq!(value("profile"), "mock (command line)");
+    assert_eq!(value("client-type"), "mock (profile: mock)");
+    assert_eq!(value("dataset-path"), "dataset (configuration file)");
+    assert_eq!(value("report"), "other.json (command line)");
+    assert_eq!(value("compreface-api-key"), "****339d (environment)");
+    // the environment overrides the configuration file
+    assert_eq!(value("concurrency"), "3 (environment)");
+    assert!(!stdout.contains("0f3cb33e"));
+    // config show does not run anything
+    assert!(!dir.path().join("other.json").exists());
+}
+
+#[test]
+fn test_default_configuration_file_is_required_only_by_a_profile() {
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("dataset")).unwrap();
+    let args = ["config", "show", "train", "--dataset-path", "dataset"];
+
+    run(dir.path(), &[], &args);
+    let output = output(dir.path(), &[("FACE_TRAINER_PROFILE", "mock")], &args);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("failed to read the configuration file")
+    );
+}
@@ -0,0 +1,89 @@
+use std::{fs, process::Command};
+
+use tempfile::tempdir;
+
+const EXPERIMENT: &str = r#"
+[options]
+client-type = "mock"
+
+[[variants]]
+name = "baseline"
+options = { mock-fixture = "baseline.json" }
+train = [{ dataset-path = "known" }]
+test = { dataset-path = "test" }
+
+[[variants]]
+name = "with-unknowns"
+options = { mock-fixture = "${WITH_UNKNOWNS_FIXTURE}" }
+train = [
+    { dataset-path = "known" },
+    { dataset-path = "famous", override-trained-name = "unknown" },
+]
+test = { dataset-path = "test" }
+"#;
+
+#[test]
+fn test_experiment_compares_the_variants() {
+    let dir = tempdir().unwrap();
+    for file in [
+        "known/alice/1.jpg",
+        "known/bob/1.jpg",
+        "famous/carol/1.jpg",
+        "test/alice/1.jpg",
+        "test/alice/2.jpg",
+        "test/bob/1.jpg",
+    ] {
+        let path = dir.path().join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, file).unwrap();
+    }
+    fs::write(
+        dir.path().join("baseline.json"),
+        r#"{"files": {"test/alice/2.jpg": {"outcome": "failure"}}}"#,
+    )
+    .unwrap();
+    fs::write(dir.path().join("with-unknowns.json"), "{}").unwrap();
+    fs::write(dir.path().join("experiment.toml"), EXPERIMENT).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_face-recognition-trainer-cli"))
+        .current_dir(dir.path())
+        .env_clear()
+        .env("WITH_UNKNOWNS_FIXTURE", "with-unknowns.json")
+        .args([
+            "experiment",
+            "experiment.toml",
+            "--output",
+            "comparison.csv",
+            "--format",
+            "csv",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let row = |label: &str| {
+        stdout
+            .lines()
+            .find(|line| line.starts_with(label))
+            .unwrap()
+            .split_whitespace()
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(row("variant"), vec!["variant", "baseline", "with-unknowns"]);
+    assert_eq!(row("trained"), vec!["trained", "2/2", "3/3"]);
+    assert_eq!(
+        row("success rate"),
+        vec!["success", "rate", "66.67%", "100.00%"]
+    );
+    assert_eq!(row("alice"), vec!["alice", "50.00%", "100.00%"]);
+
+    let comparison = fs::read_to_string(dir.path().join("comparison.csv")).unwrap();
+    assert!(comparison
+        .lines()
+        .any(|line| line == "with-unknowns,trained,,3,3,0,0,0,100.00"));
+    assert!(comparison
+        .lines()
+        .any(|line| line == "baseline,tested,,3,2,1,0,0,66.67"));
+}
@@ -0,0 +1,249 @@
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Output},
+};
+
+use serde_json::Value;
+use tempfile::{tempdir, TempDir};
+
+/// a dataset of alice with two images and bob with one, and the fixture of the mock backend
+fn setup(fixture: &str) -> TempDir {
+    let dir = tempdir().unwrap();
+    for (person, file) in [("alice", "1.jpg"), ("alice", "2.jpg"), ("bob", "1.jpg")] {
+        let person_dir = dir.path().join("dataset").join(person);
+        fs::create_dir_all(&person_dir).unwrap();
+        // the journal identifies the files by their content
+        fs::write(person_dir.join(file), format!("{}/{}", person, file)).unwrap();
+    }
+    fs::write(dir.path().join("fixture.json"), fixture).unwrap();
+    dir
+}
+
+/// run the command in the given directory, isolated from the environment and the .env file
+fn run(dir: &Path, command: &str, args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_face-recognition-trainer-cli"))
+        .current_dir(dir)
+        .env_clear()
+        .arg(command)
+        .args([
+            "--client-type",
+            "mock",
+            "--mock-fixture",
+            "fixture.json",
+            "--dataset-path",
+            "dataset",
+        ])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(
+        !String::from_utf8_lossy(&output.stderr).contains("One of the tasks failed"),
+        "{:?}",
+        output
+    );
+    output
+}
+
+fn read_json(path: &Path) -> Value {
+    serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+}
+
+#[test]
+fn test_train_reports_missed_files_and_records_the_run() {
+    let dir = setup(
+        r#"{"files": {"bob/1.jpg": {"outcome": "error", "status": 503, "message": "unavailable"}}}"#,
+    );
+    let output = run(
+        dir.path(),
+        "train",
+        &[
+            "--report",
+            "report.json",
+            "--run-id",
+            "run-1",
+            "--training-manifest",
+            "training.jsonl",
+        ],
+    );
+
+    let report = read_json(&dir.path().join("report.json"));
+    assert_eq!(report["total_count"], 3);
+    assert_eq!(report["success_count"], 2);
+    assert_eq!(report["missed_count"], 1);
+    assert!(report["missed"][0]["path"]
+        .as_str()
+        .unwrap()
+        .ends_with("bob/1.jpg"));
+    assert_eq!(report["subjects"]["alice"]["success_count"], 2);
+
+    let training_manifest = fs::read_to_string(dir.path().join("training.jsonl")).unwrap();
+    assert_eq!(training_manifest.lines().count(), 2);
+    assert!(training_manifest
+        .lines()
+        .all(|line| line.contains(r#""run_id":"run-1""#)));
+    // the mock client has no face collection, its faces can not be rolled back
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("rollback --run"));
+}
+
+#[test]
+fn test_recognize_copies_failures_and_missed_files() {
+    let dir = setup(
+        r#"{
+            "files": {
+                "alice/1.jpg": {"outcome": "success", "latency_ms": 10},
+                "alice/2.jpg": {"outcome": "failure", "subjects": [{"subject": "bob", "similarity": 0.8}]},
+                "bob/1.jpg": {"outcome": "error", "message": "connection refused"}
+            }
+        }"#,
+    );
+    let output = run(
+        dir.path(),
+        "recognize",
+        &["--error-behavior", "copy", "--output-dir", "output"],
+    );
+
+    let output_dir = dir.path().join("output");
+    // the max-similarity strategy names the copy by the recognized subject
+    assert!(output_dir
+        .join("failure_faces")
+        .join("alice")
+        .join("bob")
+        .exists());
+    assert!(output_dir
+        .join("missed_faces")
+        .join("bob")
+        .join("1.jpg")
+        .exists());
+    assert!(dir
+        .path()
+        .join("dataset")
+        .join("alice")
+        .join("2.jpg")
+        .exists());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("alice: 50.00% Total: 2, Success: 1, Failure: 1"));
+    assert!(stdout.contains("bob: 0.00% Total: 1, Success: 0, Failure: 0, missing: 1"));
+}
+
+#[test]
+fn test_recognize_applies_the_recognition_criteria() {
+    let dir = setup(
+        r#"{
+            "files": {
+                "alice/1.jpg": {"outcome": "success", "subjects": [{"subject": "alice", "similarity": 0.8}]},
+                "alice/2.jpg": {"outcome": "success", "faces": [
+                    {"box": {"probability": 0.99, "x_min": 0, "y_min": 0, "x_max": 10, "y_max": 10},
+                     "subjects": [{"subject": "alice", "similarity": 0.95}]},
+                    {"box": {"probability": 0.99, "x_min": 0, "y_min": 0, "x_max": 50, "y_max": 50},
+                     "subjects": [{"subject": "bob", "similarity": 0.97}]}
+                ]}
+            }
+        }"#,
+    );
+    run(
+        dir.path(),
+        "recognize",
+        &[
+            "--min-similarity",
+            "0.9",
+            "--multi-face-policy",
+            "largest-face",
+            "--report",
+            "report.json",
+        ],
+    );
+
+    // the counters agree with the confusion matrix
+    let report = read_json(&dir.path().join("report.json"));
+    assert_eq!(report["success_count"], 1);
+    assert_eq!(report["failure_count"], 2);
+    assert_eq!(report["confusion_matrix"]["alice"]["<none>"], 1);
+    assert_eq!(report["confusion_matrix"]["alice"]["bob"], 1);
+    assert_eq!(report["confusion_matrix"]["bob"]["bob"], 1);
+}
+
+#[test]
+fn test_resume_retries_only_the_unfinished_files() {
+    let dir = setup(r#"{"files": {"bob/1.jpg": {"outcome": "error", "message": "timeout"}}}"#);
+    let args = [
+        "--output-dir",
+        "output",
+        "--report",
+        "report.json",
+        "--resume",
+    ];
+    run(dir.path(), "train", &args);
+    assert_eq!(
+        read_json(&dir.path().join("report.json"))["missed_count"],
+        1
+    );
+
+    fs::write(dir.path().join("fixture.json"), "{}").unwrap();
+    run(dir.path(), "train", &args);
+    let report = read_json(&dir.path().join("report.json"));
+    assert_eq!(report["total_count"], 1);
+    assert_eq!(report["success_count"], 1);
+}
+
+#[test]
+fn test_augment_on_train_tags_the_variants() {
+    let dir = setup(r#"{"files": {"alice/1_aug_2.jpg": {"outcome": "failure"}}}"#);
+    // only a decodable image has variants, the other files are trained alone
+    image::RgbImage::from_fn(32, 24, |x, y| {
+        image::Rgb([(x * 8) as u8, (y * 10) as u8, 90])
+    })
+    .save(dir.path().join("dataset").join("alice").join("1.jpg"))
+    .unwrap();
+    run(
+        dir.path(),
+        "train",
+        &[
+            "--augment-on-train",
+            "flip,brightness=0.2",
+            "--augment-variants",
+            "2",
+            "--output-dir",
+            "output",
+            "--error-behavior",
+            "copy",
+            "--report",
+            "report.json",
+        ],
+    );
+
+    let report = read_json(&dir.path().join("report.json"));
+    assert_eq!(report["total_count"], 5);
+    assert_eq!(report["success_count"], 4);
+    assert_eq!(report["failure_count"], 1);
+    assert_eq!(report["subjects"]["alice"]["total_count"], 4);
+    // the variants are not on the disk, so they are reported apart from the failures
+    assert_eq!(report["failures"].as_array().unwrap().len(), 0);
+    let augmented = report["augmented"].as_array().unwrap();
+    assert_eq!(augmented.len(), 2);
+    let failed = augmented
+        .iter()
+        .find(|face| face["outcome"] == "failure")
+        .unwrap();
+    assert!(failed["path"]
+        .as_str()
+        .unwrap()
+        .ends_with("alice/1_aug_2.jpg"));
+    assert!(failed["source"].as_str().unwrap().ends_with("alice/1.jpg"));
+    assert!(failed["transforms"]
+        .as_str()
+        .unwrap()
+        .contains("brightness="));
+
+    let journal = fs::read_to_string(dir.path().join("output").join("journal.jsonl")).unwrap();
+    assert_eq!(journal.lines().count(), 5);
+    assert_eq!(
+        journal
+            .lines()
+            .filter(|line| line.contains(r#""transforms":"#))
+            .count(),
+        2
+    );
+}
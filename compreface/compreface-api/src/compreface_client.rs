@@ -1,27 +1,86 @@
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
+use anyhow::anyhow;
 use async_trait::async_trait;
 use compreface_contracts::CompreFaceConfig;
-use mime_guess::MimeGuess;
-use reqwest::{multipart::Part, Client};
-use serde::Deserialize;
+use futures::{stream, StreamExt, TryStreamExt};
+use reqwest::{multipart::Form, Client, Method, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
 use shared_api::{
-    FaceProcessingResult, FaceWithMetadata, FailureFace, ProgressReporter, Recognizer, Subject,
-    Trainer,
+    check_status, classify_faces, file_part, ApiError, AugmentedImage, FaceBox, FaceCollection,
+    FaceProcessingResult, FaceWithMetadata, FacesPage, FailureFace, MissedFace, ProgressReporter,
+    RecognitionCriteria, RecognitionSample, RecognizedFace, Recognizer, RejectedFace, RetryPolicy,
+    StoredFace, Subject, TrainedFace, Trainer, Upload, Verifier,
 };
-use tokio::{fs, io::AsyncReadExt, sync::mpsc::Sender};
-use tracing::{debug, error};
+use tokio::sync::{mpsc::Sender, Semaphore};
+use tracing::{debug, error, warn};
 
 /// Comperface client supports handling communication with the Comperface API.
 pub struct CompreFaceClient {
     client: Client,
     config: CompreFaceConfig,
+    /// the number of files of a single batch that are sent at the same time
+    concurrency: usize,
+    /// bound the number of uploads in flight across all the batches that share this client
+    upload_permits: Semaphore,
+    retry_policy: RetryPolicy,
+    /// decide which recognized subjects count as a success
+    recognition_criteria: RecognitionCriteria,
+    /// keep the raw subjects of each recognized file, only the evaluation reads them
+    keep_samples: bool,
 }
 
 impl CompreFaceClient {
     pub fn new(config: CompreFaceConfig) -> Self {
         let client = Client::new();
-        CompreFaceClient { client, config }
+        CompreFaceClient {
+            client,
+            config,
+            concurrency: 1,
+            upload_permits: Semaphore::new(1),
+            retry_policy: RetryPolicy::default(),
+            recognition_criteria: RecognitionCriteria::default(),
+            keep_samples: false,
+        }
+    }
+
+    /// allow up to `concurrency` uploads in flight at the same time
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self.upload_permits = Semaphore::new(concurrency);
+        self
+    }
+
+    /// retry the transient errors of each request with the given policy
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// fail each request that did not complete in the given time with a timeout error
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .expect("failed to build the http client");
+        self
+    }
+
+    /// count a recognition as a success only when it matches the given criteria
+    pub fn with_recognition_criteria(mut self, recognition_criteria: RecognitionCriteria) -> Self {
+        self.recognition_criteria = recognition_criteria;
+        self
+    }
+
+    /// keep the raw subjects of each recognized file on the result, to evaluate them later
+    pub fn with_samples(mut self, keep_samples: bool) -> Self {
+        self.keep_samples = keep_samples;
+        self
     }
 }
 
@@ -33,16 +92,38 @@ impl Trainer for CompreFaceClient {
         files: Vec<PathBuf>,
         progress_reporter_tx: Sender<ProgressReporter>,
     ) -> anyhow::Result<FaceProcessingResult> {
-        // this is postman example: {{compreface_base_url}}/api/v1/recognition/faces?subject={{subject_name}}
-        let url = format!(
-            "{}/api/v1/recognition/faces?subject={}",
-            self.config.compreface_url, name
-        );
+        let uploads = files.iter().map(|path| Upload::File(path)).collect();
+        self.train_uploads(name, uploads, progress_reporter_tx)
+            .await
+    }
+
+    async fn send_augmented_to_train(
+        &self,
+        name: &str,
+        images: Vec<AugmentedImage>,
+        progress_reporter_tx: Sender<ProgressReporter>,
+    ) -> anyhow::Result<FaceProcessingResult> {
+        let uploads = images.iter().map(Upload::Augmented).collect();
+        self.train_uploads(name, uploads, progress_reporter_tx)
+            .await
+    }
+}
+
+impl CompreFaceClient {
+    /// upload each image as a new face of the given subject
+    async fn train_uploads(
+        &self,
+        name: &str,
+        uploads: Vec<Upload<'_>>,
+        progress_reporter_tx: Sender<ProgressReporter>,
+    ) -> anyhow::Result<FaceProcessingResult> {
+        let url = self.train_url(name)?;
 
         let mut recognition_result = FaceProcessingResult::with_context(
-            files
+            uploads
                 .first()
                 .unwrap()
+                .path()
                 .parent()
                 .unwrap()
                 .to_str()
@@ -50,74 +131,221 @@ impl Trainer for CompreFaceClient {
                 .to_string(),
         );
 
-        recognition_result.total_count = files.len();
-        debug!("training directory {} with {} files", name, files.len());
-        for file_path in files {
-            debug!("sending file: {:?}", file_path);
-
-            let mime = MimeGuess::from_path(file_path.as_path()).first_or_octet_stream();
-            let mut file = fs::File::open(file_path.as_path()).await?;
-            let mut buffer = Vec::new();
-            file.read_to_end(&mut buffer).await?;
-
-            let part = Part::bytes(buffer)
-                .file_name(
-                    file_path
-                        .file_name()
-                        .unwrap()
-                        .to_string_lossy()
-                        .into_owned(),
-                )
-                .mime_str(mime.as_ref())?;
-            let form = reqwest::multipart::Form::new().part("file", part);
-
-            let response = self
-                .client
-                .post(&url)
-                .header("x-api-key", &self.config.compreface_api_key)
-                .multipart(form)
-                .send()
-                .await;
-            if let Err(e) = response {
-                error!(
-                    "Failed to train file: {} for name: {}: {}",
-                    file_path.display(),
-                    name,
-                    e
-                );
-                recognition_result.missed_count += 1;
-                recognition_result.missed_faces.push(file_path);
-                continue;
-            }
-            let response = response.unwrap();
-            progress_reporter_tx
-                .send(ProgressReporter::Increase(1))
-                .await?;
-            match response.status().as_u16() {
-                200 | 201 => {
-                    recognition_result.success_count += 1;
-                    debug!(
-                        "Training: {} for file: {} response: {}",
-                        name,
+        debug!("training directory {} with {} files", name, uploads.len());
+        // the futures are created upfront, they only start when the stream polls them
+        let uploads: Vec<_> = uploads
+            .into_iter()
+            .map(|upload| {
+                let url = url.as_str();
+                let progress_reporter_tx = progress_reporter_tx.clone();
+                async move {
+                    let file_path = upload.path().to_path_buf();
+                    let file_result = self
+                        .train_file(url, name, upload, progress_reporter_tx.clone())
+                        .await?;
+                    progress_reporter_tx
+                        .send(ProgressReporter::Trained(
+                            vec![file_path],
+                            file_result.clone(),
+                        ))
+                        .await?;
+                    Ok::<_, anyhow::Error>(file_result)
+                }
+            })
+            .collect();
+        let files_results: Vec<FaceProcessingResult> = stream::iter(uploads)
+            .buffer_unordered(self.concurrency)
+            .try_collect()
+            .await?;
+        for file_result in files_results {
+            recognition_result.add(file_result);
+        }
+        Ok(recognition_result)
+    }
+
+    /// detect the faces of the file with the detection service,
+    /// the file is rejected when it has no face or more than one face
+    async fn preflight(
+        &self,
+        detection_api_key: &str,
+        upload: Upload<'_>,
+    ) -> Result<Option<RejectedFace>, ApiError> {
+        // this is postman example: {{compreface_base_url}}/api/v1/detection/detect
+        let url = format!("{}/api/v1/detection/detect", self.config.compreface_url);
+        let mut query = Vec::new();
+        if let Some(det_prob_threshold) = self.config.compreface_det_prob_threshold {
+            query.push(("det_prob_threshold", det_prob_threshold.to_string()));
+        }
+        let url = &url;
+        let query = &query;
+        let response = self
+            .retry_policy
+            .retry(|| async move {
+                let form = Form::new().part("file", upload.part().await?);
+                let response = self
+                    .client
+                    .post(url)
+                    .header("x-api-key", detection_api_key)
+                    .query(query)
+                    .multipart(form)
+                    .send()
+                    .await?;
+                Ok(check_status(response)
+                    .await?
+                    .json::<DetectionApiResponse>()
+                    .await?)
+            })
+            .await;
+        let probabilities: Vec<f64> = match response {
+            Ok(response) => response
+                .result
+                .iter()
+                .map(|r| r.r#box.probability)
+                .collect(),
+            // compreface answers with 400 when no face was found in the image
+            Err(ApiError::Status {
+                status: 400,
+                ref message,
+            }) if message.contains("No face") => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        debug!(
+            "detected {} faces on file: {}",
+            probabilities.len(),
+            upload.path().display()
+        );
+        Ok(classify_faces(&probabilities).map(|reason| RejectedFace {
+            path: upload.path().to_path_buf(),
+            reason,
+            probabilities,
+        }))
+    }
+
+    /// upload a single image as a new face of the given subject
+    async fn train_file(
+        &self,
+        url: &str,
+        name: &str,
+        upload: Upload<'_>,
+        progress_reporter_tx: Sender<ProgressReporter>,
+    ) -> anyhow::Result<FaceProcessingResult> {
+        let file_path = upload.path().to_path_buf();
+        let _permit = self.upload_permits.acquire().await?;
+        let mut recognition_result = FaceProcessingResult::with_context(name.to_string());
+        recognition_result.total_count = 1;
+
+        if let Some(ref detection_api_key) = self.config.compreface_detection_api_key {
+            match self.preflight(detection_api_key, upload).await {
+                Ok(None) => {}
+                Ok(Some(rejected)) => {
+                    warn!(
+                        "Skip training file: {} for name: {}, the image has {}",
                         file_path.display(),
-                        &response.text().await?
+                        name,
+                        rejected.reason
                     );
+                    recognition_result.rejected_count += 1;
+                    recognition_result.rejected_faces.push(rejected);
+                    progress_reporter_tx
+                        .send(ProgressReporter::Increase(1))
+                        .await?;
+                    return Ok(recognition_result);
                 }
-                _ => {
-                    error!("Failed to train file: {}, for name: {}, response.status: {}, response text: {}, but will continue with the other files",
+                Err(e) => {
+                    error!(
+                        "Failed to detect file: {} for name: {}: {}",
                         file_path.display(),
                         name,
-                        &response.status(),
-                        &response.text().await?
+                        e
                     );
-                    recognition_result.failure_count += 1;
+                    recognition_result.missed_count += 1;
                     recognition_result
-                        .failure_faces
-                        .push(FailureFace::Train(file_path));
-                    continue;
+                        .missed_faces
+                        .push(MissedFace::new(file_path, e));
+                    progress_reporter_tx
+                        .send(ProgressReporter::Increase(1))
+                        .await?;
+                    return Ok(recognition_result);
                 }
             }
         }
+
+        debug!("sending file: {:?}", file_path);
+
+        // adding a face is not idempotent, it is sent again only when the service never received it
+        let response = self
+            .retry_policy
+            .retry_if(
+                || async move {
+                    let form = Form::new().part("file", upload.part().await?);
+                    let response = self
+                        .client
+                        .post(url)
+                        .header("x-api-key", self.config.api_key())
+                        .multipart(form)
+                        .send()
+                        .await?;
+                    Ok(check_status(response).await?.text().await?)
+                },
+                ApiError::is_unsent,
+            )
+            .await;
+        progress_reporter_tx
+            .send(ProgressReporter::Increase(1))
+            .await?;
+        match response {
+            Ok(text) => {
+                recognition_result.success_count += 1;
+                debug!(
+                    "Training: {} for file: {} response: {}",
+                    name,
+                    file_path.display(),
+                    &text
+                );
+                // the response is the stored face: {"image_id": "...", "subject": "..."}
+                match serde_json::from_str::<StoredFace>(&text) {
+                    Ok(face) => recognition_result.trained_faces.push(TrainedFace {
+                        path: file_path,
+                        image_id: face.image_id,
+                        subject: face.subject,
+                    }),
+                    Err(e) => {
+                        warn!(
+                            "Trained file: {} for name: {}, but its image id could not be read, the rollback will not delete it: {}",
+                            file_path.display(),
+                            name,
+                            e
+                        );
+                        recognition_result.untracked_count += 1;
+                    }
+                }
+            }
+            // the service rejected the file itself, for example when no face was found in the image
+            Err(e @ ApiError::Status { .. }) if !e.is_transient() => {
+                error!(
+                    "Failed to train file: {}, for name: {}, {}, but will continue with the other files",
+                    file_path.display(),
+                    name,
+                    e
+                );
+                recognition_result.failure_count += 1;
+                recognition_result
+                    .failure_faces
+                    .push(FailureFace::Train(file_path));
+            }
+            Err(e) => {
+                error!(
+                    "Failed to train file: {} for name: {}: {}",
+                    file_path.display(),
+                    name,
+                    e
+                );
+                recognition_result.missed_count += 1;
+                recognition_result
+                    .missed_faces
+                    .push(MissedFace::new(file_path, e));
+            }
+        }
         Ok(recognition_result)
     }
 }
@@ -148,105 +376,326 @@ impl Recognizer for CompreFaceClient {
                 .to_string(),
         );
 
-        for file_path in files {
-            debug!("sending file: {:?}", file_path);
-            recognition_result.total_count += 1;
-
-            let mime = MimeGuess::from_path(file_path.as_path()).first_or_octet_stream();
-            let mut file = fs::File::open(file_path.as_path()).await?;
-            let mut buffer = Vec::new();
-            file.read_to_end(&mut buffer).await?;
-
-            let part = Part::bytes(buffer)
-                .file_name(
-                    file_path
-                        .file_name()
-                        .unwrap()
-                        .to_string_lossy()
-                        .into_owned(),
-                )
-                .mime_str(mime.as_ref())?;
-            let form = reqwest::multipart::Form::new().part("file", part);
-
-            let response = match self
-                .client
-                .post(&url)
-                .header("x-api-key", &self.config.compreface_api_key)
-                .multipart(form)
-                .send()
-                .await
-            {
-                Ok(response) => response,
-                Err(e) => {
-                    error!(
-                        "Failed to recognize file: {} for name: {}: {}",
-                        file_path.display(),
-                        name,
-                        e
-                    );
-                    recognition_result.missed_count += 1;
-                    recognition_result.missed_faces.push(file_path);
-                    progress_reporter_tx
-                        .send(ProgressReporter::Increase(1))
-                        .await?;
-                    continue;
-                }
-            };
-            if response.status().is_success() {
-                match response.json::<RecognitionApiResponse>().await {
-                    Ok(response) => {
-                        if response
-                            .result
-                            .iter()
-                            .any(|r| r.subjects.iter().any(|s| s.subject == name))
-                        {
-                            recognition_result.success_count += 1;
-                        } else {
-                            recognition_result.failure_count += 1;
-                            recognition_result
-                                .failure_faces
-                                .push(FailureFace::Recognize(FaceWithMetadata {
-                                    path: file_path,
-                                    subjects: response.get_subjects(),
-                                }));
-                        }
-                    }
-                    Err(e) => {
-                        error!(
-                            "Failed to parse JSON response for file: {} for name: {} Error: {}",
+        let files_results: Vec<FaceProcessingResult> = stream::iter(files)
+            .map(|file_path| {
+                self.recognize_file(&url, name, file_path, progress_reporter_tx.clone())
+            })
+            .buffer_unordered(self.concurrency)
+            .try_collect()
+            .await?;
+        for file_result in files_results {
+            recognition_result.add(file_result);
+        }
+        Ok(recognition_result)
+    }
+}
+
+impl CompreFaceClient {
+    /// recognize the faces of a single file, and compare them to the expected subject name
+    async fn recognize_file(
+        &self,
+        url: &str,
+        name: &str,
+        file_path: PathBuf,
+        progress_reporter_tx: Sender<ProgressReporter>,
+    ) -> anyhow::Result<FaceProcessingResult> {
+        let _permit = self.upload_permits.acquire().await?;
+        let mut recognition_result = FaceProcessingResult::with_context(name.to_string());
+        debug!("sending file: {:?}", file_path);
+        recognition_result.total_count += 1;
+
+        let file_path_ref = &file_path;
+        let response = self
+            .retry_policy
+            .retry(|| async move {
+                let form = Form::new().part("file", file_part(file_path_ref).await?);
+                let response = self
+                    .client
+                    .post(url)
+                    .header("x-api-key", self.config.api_key())
+                    .query(&self.config.recognition_query())
+                    .multipart(form)
+                    .send()
+                    .await?;
+                Ok(check_status(response)
+                    .await?
+                    .json::<RecognitionApiResponse>()
+                    .await?)
+            })
+            .await;
+        match response {
+            Ok(response) => {
+                for item in response.result.iter() {
+                    if let Some(ref execution_time) = item.execution_time {
+                        debug!(
+                            "file: {} execution time: {:?}",
                             file_path.display(),
-                            name,
-                            e
+                            execution_time
                         );
-                        recognition_result.missed_count += 1;
-                        recognition_result.missed_faces.push(file_path);
                     }
                 }
-            } else {
-                error!("Failure response. status code: {}", response.status());
+                let faces = response.get_faces();
+                let labeled_subjects = self.recognition_criteria.labeled_subjects(&faces);
+                recognition_result.confusion_matrix.record(
+                    name,
+                    self.recognition_criteria
+                        .prediction(&labeled_subjects)
+                        .map(|s| s.subject.as_str()),
+                );
+                if self.keep_samples {
+                    recognition_result.samples.push(RecognitionSample {
+                        path: file_path.clone(),
+                        expected: name.to_string(),
+                        subjects: labeled_subjects,
+                    });
+                }
+                if self.recognition_criteria.is_recognized(name, &faces) {
+                    recognition_result.success_count += 1;
+                } else {
+                    recognition_result.failure_count += 1;
+                    recognition_result
+                        .failure_faces
+                        .push(FailureFace::Recognize(FaceWithMetadata {
+                            path: file_path,
+                            subjects: response.get_subjects(),
+                            faces,
+                        }));
+                }
+            }
+            Err(e) => {
+                error!(
+                    "Failed to recognize file: {} for name: {}: {}",
+                    file_path.display(),
+                    name,
+                    e
+                );
                 recognition_result.missed_count += 1;
-                recognition_result.missed_faces.push(file_path);
-                let error = response.json::<ErrorResponse>().await?;
-                error!("Detailed error: {:?}", error);
+                recognition_result
+                    .missed_faces
+                    .push(MissedFace::new(file_path, e));
             }
-            progress_reporter_tx
-                .send(ProgressReporter::Increase(1))
-                .await?;
         }
+        progress_reporter_tx
+            .send(ProgressReporter::Increase(1))
+            .await?;
         Ok(recognition_result)
     }
 }
 
+#[async_trait]
+impl Verifier for CompreFaceClient {
+    async fn verify(&self, source: &Path, target: &Path) -> anyhow::Result<Option<f64>> {
+        // this is postman example: {{compreface_base_url}}/api/v1/verification/verify
+        let url = format!("{}/api/v1/verification/verify", self.config.compreface_url);
+        let verification_api_key = self
+            .config
+            .compreface_verification_api_key
+            .as_deref()
+            .ok_or(anyhow!(
+                "--compreface-verification-api-key is required on verify mode"
+            ))?;
+        let _permit = self.upload_permits.acquire().await?;
+        let url = &url;
+        let response = self
+            .retry_policy
+            .retry(|| async move {
+                let form = Form::new()
+                    .part("source_image", file_part(source).await?)
+                    .part("target_image", file_part(target).await?);
+                let response = self
+                    .client
+                    .post(url)
+                    .header("x-api-key", verification_api_key)
+                    .multipart(form)
+                    .send()
+                    .await?;
+                Ok(check_status(response)
+                    .await?
+                    .json::<VerificationApiResponse>()
+                    .await?)
+            })
+            .await;
+        match response {
+            Ok(response) => Ok(response.similarity()),
+            // compreface answers with 400 when no face was found in one of the images
+            Err(ApiError::Status {
+                status: 400,
+                ref message,
+            }) if message.contains("No face") => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[async_trait]
+impl FaceCollection for CompreFaceClient {
+    async fn list_subjects(&self) -> anyhow::Result<Vec<String>> {
+        // {{compreface_base_url}}/api/v1/recognition/subjects
+        let url = self.url(&["subjects"])?;
+        let response: SubjectsResponse = self.send_json(Method::GET, url, None).await?;
+        Ok(response.subjects)
+    }
+
+    async fn add_subject(&self, subject: &str) -> anyhow::Result<()> {
+        let url = self.url(&["subjects"])?;
+        let _: SubjectResponse = self
+            .send_json(Method::POST, url, Some(json!({ "subject": subject })))
+            .await?;
+        Ok(())
+    }
+
+    async fn rename_subject(&self, subject: &str, new_name: &str) -> anyhow::Result<()> {
+        // {{compreface_base_url}}/api/v1/recognition/subjects/{{subject}}
+        let url = self.url(&["subjects", subject])?;
+        let _: serde_json::Value = self
+            .send_json(Method::PUT, url, Some(json!({ "subject": new_name })))
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_subject(&self, subject: &str) -> anyhow::Result<()> {
+        let url = self.url(&["subjects", subject])?;
+        let _: SubjectResponse = self.send_json(Method::DELETE, url, None).await?;
+        Ok(())
+    }
+
+    async fn list_faces(
+        &self,
+        subject: Option<&str>,
+        page: u32,
+        size: u32,
+    ) -> anyhow::Result<FacesPage> {
+        // {{compreface_base_url}}/api/v1/recognition/faces?page={{page}}&size={{size}}&subject={{subject}}
+        let mut url = self.url(&["faces"])?;
+        url.query_pairs_mut()
+            .append_pair("page", &page.to_string())
+            .append_pair("size", &size.to_string());
+        if let Some(subject) = subject {
+            url.query_pairs_mut().append_pair("subject", subject);
+        }
+        Ok(self.send_json(Method::GET, url, None).await?)
+    }
+
+    async fn delete_face(&self, image_id: &str) -> anyhow::Result<StoredFace> {
+        // {{compreface_base_url}}/api/v1/recognition/faces/{{image_id}}
+        let url = self.url(&["faces", image_id])?;
+        Ok(self.send_json(Method::DELETE, url, None).await?)
+    }
+
+    async fn delete_subject_faces(&self, subject: &str) -> anyhow::Result<u64> {
+        // {{compreface_base_url}}/api/v1/recognition/faces?subject={{subject}}
+        let mut url = self.url(&["faces"])?;
+        url.query_pairs_mut().append_pair("subject", subject);
+        let response: DeletedResponse = self.send_json(Method::DELETE, url, None).await?;
+        Ok(response.deleted)
+    }
+}
+
+impl CompreFaceClient {
+    /// the url of the training of the subject, the subject is encoded as a query value
+    fn train_url(&self, subject: &str) -> Result<Url, ApiError> {
+        // this is postman example: {{compreface_base_url}}/api/v1/recognition/faces?subject={{subject_name}}
+        let mut url = self.url(&["faces"])?;
+        url.query_pairs_mut().append_pair("subject", subject);
+        Ok(url)
+    }
+
+    /// the url of the recognition api, each segment is encoded as a single path segment
+    fn url(&self, segments: &[&str]) -> Result<Url, ApiError> {
+        let mut url = Url::parse(&self.config.compreface_url)
+            .map_err(|e| ApiError::Request(e.to_string()))?;
+        url.path_segments_mut()
+            .map_err(|_| ApiError::Request("invalid CompreFace url".to_string()))?
+            .pop_if_empty()
+            .extend(["api", "v1", "recognition"])
+            .extend(segments);
+        Ok(url)
+    }
+
+    /// send a request with an optional json body, and parse the json response
+    async fn send_json<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        url: Url,
+        body: Option<serde_json::Value>,
+    ) -> Result<T, ApiError> {
+        let method = &method;
+        let url = &url;
+        let body = &body;
+        self.retry_policy
+            .retry(|| async move {
+                let mut request = self
+                    .client
+                    .request(method.clone(), url.clone())
+                    .header("x-api-key", self.config.api_key());
+                if let Some(body) = body {
+                    request = request.json(body);
+                }
+                let response = request.send().await?;
+                Ok(check_status(response).await?.json::<T>().await?)
+            })
+            .await
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct SubjectsResponse {
+    subjects: Vec<String>,
+}
+
 #[derive(Deserialize, Debug)]
 #[allow(unused)]
-struct ErrorResponse {
-    message: String,
-    code: i32,
+struct SubjectResponse {
+    subject: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct DeletedResponse {
+    deleted: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct DetectionApiResponse {
+    result: Vec<DetectionItem>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DetectionItem {
+    r#box: DetectionBox,
+}
+
+#[derive(Deserialize, Debug)]
+struct VerificationApiResponse {
+    result: Vec<VerificationItem>,
+}
+
+impl VerificationApiResponse {
+    /// the highest similarity of any source face to any target face
+    fn similarity(&self) -> Option<f64> {
+        self.result
+            .iter()
+            .flat_map(|r| r.face_matches.iter().map(|m| m.similarity))
+            .reduce(f64::max)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct VerificationItem {
+    face_matches: Vec<FaceMatch>,
+}
+
+#[derive(Deserialize, Debug)]
+struct FaceMatch {
+    similarity: f64,
 }
 
 #[derive(Deserialize, Debug)]
 struct RecognitionApiResponse {
     result: Vec<ResultItem>,
+    /// the versions of the detector, calculator and plugins, only when status is set
+    #[serde(default)]
+    #[allow(unused)]
+    plugins_versions: Option<HashMap<String, String>>,
 }
 
 impl RecognitionApiResponse {
@@ -256,17 +705,73 @@ impl RecognitionApiResponse {
             .flat_map(|r| r.subjects.clone())
             .collect()
     }
+
+    /// each detected face with its own box and subjects
+    fn get_faces(&self) -> Vec<RecognizedFace> {
+        self.result
+            .iter()
+            .map(|r| RecognizedFace {
+                r#box: Some(FaceBox {
+                    probability: Some(r.r#box.probability),
+                    x_min: r.r#box.x_min,
+                    y_min: r.r#box.y_min,
+                    x_max: r.r#box.x_max,
+                    y_max: r.r#box.y_max,
+                }),
+                subjects: r.subjects.clone(),
+            })
+            .collect()
+    }
 }
 
+/// A single face of the recognition response, the optional fields are set by the face plugins
 #[derive(Deserialize, Debug)]
+#[allow(unused)]
 struct ResultItem {
-    #[allow(unused)]
     r#box: DetectionBox,
     subjects: Vec<Subject>,
+    #[serde(default)]
+    age: Option<AgeRange>,
+    #[serde(default)]
+    gender: Option<Classification>,
+    #[serde(default)]
+    mask: Option<Classification>,
+    #[serde(default)]
+    pose: Option<Pose>,
+    #[serde(default)]
+    landmarks: Option<Vec<[i32; 2]>>,
+    #[serde(default)]
+    embedding: Option<Vec<f64>>,
+    /// the execution time in milliseconds of each plugin, only when status is set
+    #[serde(default)]
+    execution_time: Option<HashMap<String, f64>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(unused)]
+struct AgeRange {
+    probability: f64,
+    high: u32,
+    low: u32,
 }
 
+/// the result of the gender and mask plugins
 #[derive(Deserialize, Debug)]
 #[allow(unused)]
+struct Classification {
+    probability: f64,
+    value: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(unused)]
+struct Pose {
+    pitch: f64,
+    roll: f64,
+    yaw: f64,
+}
+
+#[derive(Deserialize, Debug)]
 struct DetectionBox {
     probability: f64,
     x_max: u32,
@@ -274,3 +779,93 @@ struct DetectionBox {
     x_min: u32,
     y_min: u32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_response_with_plugins_and_status() {
+        let response: RecognitionApiResponse = serde_json::from_str(
+            r#"{
+                "result": [{
+                    "age": {"probability": 0.98, "high": 32, "low": 25},
+                    "gender": {"probability": 0.99, "value": "female"},
+                    "mask": {"probability": 0.95, "value": "without_mask"},
+                    "pose": {"pitch": -5.1, "roll": 2.3, "yaw": 10.7},
+                    "landmarks": [[100, 120], [140, 121]],
+                    "box": {"probability": 0.99, "x_max": 200, "y_max": 220, "x_min": 80, "y_min": 90},
+                    "subjects": [{"subject": "alice", "similarity": 0.97}],
+                    "execution_time": {"age": 28.0, "detector": 117.0, "calculator": 45.0}
+                }],
+                "plugins_versions": {"detector": "facenet.FaceDetector", "calculator": "facenet.Calculator"}
+            }"#,
+        )
+        .unwrap();
+        let item = &response.result[0];
+        assert_eq!(item.age.as_ref().unwrap().high, 32);
+        assert_eq!(item.gender.as_ref().unwrap().value, "female");
+        assert_eq!(item.execution_time.as_ref().unwrap()["detector"], 117.0);
+        assert_eq!(response.get_subjects()[0].subject, "alice");
+        assert_eq!(
+            response.get_faces()[0].r#box.as_ref().unwrap().area(),
+            120 * 130
+        );
+    }
+
+    #[test]
+    fn test_parse_verification_highest_similarity() {
+        let response: VerificationApiResponse = serde_json::from_str(
+            r#"{
+                "result": [{
+                    "source_image_face": {"box": {"probability": 0.99, "x_max": 200, "y_max": 220, "x_min": 80, "y_min": 90}},
+                    "face_matches": [
+                        {"box": {"probability": 0.99, "x_max": 210, "y_max": 230, "x_min": 85, "y_min": 95}, "similarity": 0.42},
+                        {"box": {"probability": 0.98, "x_max": 410, "y_max": 230, "x_min": 285, "y_min": 95}, "similarity": 0.97}
+                    ]
+                }]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(response.similarity(), Some(0.97));
+
+        let response: VerificationApiResponse =
+            serde_json::from_str(r#"{"result": [{"face_matches": []}]}"#).unwrap();
+        assert_eq!(response.similarity(), None);
+    }
+
+    #[test]
+    fn test_parse_response_without_plugins() {
+        let response: RecognitionApiResponse = serde_json::from_str(
+            r#"{"result": [{"box": {"probability": 0.99, "x_max": 200, "y_max": 220, "x_min": 80, "y_min": 90}, "subjects": []}]}"#,
+        )
+        .unwrap();
+        assert!(response.result[0].execution_time.is_none());
+        assert!(response.plugins_versions.is_none());
+    }
+
+    #[test]
+    fn test_url_encodes_each_segment() {
+        let client = CompreFaceClient::new(CompreFaceConfig {
+            compreface_url: "http://localhost:8000/".to_string(),
+            compreface_api_key: Some("key".to_string()),
+            compreface_detection_api_key: None,
+            compreface_verification_api_key: None,
+            compreface_limit: None,
+            compreface_prediction_count: None,
+            compreface_det_prob_threshold: None,
+            compreface_face_plugins: None,
+            compreface_status: None,
+        });
+        let url = client.url(&["subjects", "alice smith/2"]).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "http://localhost:8000/api/v1/recognition/subjects/alice%20smith%2F2"
+        );
+        let url = client.train_url("alice & bob+1#2").unwrap();
+        assert_eq!(
+            url.as_str(),
+            "http://localhost:8000/api/v1/recognition/faces?subject=alice+%26+bob%2B1%232"
+        );
+    }
+}
@@ -1,93 +1,49 @@
 mod compreface_client;
-use compreface_client::CompreFaceClient;
-use shared_api::{process_files, FaceProcessingResult, Recognizer, Trainer};
-use shared_api::{Configuration, ProgressReporter};
+use anyhow::anyhow;
+pub use compreface_client::CompreFaceClient;
+use shared_api::{Configuration, FaceBackend, FaceCollection, Verifier};
 use std::sync::Arc;
-use tokio::sync::mpsc::Sender;
-use tokio::sync::Mutex;
 
-pub async fn train(
-    config: &Configuration,
-    progress_reporter_tx: Sender<ProgressReporter>,
-) -> anyhow::Result<FaceProcessingResult> {
-    let api_client = Arc::new(CompreFaceClient::new(config.compreface.clone().unwrap()));
-    let state = Arc::new(Mutex::new(FaceProcessingResult::with_context(
-        config.dataset_path.to_string(),
-    )));
-    let state_result = state.clone();
-    let process_progress_reporter_tx = progress_reporter_tx.clone();
-    let api_progress_reporter_tx = progress_reporter_tx.clone();
-
-    process_files(
-        config,
-        process_progress_reporter_tx,
-        move |name: String, files, process_progress_reporter_tx| {
-            let api_client = Arc::clone(&api_client);
-            let cloned_result = state.clone();
-            let cloned_tx = api_progress_reporter_tx.clone();
-            async move {
-                let partial_result = api_client
-                    .send_to_train(&name, files, process_progress_reporter_tx)
-                    .await?;
-                // accumulate the result
-                let mut guard = cloned_result.lock().await;
-                guard.add(partial_result);
-                let report: FaceProcessingResult = guard.clone();
-                cloned_tx
-                    .send(ProgressReporter::AccumulatedStructedMessage(report))
-                    .await?;
-
-                Ok(())
-            }
-        },
-    )
-    .await?;
-    let state_result = state_result.lock().await.clone();
-    Ok(state_result)
+/// create the CompreFace backend from the configuration
+pub fn create_backend(config: &Configuration) -> anyhow::Result<Arc<dyn FaceBackend>> {
+    let compreface_config = config
+        .compreface
+        .clone()
+        .ok_or(anyhow!("missing CompreFace configuration"))?;
+    Ok(Arc::new(
+        CompreFaceClient::new(compreface_config)
+            .with_concurrency(config.concurrency)
+            .with_retry_policy(config.retry_policy.clone())
+            .with_recognition_criteria(config.recognition_criteria.clone())
+            .with_samples(config.keep_samples()),
+    ))
 }
 
-pub async fn recognize(
-    config: &Configuration,
-    progress_reporter_tx: Sender<ProgressReporter>,
-) -> anyhow::Result<FaceProcessingResult> {
-    let api_client = Arc::new(CompreFaceClient::new(config.compreface.clone().unwrap()));
-    let state = Arc::new(Mutex::new(FaceProcessingResult::with_context(
-        config.dataset_path.to_string(),
-    )));
-    let state_result = state.clone();
-    let process_progress_reporter_tx = progress_reporter_tx.clone();
-    let api_progress_reporter_tx = progress_reporter_tx.clone();
-    process_files(
-        config,
-        process_progress_reporter_tx,
-        move |name: String, files, process_progress_reporter_tx| {
-            let api_client = Arc::clone(&api_client);
-            let cloned_result = state.clone();
-            let cloned_tx = api_progress_reporter_tx.clone();
-            async move {
-                let partial_result = api_client
-                    .recognize(&name, files, process_progress_reporter_tx)
-                    .await?;
-
-                // send the partial result, before accumulating it
-                cloned_tx
-                    .send(ProgressReporter::PartialStructedMessage(
-                        partial_result.clone(),
-                    ))
-                    .await?;
-                // accumulate the result
-                let mut guard = cloned_result.lock().await;
-                guard.add(partial_result);
-                let report: FaceProcessingResult = guard.clone();
-                cloned_tx
-                    .send(ProgressReporter::AccumulatedStructedMessage(report))
-                    .await?;
+/// create the CompreFace subjects and faces collection from the configuration
+pub fn create_collection(config: &Configuration) -> anyhow::Result<Arc<dyn FaceCollection>> {
+    let compreface_config = config
+        .compreface
+        .clone()
+        .ok_or(anyhow!("missing CompreFace configuration"))?;
+    Ok(Arc::new(
+        CompreFaceClient::new(compreface_config).with_retry_policy(config.retry_policy.clone()),
+    ))
+}
 
-                Ok(())
-            }
-        },
-    )
-    .await?;
-    let state_result = state_result.lock().await.clone();
-    Ok(state_result)
+/// create the CompreFace verifier from the configuration
+pub fn create_verifier(config: &Configuration) -> anyhow::Result<Arc<dyn Verifier>> {
+    let compreface_config = config
+        .compreface
+        .clone()
+        .ok_or(anyhow!("missing CompreFace configuration"))?;
+    if compreface_config.compreface_verification_api_key.is_none() {
+        return Err(anyhow!(
+            "--compreface-verification-api-key is required on verify mode"
+        ));
+    }
+    Ok(Arc::new(
+        CompreFaceClient::new(compreface_config)
+            .with_concurrency(config.concurrency)
+            .with_retry_policy(config.retry_policy.clone()),
+    ))
 }
@@ -0,0 +1,229 @@
+use std::{path::PathBuf, time::Duration};
+
+use compreface_api::CompreFaceClient;
+use compreface_contracts::CompreFaceConfig;
+use compreface_stub::{ApiKeys, CompreFaceStub, Fault, MATCH_SIMILARITY};
+use shared_api::{
+    FaceCollection, FaceProcessingResult, ProgressReporter, Recognizer, RetryPolicy, Trainer,
+    Verifier,
+};
+use tempfile::{tempdir, TempDir};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+fn config(stub: &CompreFaceStub, api_key: &str) -> CompreFaceConfig {
+    let keys = ApiKeys::default();
+    CompreFaceConfig {
+        compreface_url: stub.url(),
+        compreface_api_key: Some(api_key.to_string()),
+        compreface_detection_api_key: None,
+        compreface_verification_api_key: Some(keys.verification),
+        compreface_limit: None,
+        compreface_prediction_count: None,
+        compreface_det_prob_threshold: None,
+        compreface_face_plugins: None,
+        compreface_status: None,
+    }
+}
+
+/// a client of the stub that keeps the samples, retrying twice without delay
+fn client(config: CompreFaceConfig) -> CompreFaceClient {
+    CompreFaceClient::new(config)
+        .with_retry_policy(RetryPolicy {
+            retry_max_attempts: 2,
+            retry_base_delay_ms: 1,
+            retry_max_delay_ms: 1,
+            retry_jitter: 0.0,
+        })
+        .with_samples(true)
+}
+
+/// the progress channel, the receiver is kept so the client can report
+fn progress() -> (Sender<ProgressReporter>, Receiver<ProgressReporter>) {
+    channel(100)
+}
+
+/// write the images of a single person, the content decides the faces of the stub
+fn person(dir: &TempDir, name: &str, contents: &[&str]) -> Vec<PathBuf> {
+    let person_dir = dir.path().join(name);
+    std::fs::create_dir_all(&person_dir).unwrap();
+    contents
+        .iter()
+        .enumerate()
+        .map(|(index, content)| {
+            let path = person_dir.join(format!("{}.jpg", index + 1));
+            std::fs::write(&path, content).unwrap();
+            path
+        })
+        .collect()
+}
+
+async fn train(client: &CompreFaceClient, name: &str, files: &[PathBuf]) -> FaceProcessingResult {
+    let (tx, _rx) = progress();
+    client
+        .send_to_train(name, files.to_vec(), tx)
+        .await
+        .unwrap()
+}
+
+async fn recognize(
+    client: &CompreFaceClient,
+    name: &str,
+    files: &[PathBuf],
+) -> FaceProcessingResult {
+    let (tx, _rx) = progress();
+    client.recognize(name, files.to_vec(), tx).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_train_then_recognize() {
+    let stub = CompreFaceStub::start(ApiKeys::default()).await.unwrap();
+    let client = client(config(&stub, &ApiKeys::default().recognition));
+    let dir = tempdir().unwrap();
+    let alice = person(&dir, "alice", &["alice 1", "alice 2"]);
+    let bob = person(&dir, "bob", &["bob 1"]);
+
+    let trained = train(&client, "alice", &alice).await;
+    assert_eq!(trained.success_count, 2);
+    assert_eq!(stub.subjects(), vec!["alice"]);
+    let mut trained_ids: Vec<String> = trained
+        .trained_faces
+        .iter()
+        .map(|f| f.image_id.clone())
+        .collect();
+    let mut stored_ids: Vec<String> = stub.faces().into_iter().map(|f| f.image_id).collect();
+    trained_ids.sort();
+    stored_ids.sort();
+    assert_eq!(trained_ids, stored_ids);
+
+    let recognized = recognize(&client, "alice", &alice[..1]).await;
+    assert_eq!(recognized.success_count, 1);
+    assert_eq!(
+        recognized.samples[0].subjects[0].similarity,
+        MATCH_SIMILARITY
+    );
+
+    // bob was never trained, so the only candidate is alice
+    let recognized = recognize(&client, "bob", &bob).await;
+    assert_eq!(recognized.failure_count, 1);
+    assert_eq!(recognized.samples[0].subjects[0].subject, "alice");
+}
+
+#[tokio::test]
+async fn test_rejected_requests_are_failures() {
+    let stub = CompreFaceStub::start(ApiKeys::default()).await.unwrap();
+    let dir = tempdir().unwrap();
+    let alice = person(&dir, "alice", &["alice 1", "no-face"]);
+
+    let trained = train(&client(config(&stub, "wrong-key")), "alice", &alice[..1]).await;
+    assert_eq!(trained.failure_count, 1);
+    assert!(stub.faces().is_empty());
+
+    // the service answers 400 when the image has no face
+    let client = client(config(&stub, &ApiKeys::default().recognition));
+    let trained = train(&client, "alice", &alice).await;
+    assert_eq!(trained.success_count, 1);
+    assert_eq!(trained.failure_count, 1);
+}
+
+#[tokio::test]
+async fn test_transient_errors_are_retried() {
+    let stub = CompreFaceStub::start(ApiKeys::default()).await.unwrap();
+    let client = client(config(&stub, &ApiKeys::default().recognition));
+    let dir = tempdir().unwrap();
+    let alice = person(&dir, "alice", &["alice 1", "alice 2"]);
+
+    train(&client, "alice", &alice[..1]).await;
+    stub.inject(Fault::Status(500));
+    let recognized = recognize(&client, "alice", &alice[..1]).await;
+    assert_eq!(recognized.success_count, 1);
+    assert_eq!(stub.requests(), 3);
+
+    // the service may have stored the face before the server error, so the training is not retried
+    stub.inject(Fault::Status(503));
+    let trained = train(&client, "alice", &alice[1..]).await;
+    assert_eq!(trained.missed_count, 1);
+    assert_eq!(stub.requests(), 4);
+    assert_eq!(stub.faces().len(), 1);
+}
+
+#[tokio::test]
+async fn test_timed_out_training_is_not_stored_twice() {
+    let stub = CompreFaceStub::start(ApiKeys::default()).await.unwrap();
+    let client = client(config(&stub, &ApiKeys::default().recognition))
+        .with_timeout(Duration::from_millis(100));
+    let dir = tempdir().unwrap();
+    let alice = person(&dir, "alice", &["alice 1"]);
+
+    // the service stored the face, but the client gave up waiting for the response
+    stub.inject(Fault::SlowResponse(Duration::from_millis(300)));
+    let trained = train(&client, "alice", &alice).await;
+    assert_eq!(trained.missed_count, 1);
+    assert!(trained.missed_faces[0].reason.contains("timeout"));
+    assert_eq!(stub.requests(), 1);
+    assert_eq!(stub.faces().len(), 1);
+}
+
+#[tokio::test]
+async fn test_malformed_and_slow_recognition() {
+    let stub = CompreFaceStub::start(ApiKeys::default()).await.unwrap();
+    let client = client(config(&stub, &ApiKeys::default().recognition));
+    let dir = tempdir().unwrap();
+    let alice = person(&dir, "alice", &["alice 1"]);
+    train(&client, "alice", &alice).await;
+
+    stub.inject(Fault::MalformedJson);
+    let recognized = recognize(&client, "alice", &alice).await;
+    assert_eq!(recognized.missed_count, 1);
+    assert!(recognized.missed_faces[0]
+        .reason
+        .contains("invalid response"));
+
+    stub.inject(Fault::Delay(Duration::from_millis(50)));
+    let recognized = recognize(&client, "alice", &alice).await;
+    assert_eq!(recognized.success_count, 1);
+}
+
+#[tokio::test]
+async fn test_preflight_rejects_no_face_and_multi_face() {
+    let stub = CompreFaceStub::start(ApiKeys::default()).await.unwrap();
+    let mut config = config(&stub, &ApiKeys::default().recognition);
+    config.compreface_detection_api_key = Some(ApiKeys::default().detection);
+    let client = client(config);
+    let dir = tempdir().unwrap();
+    let alice = person(&dir, "alice", &["alice 1", "no-face", "multi-face"]);
+
+    let trained = train(&client, "alice", &alice).await;
+    assert_eq!(trained.success_count, 1);
+    assert_eq!(trained.rejected_count, 2);
+    assert_eq!(stub.faces().len(), 1);
+}
+
+#[tokio::test]
+async fn test_collection_and_verification() {
+    let stub = CompreFaceStub::start(ApiKeys::default()).await.unwrap();
+    let client = client(config(&stub, &ApiKeys::default().recognition));
+    let dir = tempdir().unwrap();
+    let alice = person(&dir, "alice", &["alice 1", "alice 2", "no-face"]);
+    train(&client, "alice", &alice[..2]).await;
+
+    client.add_subject("bob").await.unwrap();
+    client.rename_subject("alice", "alice smith").await.unwrap();
+    assert_eq!(
+        client.list_subjects().await.unwrap(),
+        vec!["alice smith", "bob"]
+    );
+
+    let page = client.list_faces(Some("alice smith"), 0, 1).await.unwrap();
+    assert_eq!(page.total_elements, 2);
+    assert_eq!(page.total_pages, 2);
+    let deleted = client.delete_face(&page.faces[0].image_id).await.unwrap();
+    assert_eq!(deleted.subject, "alice smith");
+    assert_eq!(client.delete_subject_faces("alice smith").await.unwrap(), 1);
+    client.delete_subject("bob").await.unwrap();
+    assert!(client.delete_subject("bob").await.is_err());
+
+    let same = client.verify(&alice[0], &alice[0]).await.unwrap();
+    assert_eq!(same, Some(MATCH_SIMILARITY));
+    let no_face = client.verify(&alice[0], &alice[2]).await.unwrap();
+    assert_eq!(no_face, None);
+}
@@ -8,6 +8,72 @@ pub struct CompreFaceConfig {
     )]
     pub compreface_url: String,
 
-    #[clap(long, env = "COMPREFACE_API_KEY", help = "CompreFace API key")]
-    pub compreface_api_key: String,
+    /// Required when the client type is CompreFace
+    #[clap(
+        long,
+        env = "COMPREFACE_API_KEY",
+        required_if_eq("client_type", "compreface"),
+        help = "CompreFace API key"
+    )]
+    pub compreface_api_key: Option<String>,
+
+    /// The API key of a CompreFace detection service
+    /// When set, each image is detected before training, and images with no face or multiple faces are not trained
+    #[clap(long, env = "COMPREFACE_DETECTION_API_KEY")]
+    pub compreface_detection_api_key: Option<String>,
+
+    /// The API key of a CompreFace verification service, required on verify mode
+    #[clap(long, env = "COMPREFACE_VERIFICATION_API_KEY")]
+    pub compreface_verification_api_key: Option<String>,
+
+    /// The maximum number of faces to recognize on each image, the biggest faces first
+    /// When not set, CompreFace recognizes all the faces of the image
+    #[clap(long, env = "COMPREFACE_LIMIT")]
+    pub compreface_limit: Option<u32>,
+
+    /// The maximum number of subjects to return for each face
+    /// When not set, CompreFace returns the single most similar subject
+    #[clap(long, env = "COMPREFACE_PREDICTION_COUNT")]
+    pub compreface_prediction_count: Option<u32>,
+
+    /// The minimum probability, between 0 and 1, of a detected face to be recognized
+    /// When not set, CompreFace uses its own default
+    #[clap(long, env = "COMPREFACE_DET_PROB_THRESHOLD")]
+    pub compreface_det_prob_threshold: Option<f64>,
+
+    /// Comma separated face plugins to run on recognition, e.g. age,gender,mask,landmarks,calculator,pose
+    #[clap(long, env = "COMPREFACE_FACE_PLUGINS")]
+    pub compreface_face_plugins: Option<String>,
+
+    /// Ask CompreFace to return the system information, such as the execution time of each plugin
+    #[clap(long, env = "COMPREFACE_STATUS")]
+    pub compreface_status: Option<bool>,
+}
+
+impl CompreFaceConfig {
+    /// the API key of the recognition service, empty when it was not set
+    pub fn api_key(&self) -> &str {
+        self.compreface_api_key.as_deref().unwrap_or_default()
+    }
+
+    /// the query parameters of the recognition request, only the options that were set
+    pub fn recognition_query(&self) -> Vec<(&'static str, String)> {
+        let mut query = Vec::new();
+        if let Some(limit) = self.compreface_limit {
+            query.push(("limit", limit.to_string()));
+        }
+        if let Some(prediction_count) = self.compreface_prediction_count {
+            query.push(("prediction_count", prediction_count.to_string()));
+        }
+        if let Some(det_prob_threshold) = self.compreface_det_prob_threshold {
+            query.push(("det_prob_threshold", det_prob_threshold.to_string()));
+        }
+        if let Some(ref face_plugins) = self.compreface_face_plugins {
+            query.push(("face_plugins", face_plugins.clone()));
+        }
+        if let Some(status) = self.compreface_status {
+            query.push(("status", status.to_string()));
+        }
+        query
+    }
 }
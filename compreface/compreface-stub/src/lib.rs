@@ -0,0 +1,590 @@
+//! An in-process stand-in of the CompreFace API, to test the CompreFace client without Docker
+//!
+//! The stub keeps the subjects and the faces in memory, and checks the x-api-key header of each service.
+//! It does not detect real faces, the number of faces of an image is decided by its content:
+//! an image that contains `no-face` has no face, an image that contains `multi-face` has two faces,
+//! any other image has a single face.
+//! A face is recognized as the subjects that have a stored face with the same content.
+
+use std::{
+    collections::{BTreeSet, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::{Multipart, Path, Query, Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::{net::TcpListener, task::JoinHandle};
+
+/// the similarity of a face to a subject with a stored face of the same content
+pub const MATCH_SIMILARITY: f64 = 0.99;
+
+/// the similarity of a face to any other subject
+pub const MISMATCH_SIMILARITY: f64 = 0.1;
+
+/// The API key of each CompreFace service
+#[derive(Debug, Clone)]
+pub struct ApiKeys {
+    pub recognition: String,
+    pub detection: String,
+    pub verification: String,
+}
+
+impl Default for ApiKeys {
+    fn default() -> Self {
+        ApiKeys {
+            recognition: "recognition-key".to_string(),
+            detection: "detection-key".to_string(),
+            verification: "verification-key".to_string(),
+        }
+    }
+}
+
+/// A fault to inject, each fault is applied to a single request, in the order they were injected
+#[derive(Debug, Clone)]
+pub enum Fault {
+    /// answer with the given status and a json error body
+    Status(u16),
+    /// answer with success and a body that is not valid json
+    MalformedJson,
+    /// wait before handling the request as usual
+    Delay(Duration),
+    /// handle the request as usual and wait before answering, like a response that is lost after the change
+    SlowResponse(Duration),
+}
+
+/// A stored example face
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct StoredFace {
+    pub image_id: String,
+    pub subject: String,
+    #[serde(skip)]
+    content: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+struct Store {
+    keys: ApiKeys,
+    faults: VecDeque<Fault>,
+    /// the delay of the response of the request that is handled now
+    slow_response: Option<Duration>,
+    subjects: BTreeSet<String>,
+    faces: Vec<StoredFace>,
+    requests: usize,
+}
+
+type SharedStore = Arc<Mutex<Store>>;
+
+/// CompreFaceStub serves the CompreFace API on a random local port, until it is dropped
+pub struct CompreFaceStub {
+    address: SocketAddr,
+    store: SharedStore,
+    handle: JoinHandle<()>,
+}
+
+impl CompreFaceStub {
+    pub async fn start(keys: ApiKeys) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let store = Arc::new(Mutex::new(Store {
+            keys,
+            ..Default::default()
+        }));
+        let app = router(store.clone());
+        let handle = tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+        Ok(CompreFaceStub {
+            address,
+            store,
+            handle,
+        })
+    }
+
+    /// the base url, to use as the CompreFace url of the client
+    pub fn url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    /// apply the fault to the next request that was not faulted yet
+    pub fn inject(&self, fault: Fault) {
+        self.store.lock().unwrap().faults.push_back(fault);
+    }
+
+    pub fn subjects(&self) -> Vec<String> {
+        self.store
+            .lock()
+            .unwrap()
+            .subjects
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    pub fn faces(&self) -> Vec<StoredFace> {
+        self.store.lock().unwrap().faces.clone()
+    }
+
+    /// the number of requests that were received, including the faulted ones
+    pub fn requests(&self) -> usize {
+        self.store.lock().unwrap().requests
+    }
+}
+
+impl Drop for CompreFaceStub {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+fn router(store: SharedStore) -> Router {
+    Router::new()
+        .route(
+            "/api/v1/recognition/faces",
+            post(add_face).get(list_faces).delete(delete_subject_faces),
+        )
+        .route("/api/v1/recognition/faces/{image_id}", delete(delete_face))
+        .route("/api/v1/recognition/recognize", post(recognize))
+        .route(
+            "/api/v1/recognition/subjects",
+            get(list_subjects).post(add_subject),
+        )
+        .route(
+            "/api/v1/recognition/subjects/{subject}",
+            delete(delete_subject).put(rename_subject),
+        )
+        .route("/api/v1/detection/detect", post(detect))
+        .route("/api/v1/verification/verify", post(verify))
+        .layer(middleware::from_fn_with_state(store.clone(), slow_response))
+        .with_state(store)
+}
+
+/// delay the response of the request that was faulted with a slow response, after it was handled
+async fn slow_response(State(store): State<SharedStore>, request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let delay = store.lock().unwrap().slow_response.take();
+    if let Some(delay) = delay {
+        tokio::time::sleep(delay).await;
+    }
+    response
+}
+
+#[derive(Clone, Copy)]
+enum Service {
+    Recognition,
+    Detection,
+    Verification,
+}
+
+/// the error response of CompreFace: {"message": "...", "code": n}
+fn error(status: StatusCode, message: &str, code: u32) -> Response {
+    (status, Json(json!({ "message": message, "code": code }))).into_response()
+}
+
+fn no_face() -> Response {
+    error(
+        StatusCode::BAD_REQUEST,
+        "No face is found in the given image",
+        28,
+    )
+}
+
+/// count the request, check its api key and apply the next fault,
+/// a response is returned when the request should not be handled
+async fn before(store: &SharedStore, headers: &HeaderMap, service: Service) -> Option<Response> {
+    let fault = {
+        let mut store = store.lock().unwrap();
+        store.requests += 1;
+        let key = match service {
+            Service::Recognition => &store.keys.recognition,
+            Service::Detection => &store.keys.detection,
+            Service::Verification => &store.keys.verification,
+        };
+        match headers.get("x-api-key").and_then(|v| v.to_str().ok()) {
+            None => {
+                return Some(error(
+                    StatusCode::UNAUTHORIZED,
+                    "Missing header x-api-key",
+                    20,
+                ))
+            }
+            Some(api_key) if api_key != key => {
+                return Some(error(StatusCode::UNAUTHORIZED, "API key not found", 21))
+            }
+            Some(_) => {}
+        }
+        store.faults.pop_front()
+    };
+    match fault {
+        Some(Fault::Status(status)) => Some(error(
+            StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+            "injected fault",
+            0,
+        )),
+        Some(Fault::MalformedJson) => Some((StatusCode::OK, "{\"result\": [").into_response()),
+        Some(Fault::Delay(delay)) => {
+            tokio::time::sleep(delay).await;
+            None
+        }
+        Some(Fault::SlowResponse(delay)) => {
+            store.lock().unwrap().slow_response = Some(delay);
+            None
+        }
+        None => None,
+    }
+}
+
+/// the content of the multipart field with the given name
+async fn field(multipart: &mut Multipart, name: &str) -> Result<Vec<u8>, Response> {
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| error(StatusCode::BAD_REQUEST, &e.to_string(), 1))?
+    {
+        if field.name() == Some(name) {
+            return field
+                .bytes()
+                .await
+                .map(|bytes| bytes.to_vec())
+                .map_err(|e| error(StatusCode::BAD_REQUEST, &e.to_string(), 1));
+        }
+    }
+    Err(error(
+        StatusCode::BAD_REQUEST,
+        &format!("Required request part '{}' is not present", name),
+        1,
+    ))
+}
+
+fn contains(content: &[u8], pattern: &[u8]) -> bool {
+    content.windows(pattern.len()).any(|w| w == pattern)
+}
+
+fn face_count(content: &[u8]) -> usize {
+    if contains(content, b"no-face") {
+        0
+    } else if contains(content, b"multi-face") {
+        2
+    } else {
+        1
+    }
+}
+
+/// the box of the n-th face, the first face is the largest one
+fn face_box(index: usize) -> Value {
+    let offset = index as u32 * 300;
+    let size = 200 - index as u32 * 50;
+    json!({
+        "probability": 0.99,
+        "x_min": 80 + offset,
+        "y_min": 90,
+        "x_max": 80 + offset + size,
+        "y_max": 90 + size,
+    })
+}
+
+/// return the early response of the checks from the handler, when the request should not be handled
+macro_rules! checked {
+    ($store:expr, $headers:expr, $service:expr) => {
+        if let Some(response) = before(&$store, &$headers, $service).await {
+            return response;
+        }
+    };
+}
+
+#[derive(Deserialize)]
+struct SubjectQuery {
+    subject: Option<String>,
+}
+
+async fn add_face(
+    State(store): State<SharedStore>,
+    headers: HeaderMap,
+    Query(query): Query<SubjectQuery>,
+    mut multipart: Multipart,
+) -> Response {
+    checked!(store, headers, Service::Recognition);
+    let Some(subject) = query.subject else {
+        return error(StatusCode::BAD_REQUEST, "subject is required", 1);
+    };
+    let content = match field(&mut multipart, "file").await {
+        Ok(content) => content,
+        Err(response) => return response,
+    };
+    if face_count(&content) == 0 {
+        return no_face();
+    }
+    let face = StoredFace {
+        image_id: uuid::Uuid::new_v4().to_string(),
+        subject: subject.clone(),
+        content,
+    };
+    let mut store = store.lock().unwrap();
+    store.subjects.insert(subject);
+    store.faces.push(face.clone());
+    Json(face).into_response()
+}
+
+#[derive(Deserialize)]
+struct FacesQuery {
+    subject: Option<String>,
+    #[serde(default)]
+    page: usize,
+    #[serde(default = "default_size")]
+    size: usize,
+}
+
+fn default_size() -> usize {
+    20
+}
+
+async fn list_faces(
+    State(store): State<SharedStore>,
+    headers: HeaderMap,
+    Query(query): Query<FacesQuery>,
+) -> Response {
+    checked!(store, headers, Service::Recognition);
+    let store = store.lock().unwrap();
+    let faces: Vec<&StoredFace> = store
+        .faces
+        .iter()
+        .filter(|f| query.subject.as_ref().is_none_or(|s| &f.subject == s))
+        .collect();
+    let size = query.size.max(1);
+    let page: Vec<&StoredFace> = faces
+        .iter()
+        .skip(query.page * size)
+        .take(size)
+        .copied()
+        .collect();
+    Json(json!({
+        "faces": page,
+        "page_number": query.page,
+        "page_size": size,
+        "total_pages": faces.len().div_ceil(size),
+        "total_elements": faces.len(),
+    }))
+    .into_response()
+}
+
+async fn delete_subject_faces(
+    State(store): State<SharedStore>,
+    headers: HeaderMap,
+    Query(query): Query<SubjectQuery>,
+) -> Response {
+    checked!(store, headers, Service::Recognition);
+    let mut store = store.lock().unwrap();
+    let before = store.faces.len();
+    match query.subject {
+        Some(subject) => store.faces.retain(|f| f.subject != subject),
+        None => store.faces.clear(),
+    }
+    Json(json!({ "deleted": before - store.faces.len() })).into_response()
+}
+
+async fn delete_face(
+    State(store): State<SharedStore>,
+    headers: HeaderMap,
+    Path(image_id): Path<String>,
+) -> Response {
+    checked!(store, headers, Service::Recognition);
+    let mut store = store.lock().unwrap();
+    match store.faces.iter().position(|f| f.image_id == image_id) {
+        Some(index) => Json(store.faces.remove(index)).into_response(),
+        None => error(
+            StatusCode::NOT_FOUND,
+            &format!("Image with id {} not found", image_id),
+            30,
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+struct RecognizeQuery {
+    limit: Option<usize>,
+    prediction_count: Option<usize>,
+}
+
+/// the subjects of a face, ordered by similarity, only the first face can match the stored faces
+fn subjects_of(store: &Store, content: &[u8], index: usize, count: usize) -> Vec<Value> {
+    let mut subjects: Vec<(f64, &String)> = store
+        .subjects
+        .iter()
+        .map(|subject| {
+            let matched = index == 0
+                && store
+                    .faces
+                    .iter()
+                    .any(|f| &f.subject == subject && f.content == content);
+            let similarity = if matched {
+                MATCH_SIMILARITY
+            } else {
+                MISMATCH_SIMILARITY
+            };
+            (similarity, subject)
+        })
+        .collect();
+    subjects.sort_by(|a, b| b.0.total_cmp(&a.0));
+    subjects
+        .into_iter()
+        .take(count)
+        .map(|(similarity, subject)| json!({ "subject": subject, "similarity": similarity }))
+        .collect()
+}
+
+async fn recognize(
+    State(store): State<SharedStore>,
+    headers: HeaderMap,
+    Query(query): Query<RecognizeQuery>,
+    mut multipart: Multipart,
+) -> Response {
+    checked!(store, headers, Service::Recognition);
+    let content = match field(&mut multipart, "file").await {
+        Ok(content) => content,
+        Err(response) => return response,
+    };
+    let faces = face_count(&content).min(query.limit.unwrap_or(usize::MAX));
+    if faces == 0 {
+        return no_face();
+    }
+    let store = store.lock().unwrap();
+    let prediction_count = query.prediction_count.unwrap_or(1);
+    let result: Vec<Value> = (0..faces)
+        .map(|index| {
+            json!({
+                "box": face_box(index),
+                "subjects": subjects_of(&store, &content, index, prediction_count),
+            })
+        })
+        .collect();
+    Json(json!({ "result": result })).into_response()
+}
+
+async fn list_subjects(State(store): State<SharedStore>, headers: HeaderMap) -> Response {
+    checked!(store, headers, Service::Recognition);
+    let store = store.lock().unwrap();
+    Json(json!({ "subjects": store.subjects })).into_response()
+}
+
+#[derive(Deserialize)]
+struct SubjectBody {
+    subject: String,
+}
+
+async fn add_subject(
+    State(store): State<SharedStore>,
+    headers: HeaderMap,
+    Json(body): Json<SubjectBody>,
+) -> Response {
+    checked!(store, headers, Service::Recognition);
+    let mut store = store.lock().unwrap();
+    if !store.subjects.insert(body.subject.clone()) {
+        return error(
+            StatusCode::BAD_REQUEST,
+            &format!("Subject {} already exists", body.subject),
+            44,
+        );
+    }
+    Json(json!({ "subject": body.subject })).into_response()
+}
+
+async fn rename_subject(
+    State(store): State<SharedStore>,
+    headers: HeaderMap,
+    Path(subject): Path<String>,
+    Json(body): Json<SubjectBody>,
+) -> Response {
+    checked!(store, headers, Service::Recognition);
+    let mut store = store.lock().unwrap();
+    if !store.subjects.remove(&subject) {
+        return error(
+            StatusCode::NOT_FOUND,
+            &format!("Subject {} not found", subject),
+            42,
+        );
+    }
+    store.subjects.insert(body.subject.clone());
+    for face in store.faces.iter_mut().filter(|f| f.subject == subject) {
+        face.subject = body.subject.clone();
+    }
+    Json(json!({ "updated": true })).into_response()
+}
+
+async fn delete_subject(
+    State(store): State<SharedStore>,
+    headers: HeaderMap,
+    Path(subject): Path<String>,
+) -> Response {
+    checked!(store, headers, Service::Recognition);
+    let mut store = store.lock().unwrap();
+    if !store.subjects.remove(&subject) {
+        return error(
+            StatusCode::NOT_FOUND,
+            &format!("Subject {} not found", subject),
+            42,
+        );
+    }
+    store.faces.retain(|f| f.subject != subject);
+    Json(json!({ "subject": subject })).into_response()
+}
+
+async fn detect(
+    State(store): State<SharedStore>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Response {
+    checked!(store, headers, Service::Detection);
+    let content = match field(&mut multipart, "file").await {
+        Ok(content) => content,
+        Err(response) => return response,
+    };
+    let faces = face_count(&content);
+    if faces == 0 {
+        return no_face();
+    }
+    let result: Vec<Value> = (0..faces)
+        .map(|index| json!({ "box": face_box(index) }))
+        .collect();
+    Json(json!({ "result": result })).into_response()
+}
+
+async fn verify(
+    State(store): State<SharedStore>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Response {
+    checked!(store, headers, Service::Verification);
+    // the parts are read in the order the client sends them
+    let source = match field(&mut multipart, "source_image").await {
+        Ok(content) => content,
+        Err(response) => return response,
+    };
+    let target = match field(&mut multipart, "target_image").await {
+        Ok(content) => content,
+        Err(response) => return response,
+    };
+    if face_count(&source) == 0 || face_count(&target) == 0 {
+        return no_face();
+    }
+    let similarity = if source == target {
+        MATCH_SIMILARITY
+    } else {
+        MISMATCH_SIMILARITY
+    };
+    Json(json!({
+        "result": [{
+            "source_image_face": { "box": face_box(0) },
+            "face_matches": [{ "box": face_box(0), "similarity": similarity }],
+        }]
+    }))
+    .into_response()
+}
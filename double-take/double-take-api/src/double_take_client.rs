@@ -0,0 +1,510 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use double_take_contracts::DoubleTakeConfig;
+use futures::{stream, StreamExt, TryStreamExt};
+use reqwest::{multipart::Form, Client, Url};
+use serde::Deserialize;
+use shared_api::{
+    check_status, file_part, ApiError, AugmentedImage, FaceBox, FaceProcessingResult,
+    FaceWithMetadata, FailureFace, MissedFace, ProgressReporter, RecognitionCriteria,
+    RecognitionSample, RecognizedFace, Recognizer, RetryPolicy, Subject, Trainer, Upload,
+};
+use tokio::sync::{mpsc::Sender, Semaphore};
+use tracing::{debug, error};
+
+/// Double-take client supports handling communication with the Double-take API.
+pub struct DoubleTakeClient {
+    client: Client,
+    config: DoubleTakeConfig,
+    /// the number of files of a single batch that are recognized at the same time
+    concurrency: usize,
+    /// bound the number of requests in flight across all the batches that share this client
+    upload_permits: Semaphore,
+    retry_policy: RetryPolicy,
+    /// decide which recognized subjects count as a success
+    recognition_criteria: RecognitionCriteria,
+    /// keep the raw subjects of each recognized file, only the evaluation reads them
+    keep_samples: bool,
+}
+
+impl DoubleTakeClient {
+    pub fn new(config: DoubleTakeConfig) -> Self {
+        let client = Client::new();
+        DoubleTakeClient {
+            client,
+            config,
+            concurrency: 1,
+            upload_permits: Semaphore::new(1),
+            retry_policy: RetryPolicy::default(),
+            recognition_criteria: RecognitionCriteria::default(),
+            keep_samples: false,
+        }
+    }
+
+    /// allow up to `concurrency` requests in flight at the same time
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self.upload_permits = Semaphore::new(concurrency);
+        self
+    }
+
+    /// retry the transient errors of each request with the given policy
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// count a recognition as a success only when it matches the given criteria
+    pub fn with_recognition_criteria(mut self, recognition_criteria: RecognitionCriteria) -> Self {
+        self.recognition_criteria = recognition_criteria;
+        self
+    }
+
+    /// keep the raw subjects of each recognized file on the result, to evaluate them later
+    pub fn with_samples(mut self, keep_samples: bool) -> Self {
+        self.keep_samples = keep_samples;
+        self
+    }
+}
+
+#[async_trait]
+impl Trainer for DoubleTakeClient {
+    async fn send_to_train(
+        &self,
+        name: &str,
+        files: Vec<PathBuf>,
+        progress_reporter_tx: Sender<ProgressReporter>,
+    ) -> anyhow::Result<FaceProcessingResult> {
+        let uploads = files.iter().map(|path| Upload::File(path)).collect();
+        self.train_uploads(name, uploads, progress_reporter_tx)
+            .await
+    }
+
+    async fn send_augmented_to_train(
+        &self,
+        name: &str,
+        images: Vec<AugmentedImage>,
+        progress_reporter_tx: Sender<ProgressReporter>,
+    ) -> anyhow::Result<FaceProcessingResult> {
+        let uploads = images.iter().map(Upload::Augmented).collect();
+        self.train_uploads(name, uploads, progress_reporter_tx)
+            .await
+    }
+}
+
+impl DoubleTakeClient {
+    /// train all the images of a batch with a single request
+    async fn train_uploads(
+        &self,
+        name: &str,
+        uploads: Vec<Upload<'_>>,
+        progress_reporter_tx: Sender<ProgressReporter>,
+    ) -> anyhow::Result<FaceProcessingResult> {
+        // double-take accepts all the files of a batch in a single request: {{double_take_url}}/api/train/add/{{name}}
+        let url = self.url(&["train", "add", name])?;
+
+        let files: Vec<PathBuf> = uploads
+            .iter()
+            .map(|upload| upload.path().to_path_buf())
+            .collect();
+        let mut train_result = FaceProcessingResult::with_context(
+            files
+                .first()
+                .unwrap()
+                .parent()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string(),
+        );
+        train_result.total_count = files.len();
+        debug!("training directory {} with {} files", name, files.len());
+
+        let uploads_ref = &uploads;
+        let url = &url;
+        let permit = self.upload_permits.acquire().await?;
+        // adding faces is not idempotent, the request is sent again only when the service never received it
+        let response = self
+            .retry_policy
+            .retry_if(
+                || async move {
+                    let mut form = Form::new();
+                    for upload in uploads_ref.iter() {
+                        debug!("adding file: {:?}", upload.path());
+                        form = form.part("files[]", upload.part().await?);
+                    }
+                    let response = self.client.post(url.clone()).multipart(form).send().await?;
+                    Ok(check_status(response).await?.text().await?)
+                },
+                ApiError::is_unsent,
+            )
+            .await;
+        drop(permit);
+        progress_reporter_tx
+            .send(ProgressReporter::Increase(files.len() as u64))
+            .await?;
+        let trained_files = files.clone();
+        match response {
+            Ok(text) => {
+                train_result.success_count += files.len();
+                debug!(
+                    "Training: {} with {} files response: {}",
+                    name,
+                    files.len(),
+                    &text
+                );
+            }
+            // the service rejected the request itself
+            Err(e @ ApiError::Status { .. }) if !e.is_transient() => {
+                error!(
+                    "Failed to train {} files, for name: {}, {}, but will continue with the other files",
+                    files.len(),
+                    name,
+                    e
+                );
+                train_result.failure_count += files.len();
+                train_result
+                    .failure_faces
+                    .extend(files.into_iter().map(FailureFace::Train));
+            }
+            Err(e) => {
+                error!(
+                    "Failed to train {} files for name: {}: {}",
+                    files.len(),
+                    name,
+                    e
+                );
+                train_result.missed_count += files.len();
+                train_result
+                    .missed_faces
+                    .extend(files.into_iter().map(|path| MissedFace::new(path, &e)));
+            }
+        }
+        // the whole batch is a single request, it is reported at once
+        progress_reporter_tx
+            .send(ProgressReporter::Trained(
+                trained_files,
+                train_result.clone(),
+            ))
+            .await?;
+        Ok(train_result)
+    }
+}
+
+#[async_trait]
+impl Recognizer for DoubleTakeClient {
+    async fn recognize(
+        &self,
+        name: &str,
+        files: Vec<PathBuf>,
+        progress_reporter_tx: Sender<ProgressReporter>,
+    ) -> anyhow::Result<FaceProcessingResult> {
+        // each file is recognized on its own request: {{double_take_url}}/api/recognize/upload
+        let url = self.url(&["recognize", "upload"])?;
+
+        debug!("recognizing directory {} with {} files", name, files.len());
+        let mut recognition_result = FaceProcessingResult::with_context(
+            files
+                .first()
+                .unwrap()
+                .parent()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string(),
+        );
+
+        let files_results: Vec<FaceProcessingResult> = stream::iter(files)
+            .map(|file_path| {
+                self.recognize_file(&url, name, file_path, progress_reporter_tx.clone())
+            })
+            .buffer_unordered(self.concurrency)
+            .try_collect()
+            .await?;
+        for file_result in files_results {
+            recognition_result.add(file_result);
+        }
+        Ok(recognition_result)
+    }
+}
+
+impl DoubleTakeClient {
+    /// the url of the api, each segment is encoded as a single path segment
+    fn url(&self, segments: &[&str]) -> Result<Url, ApiError> {
+        let mut url = Url::parse(&self.config.doubletake_url)
+            .map_err(|e| ApiError::Request(e.to_string()))?;
+        url.path_segments_mut()
+            .map_err(|_| ApiError::Request("invalid Double-take url".to_string()))?
+            .pop_if_empty()
+            .push("api")
+            .extend(segments);
+        Ok(url)
+    }
+
+    /// recognize the faces of a single file, and compare them to the expected subject name
+    async fn recognize_file(
+        &self,
+        url: &Url,
+        name: &str,
+        file_path: PathBuf,
+        progress_reporter_tx: Sender<ProgressReporter>,
+    ) -> anyhow::Result<FaceProcessingResult> {
+        let _permit = self.upload_permits.acquire().await?;
+        let mut recognition_result = FaceProcessingResult::with_context(name.to_string());
+        debug!("sending file: {:?}", file_path);
+        recognition_result.total_count += 1;
+
+        let file_path_ref = &file_path;
+        let response = self
+            .retry_policy
+            .retry(|| async move {
+                let form = Form::new().part("files[]", file_part(file_path_ref).await?);
+                let response = self.client.post(url.clone()).multipart(form).send().await?;
+                Ok(check_status(response)
+                    .await?
+                    .json::<Vec<UploadResult>>()
+                    .await?)
+            })
+            .await;
+        match response {
+            Ok(response) => {
+                let faces = get_faces(&response, false);
+                let labeled_subjects = self.recognition_criteria.labeled_subjects(&faces);
+                recognition_result.confusion_matrix.record(
+                    name,
+                    self.recognition_criteria
+                        .prediction(&labeled_subjects)
+                        .map(|s| s.subject.as_str()),
+                );
+                if self.keep_samples {
+                    recognition_result.samples.push(RecognitionSample {
+                        path: file_path.clone(),
+                        expected: name.to_string(),
+                        subjects: labeled_subjects,
+                    });
+                }
+                // double-take decides the match by its own detector thresholds, the criteria can only tighten it
+                if response
+                    .iter()
+                    .any(|r| r.is_recognized(name, &self.recognition_criteria))
+                {
+                    recognition_result.success_count += 1;
+                } else {
+                    recognition_result.failure_count += 1;
+                    recognition_result
+                        .failure_faces
+                        .push(FailureFace::Recognize(FaceWithMetadata {
+                            path: file_path,
+                            subjects: get_subjects(&response),
+                            faces,
+                        }));
+                }
+            }
+            Err(e) => {
+                error!(
+                    "Failed to recognize file: {} for name: {}: {}",
+                    file_path.display(),
+                    name,
+                    e
+                );
+                recognition_result.missed_count += 1;
+                recognition_result
+                    .missed_faces
+                    .push(MissedFace::new(file_path, e));
+            }
+        }
+        progress_reporter_tx
+            .send(ProgressReporter::Increase(1))
+            .await?;
+        Ok(recognition_result)
+    }
+}
+
+/// flatten the matches of all the detectors into subjects, double-take reports the confidence in percents
+fn get_subjects(results: &[UploadResult]) -> Vec<Subject> {
+    results
+        .iter()
+        .flat_map(|r| r.results.iter())
+        .flat_map(|d| d.results.iter())
+        .map(|m| Subject {
+            subject: m.name.clone(),
+            similarity: m.confidence / 100.0,
+        })
+        .collect()
+}
+
+/// each detected face of all the detectors, with its box and subject
+/// when `matched_only` is set, a face that double-take did not match is kept without a subject
+fn get_faces(results: &[UploadResult], matched_only: bool) -> Vec<RecognizedFace> {
+    results
+        .iter()
+        .flat_map(|r| r.results.iter())
+        .flat_map(|d| d.faces(matched_only))
+        .collect()
+}
+
+/// The result of a single uploaded file, with the result of each configured detector
+#[derive(Deserialize, Debug)]
+struct UploadResult {
+    #[serde(default)]
+    results: Vec<DetectorResult>,
+}
+
+impl UploadResult {
+    /// true when the matched faces of one of the detectors are recognized as the given name,
+    /// each detector sees all the faces of the image, so the multi face policy is applied per detector
+    fn is_recognized(&self, name: &str, criteria: &RecognitionCriteria) -> bool {
+        self.results
+            .iter()
+            .any(|d| criteria.is_recognized(name, &d.faces(true)))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(unused)]
+struct DetectorResult {
+    detector: String,
+    #[serde(default)]
+    results: Vec<DetectorMatch>,
+}
+
+impl DetectorResult {
+    /// every face of the detector, with the subject as similarity between 0 and 1
+    fn faces(&self, matched_only: bool) -> Vec<RecognizedFace> {
+        self.results
+            .iter()
+            .map(|m| RecognizedFace {
+                r#box: m.r#box.as_ref().map(|b| FaceBox {
+                    probability: None,
+                    x_min: b.left,
+                    y_min: b.top,
+                    x_max: b.left + b.width,
+                    y_max: b.top + b.height,
+                }),
+                subjects: if matched_only && !m.is_match {
+                    Vec::new()
+                } else {
+                    vec![Subject {
+                        subject: m.name.clone(),
+                        similarity: m.confidence / 100.0,
+                    }]
+                },
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct DetectorMatch {
+    name: String,
+    confidence: f64,
+    #[serde(rename = "match", default)]
+    is_match: bool,
+    #[serde(default)]
+    r#box: Option<MatchBox>,
+}
+
+/// The location of the face on the image, in pixels
+#[derive(Deserialize, Debug)]
+struct MatchBox {
+    top: u32,
+    left: u32,
+    width: u32,
+    height: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Vec<UploadResult> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_parse_response_of_all_the_detectors() {
+        let response = parse(
+            r#"[{
+                "results": [{
+                    "detector": "compreface",
+                    "results": [
+                        {"name": "alice", "confidence": 98.5, "match": true,
+                         "box": {"top": 10, "left": 20, "width": 30, "height": 40}},
+                        {"name": "bob", "confidence": 40.0, "match": false}
+                    ]
+                }, {
+                    "detector": "deepstack",
+                    "results": [{"name": "alice", "confidence": 75.0, "match": true}]
+                }]
+            }]"#,
+        );
+
+        let subjects = get_subjects(&response);
+        assert_eq!(subjects.len(), 3);
+        assert_eq!(subjects[0].subject, "alice");
+        assert_eq!(subjects[0].similarity, 0.985);
+        assert_eq!(subjects[1].subject, "bob");
+
+        let faces = get_faces(&response, false);
+        assert_eq!(faces.len(), 3);
+        let face_box = faces[0].r#box.as_ref().unwrap();
+        assert_eq!(
+            (
+                face_box.x_min,
+                face_box.y_min,
+                face_box.x_max,
+                face_box.y_max
+            ),
+            (20, 10, 50, 50)
+        );
+        assert!(faces[1].r#box.is_none());
+        assert_eq!(faces[1].subjects[0].subject, "bob");
+
+        // a face that double-take did not match is kept without a subject
+        let faces = get_faces(&response, true);
+        assert_eq!(faces.len(), 3);
+        assert!(faces[1].subjects.is_empty());
+    }
+
+    #[test]
+    fn test_is_recognized_only_by_the_matched_faces() {
+        let response = parse(
+            r#"[{"results": [{"detector": "compreface", "results": [
+                {"name": "alice", "confidence": 60.0, "match": false},
+                {"name": "bob", "confidence": 90.0, "match": true}
+            ]}]}]"#,
+        );
+        let criteria = RecognitionCriteria::default();
+        assert!(response[0].is_recognized("bob", &criteria));
+        assert!(!response[0].is_recognized("alice", &criteria));
+
+        let strict = RecognitionCriteria {
+            min_similarity: 0.95,
+            ..Default::default()
+        };
+        assert!(!response[0].is_recognized("bob", &strict));
+    }
+
+    #[test]
+    fn test_parse_response_without_results() {
+        let response = parse(r#"[{}, {"results": [{"detector": "compreface"}]}]"#);
+        assert!(get_subjects(&response).is_empty());
+        assert!(get_faces(&response, false).is_empty());
+        assert!(!response[1].is_recognized("alice", &RecognitionCriteria::default()));
+    }
+
+    #[test]
+    fn test_url_encodes_each_segment() {
+        let client = DoubleTakeClient::new(DoubleTakeConfig {
+            doubletake_url: "http://localhost:3000/".to_string(),
+        });
+        let url = client.url(&["train", "add", "alice smith/2#b"]).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "http://localhost:3000/api/train/add/alice%20smith%2F2%23b"
+        );
+        let url = client.url(&["recognize", "upload"]).unwrap();
+        assert_eq!(url.as_str(), "http://localhost:3000/api/recognize/upload");
+    }
+}
@@ -0,0 +1,20 @@
+mod double_take_client;
+use anyhow::anyhow;
+pub use double_take_client::DoubleTakeClient;
+use shared_api::{Configuration, FaceBackend};
+use std::sync::Arc;
+
+/// create the Double-take backend from the configuration
+pub fn create_backend(config: &Configuration) -> anyhow::Result<Arc<dyn FaceBackend>> {
+    let double_take_config = config
+        .double_take
+        .clone()
+        .ok_or(anyhow!("missing Double-take configuration"))?;
+    Ok(Arc::new(
+        DoubleTakeClient::new(double_take_config)
+            .with_concurrency(config.concurrency)
+            .with_retry_policy(config.retry_policy.clone())
+            .with_recognition_criteria(config.recognition_criteria.clone())
+            .with_samples(config.keep_samples()),
+    ))
+}
@@ -0,0 +1,195 @@
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{Multipart, Path, State},
+    routing::post,
+    Json, Router,
+};
+use double_take_api::{create_backend, DoubleTakeClient};
+use double_take_contracts::DoubleTakeConfig;
+use serde_json::{json, Value};
+use shared_api::{run_train, Configuration, ProgressReporter, Recognizer, Trainer};
+use tempfile::{tempdir, TempDir};
+use tokio::{net::TcpListener, sync::mpsc::channel, task::JoinHandle};
+
+/// a single train request, with the file names of its parts
+#[derive(Clone, Debug, PartialEq)]
+struct TrainRequest {
+    name: String,
+    files: Vec<String>,
+}
+
+type Requests = Arc<Mutex<Vec<TrainRequest>>>;
+
+/// a Double-take stub that records the train requests and recognizes every image as alice
+struct DoubleTakeStub {
+    address: SocketAddr,
+    requests: Requests,
+    handle: JoinHandle<()>,
+}
+
+impl DoubleTakeStub {
+    async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests = Requests::default();
+        let app = Router::new()
+            .route("/api/train/add/{name}", post(train))
+            .route("/api/recognize/upload", post(recognize))
+            .with_state(requests.clone());
+        let handle = tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+        DoubleTakeStub {
+            address,
+            requests,
+            handle,
+        }
+    }
+
+    fn url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    fn requests(&self) -> Vec<TrainRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for DoubleTakeStub {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+async fn train(
+    State(requests): State<Requests>,
+    Path(name): Path<String>,
+    mut multipart: Multipart,
+) -> Json<Value> {
+    let mut files = Vec::new();
+    while let Some(field) = multipart.next_field().await.unwrap() {
+        assert_eq!(field.name(), Some("files[]"));
+        files.push(field.file_name().unwrap().to_string());
+        field.bytes().await.unwrap();
+    }
+    requests.lock().unwrap().push(TrainRequest { name, files });
+    Json(json!({ "message": "trained" }))
+}
+
+async fn recognize(mut multipart: Multipart) -> Json<Value> {
+    while let Some(field) = multipart.next_field().await.unwrap() {
+        field.bytes().await.unwrap();
+    }
+    Json(json!([{
+        "results": [{
+            "detector": "compreface",
+            "results": [{"name": "alice", "confidence": 99.0, "match": true}]
+        }]
+    }]))
+}
+
+/// write the images of a single person, each image is 10 bytes long
+fn person(dir: &TempDir, name: &str, count: usize) -> Vec<PathBuf> {
+    let person_dir = dir.path().join(name);
+    std::fs::create_dir_all(&person_dir).unwrap();
+    (1..=count)
+        .map(|index| {
+            let path = person_dir.join(format!("{}.jpg", index));
+            std::fs::write(&path, format!("{:<10}", index)).unwrap();
+            path
+        })
+        .collect()
+}
+
+fn client(stub: &DoubleTakeStub) -> DoubleTakeClient {
+    DoubleTakeClient::new(DoubleTakeConfig {
+        doubletake_url: stub.url(),
+    })
+    .with_samples(true)
+}
+
+#[tokio::test]
+async fn test_train_sends_the_batch_in_a_single_request() {
+    let stub = DoubleTakeStub::start().await;
+    let dir = tempdir().unwrap();
+    let files = person(&dir, "alice smith", 3);
+    let (tx, mut rx) = channel(10);
+
+    let trained = client(&stub)
+        .send_to_train("alice smith", files, tx)
+        .await
+        .unwrap();
+    assert_eq!(trained.success_count, 3);
+    assert_eq!(
+        stub.requests(),
+        vec![TrainRequest {
+            name: "alice smith".to_string(),
+            files: vec!["1.jpg".into(), "2.jpg".into(), "3.jpg".into()],
+        }]
+    );
+    // the progress of the whole batch, then the trained files with their result
+    assert!(matches!(
+        rx.recv().await,
+        Some(ProgressReporter::Increase(3))
+    ));
+    assert!(
+        matches!(rx.recv().await, Some(ProgressReporter::Trained(files, _)) if files.len() == 3)
+    );
+}
+
+#[tokio::test]
+async fn test_train_splits_the_folder_by_max_request_size() {
+    let stub = DoubleTakeStub::start().await;
+    let dir = tempdir().unwrap();
+    person(&dir, "alice", 5);
+    let config = Configuration::try_get_from([
+        "face-recognition-trainer",
+        "train",
+        "--client-type",
+        "double-take",
+        "--doubletake-url",
+        &stub.url(),
+        "--dataset-path",
+        dir.path().to_str().unwrap(),
+        "--max-request-size",
+        "25",
+    ])
+    .unwrap();
+    let (tx, mut rx) = channel(100);
+    let drain = tokio::spawn(async move { while rx.recv().await.is_some() {} });
+
+    let trained = run_train(&config, create_backend(&config).unwrap(), tx)
+        .await
+        .unwrap();
+    drain.await.unwrap();
+    assert_eq!(trained.success_count, 5);
+    // each request holds as many files as fit in 25 bytes
+    let mut batches: Vec<usize> = stub.requests().iter().map(|r| r.files.len()).collect();
+    batches.sort();
+    assert_eq!(batches, vec![1, 2, 2]);
+    assert!(stub.requests().iter().all(|r| r.name == "alice"));
+}
+
+#[tokio::test]
+async fn test_recognize_each_file() {
+    let stub = DoubleTakeStub::start().await;
+    let dir = tempdir().unwrap();
+    let alice = person(&dir, "alice", 2);
+    let bob = person(&dir, "bob", 1);
+    let client = client(&stub);
+
+    let (tx, _rx) = channel(10);
+    let recognized = client.recognize("alice", alice, tx).await.unwrap();
+    assert_eq!(recognized.success_count, 2);
+    assert_eq!(recognized.samples[0].subjects[0].similarity, 0.99);
+
+    let (tx, _rx) = channel(10);
+    let recognized = client.recognize("bob", bob, tx).await.unwrap();
+    assert_eq!(recognized.failure_count, 1);
+    assert!(stub.requests().is_empty());
+}
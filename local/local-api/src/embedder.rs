@@ -0,0 +1,144 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use image::{imageops::FilterType, DynamicImage};
+use shared_api::utils;
+use tract_onnx::prelude::*;
+
+/// Embedder trait
+/// Compute the embedding of a face image, similar faces have embeddings with a high cosine similarity
+pub trait Embedder: Send + Sync {
+    /// identify the model, embeddings of different models are not comparable
+    fn id(&self) -> &str;
+
+    /// the L2 normalized embedding of the image
+    fn embed(&self, image: &DynamicImage) -> anyhow::Result<Vec<f32>>;
+}
+
+/// The side of the grayscale thumbnail of the pixel embedding
+const PIXEL_EMBEDDING_SIZE: u32 = 32;
+
+/// Pixel embedder, the normalized grayscale thumbnail of the image
+/// It is not a face embedding: it does not need any model, but it only matches near duplicate images,
+/// so it is used by the tests rather than to recognize faces
+#[derive(Debug, Default)]
+pub struct PixelEmbedder;
+
+impl Embedder for PixelEmbedder {
+    fn id(&self) -> &str {
+        "pixels-32"
+    }
+
+    fn embed(&self, image: &DynamicImage) -> anyhow::Result<Vec<f32>> {
+        let thumbnail = image
+            .resize_exact(
+                PIXEL_EMBEDDING_SIZE,
+                PIXEL_EMBEDDING_SIZE,
+                FilterType::Triangle,
+            )
+            .to_luma32f();
+        let pixels = thumbnail.as_raw();
+        // remove the brightness, so the same image with other exposure is still similar
+        let mean = pixels.iter().sum::<f32>() / pixels.len() as f32;
+        Ok(normalize(pixels.iter().map(|p| p - mean).collect()))
+    }
+}
+
+/// ONNX embedder, runs a face embedding model such as ArcFace on the CPU
+/// The image is resized to the model input and its RGB values are scaled to -1..1
+/// The image must already be cropped and aligned to a single face, as the embedder does not detect faces
+pub struct OnnxEmbedder {
+    id: String,
+    input_size: u32,
+    model: TypedRunnableModel<TypedModel>,
+}
+
+impl OnnxEmbedder {
+    pub fn load(path: &Path, input_size: u32) -> anyhow::Result<Self> {
+        let size = input_size as usize;
+        let bytes = std::fs::read(path)
+            .map_err(|e| anyhow!("failed to read model: {}: {}", path.display(), e))?;
+        let model = tract_onnx::onnx()
+            .model_for_read(&mut bytes.as_slice())
+            .map_err(|e| anyhow!("failed to load model: {}: {}", path.display(), e))?
+            .with_input_fact(0, f32::fact([1, 3, size, size]).into())?
+            .into_optimized()?
+            .into_runnable()?;
+        let model_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        // the id follows the content of the model, so a replaced model file does not reuse the gallery
+        let model_hash = utils::content_hash(&bytes);
+        Ok(OnnxEmbedder {
+            id: format!("onnx:{}:{}:{}", model_name, input_size, &model_hash[..12]),
+            input_size,
+            model,
+        })
+    }
+}
+
+impl Embedder for OnnxEmbedder {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn embed(&self, image: &DynamicImage) -> anyhow::Result<Vec<f32>> {
+        let size = self.input_size as usize;
+        let rgb = image
+            .resize_exact(self.input_size, self.input_size, FilterType::Triangle)
+            .to_rgb8();
+        let input: Tensor =
+            tract_ndarray::Array4::from_shape_fn((1, 3, size, size), |(_, c, y, x)| {
+                (rgb.get_pixel(x as u32, y as u32)[c] as f32 - 127.5) / 127.5
+            })
+            .into();
+        let outputs = self.model.run(tvec!(input.into()))?;
+        let embedding = outputs
+            .first()
+            .ok_or(anyhow!("the model returned no output"))?
+            .to_array_view::<f32>()?
+            .iter()
+            .copied()
+            .collect();
+        Ok(normalize(embedding))
+    }
+}
+
+/// scale the vector to unit length, a zero vector is kept as is
+fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|v| *v /= norm);
+    }
+    vector
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn test_pixel_embedding_ignores_brightness() {
+        let image = RgbImage::from_fn(64, 64, |x, y| Rgb([(x * 3) as u8, (y * 2) as u8, 40]));
+        let brighter = RgbImage::from_fn(64, 64, |x, y| {
+            let pixel = image.get_pixel(x, y);
+            Rgb([pixel[0] + 20, pixel[1] + 20, pixel[2] + 20])
+        });
+        let embedding = PixelEmbedder
+            .embed(&DynamicImage::ImageRgb8(image))
+            .unwrap();
+        let brighter_embedding = PixelEmbedder
+            .embed(&DynamicImage::ImageRgb8(brighter))
+            .unwrap();
+        assert_eq!(embedding.len(), 1024);
+        let similarity: f32 = embedding
+            .iter()
+            .zip(&brighter_embedding)
+            .map(|(a, b)| a * b)
+            .sum();
+        assert!(similarity > 0.99, "{}", similarity);
+    }
+}
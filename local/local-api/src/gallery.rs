@@ -0,0 +1,164 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use shared_api::Subject;
+
+/// A trained face of the gallery, with its embedding
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GalleryFace {
+    pub image_id: String,
+    pub subject: String,
+    pub path: PathBuf,
+    pub embedding: Vec<f32>,
+}
+
+/// Gallery holds the embeddings of the trained faces, it is saved as a single json file
+/// The embeddings of a gallery are all computed by the same model
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Gallery {
+    pub model: String,
+    faces: Vec<GalleryFace>,
+    /// the position of each face by its image id, it is rebuilt when the gallery is loaded
+    #[serde(skip)]
+    index: HashMap<String, usize>,
+}
+
+impl Gallery {
+    /// load the gallery of the given model, an empty gallery when the file does not exist yet
+    pub fn load(path: &Path, model: &str) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Gallery {
+                model: model.to_string(),
+                ..Default::default()
+            });
+        }
+        let content = std::fs::read_to_string(path)?;
+        let mut gallery: Gallery = serde_json::from_str(&content)
+            .map_err(|e| anyhow!("invalid gallery file: {}: {}", path.display(), e))?;
+        if gallery.model != model {
+            return Err(anyhow!(
+                "the gallery: {} was trained with the model: {}, not: {}",
+                path.display(),
+                gallery.model,
+                model
+            ));
+        }
+        gallery.index = gallery
+            .faces
+            .iter()
+            .enumerate()
+            .map(|(position, face)| (face.image_id.clone(), position))
+            .collect();
+        Ok(gallery)
+    }
+
+    pub fn faces(&self) -> &[GalleryFace] {
+        &self.faces
+    }
+
+    /// save the gallery through a temporary file, so a crash never leaves a partial gallery
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let temp_path = path.with_extension("json.tmp");
+        std::fs::write(&temp_path, serde_json::to_string(self)?)?;
+        std::fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    /// add the face, replacing the face with the same image id
+    pub fn insert(&mut self, face: GalleryFace) {
+        match self.index.get(&face.image_id) {
+            Some(&position) => self.faces[position] = face,
+            None => {
+                self.index.insert(face.image_id.clone(), self.faces.len());
+                self.faces.push(face);
+            }
+        }
+    }
+
+    /// the subjects of the nearest faces, the similarity of a subject is its most similar face
+    /// ordered by similarity, up to limit subjects
+    pub fn nearest(&self, embedding: &[f32], limit: usize) -> Vec<Subject> {
+        let mut similarities: BTreeMap<&str, f64> = BTreeMap::new();
+        for face in self.faces.iter() {
+            let similarity = cosine_similarity(embedding, &face.embedding);
+            let best = similarities.entry(&face.subject).or_insert(similarity);
+            *best = best.max(similarity);
+        }
+        let mut subjects: Vec<Subject> = similarities
+            .into_iter()
+            .map(|(subject, similarity)| Subject {
+                subject: subject.to_string(),
+                similarity,
+            })
+            .collect();
+        subjects.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+        subjects.truncate(limit);
+        subjects
+    }
+}
+
+/// the cosine similarity of two embeddings, negative similarities are reported as 0
+/// so it can be compared with the similarity thresholds of the remote services
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f64 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+    let norm =
+        a.iter().map(|v| v * v).sum::<f32>().sqrt() * b.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm == 0.0 {
+        return 0.0;
+    }
+    (dot / norm).clamp(0.0, 1.0) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn face(image_id: &str, subject: &str, embedding: Vec<f32>) -> GalleryFace {
+        GalleryFace {
+            image_id: image_id.to_string(),
+            subject: subject.to_string(),
+            path: PathBuf::from(format!("{}/{}.jpg", subject, image_id)),
+            embedding,
+        }
+    }
+
+    #[test]
+    fn test_nearest_subjects_and_persistence() {
+        let mut gallery = Gallery::load(Path::new("/not/existing/gallery.json"), "m").unwrap();
+        gallery.insert(face("1", "alice", vec![1.0, 0.0]));
+        gallery.insert(face("2", "alice", vec![0.6, 0.8]));
+        gallery.insert(face("3", "bob", vec![0.0, 1.0]));
+        gallery.insert(face("4", "carol", vec![-1.0, 0.0]));
+        // the same image id replaces the face
+        gallery.insert(face("3", "bob", vec![0.8, 0.6]));
+        assert_eq!(gallery.faces().len(), 4);
+        assert_eq!(gallery.faces()[2].embedding, vec![0.8, 0.6]);
+
+        let subjects = gallery.nearest(&[1.0, 0.0], 2);
+        assert_eq!(subjects.len(), 2);
+        assert_eq!(subjects[0].subject, "alice");
+        assert!((subjects[0].similarity - 1.0).abs() < 1e-6);
+        assert_eq!(subjects[1].subject, "bob");
+        assert!((subjects[1].similarity - 0.8).abs() < 1e-6);
+        assert_eq!(gallery.nearest(&[1.0, 0.0], 5)[2].similarity, 0.0);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("gallery.json");
+        gallery.save(&path).unwrap();
+        let mut loaded = Gallery::load(&path, "m").unwrap();
+        assert_eq!(loaded, gallery);
+        // the loaded gallery still replaces the face of the same image id
+        loaded.insert(face("1", "alice", vec![0.0, 1.0]));
+        assert_eq!(loaded.faces().len(), 4);
+        assert_eq!(loaded.faces()[0].embedding, vec![0.0, 1.0]);
+        let error = Gallery::load(&path, "other").err().unwrap();
+        assert!(error.to_string().contains("was trained with the model: m"));
+    }
+}
@@ -0,0 +1,34 @@
+mod embedder;
+mod gallery;
+mod local_client;
+use anyhow::anyhow;
+pub use embedder::{Embedder, OnnxEmbedder, PixelEmbedder};
+pub use gallery::{cosine_similarity, Gallery, GalleryFace};
+pub use local_client::LocalClient;
+use shared_api::{Configuration, FaceBackend, Verifier};
+use std::sync::Arc;
+
+/// create the local client with the configured ONNX model
+fn create_client(config: &Configuration) -> anyhow::Result<LocalClient> {
+    let local_config = config.local.clone().unwrap_or_default();
+    let model = local_config
+        .local_model
+        .as_ref()
+        .ok_or(anyhow!("--local-model is required by the local client"))?;
+    let embedder: Arc<dyn Embedder> =
+        Arc::new(OnnxEmbedder::load(model, local_config.input_size())?);
+    Ok(LocalClient::new(embedder, local_config.gallery_path())?
+        .with_limit(local_config.limit())
+        .with_recognition_criteria(config.recognition_criteria.clone())
+        .with_samples(config.keep_samples()))
+}
+
+/// create the local backend from the configuration
+pub fn create_backend(config: &Configuration) -> anyhow::Result<Arc<dyn FaceBackend>> {
+    Ok(Arc::new(create_client(config)?))
+}
+
+/// create the local verifier from the configuration, it compares the embeddings of each pair
+pub fn create_verifier(config: &Configuration) -> anyhow::Result<Arc<dyn Verifier>> {
+    Ok(Arc::new(create_client(config)?))
+}
@@ -0,0 +1,378 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use shared_api::{
+    utils, AugmentedImage, FaceProcessingResult, FaceWithMetadata, FailureFace, MissedFace,
+    ProgressReporter, RecognitionCriteria, RecognitionSample, RecognizedFace, Recognizer,
+    TrainedFace, Trainer, Upload, Verifier,
+};
+use tokio::sync::{mpsc::Sender, Mutex, RwLock};
+use tracing::{debug, error};
+
+use crate::{
+    embedder::Embedder,
+    gallery::{cosine_similarity, Gallery, GalleryFace},
+};
+
+/// The minimum time between two saves of the gallery during a training run
+const GALLERY_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Local client computes the face embeddings on this machine and keeps them in an on-disk gallery,
+/// the recognition is the nearest neighbour of the gallery, without any external service
+pub struct LocalClient {
+    embedder: Arc<dyn Embedder>,
+    gallery: RwLock<Gallery>,
+    gallery_path: PathBuf,
+    /// true when the gallery has faces that were not saved yet
+    unsaved: AtomicBool,
+    /// the time of the last save, it also keeps two saves from running at the same time
+    last_saved: Mutex<Instant>,
+    /// the maximum number of subjects per recognized face
+    limit: usize,
+    /// decide which recognized subjects count as a success
+    recognition_criteria: RecognitionCriteria,
+    /// keep the raw subjects of each recognized file, only the evaluation reads them
+    keep_samples: bool,
+}
+
+impl LocalClient {
+    /// load the gallery of the embedder model, an empty gallery when the file does not exist yet
+    pub fn new(embedder: Arc<dyn Embedder>, gallery_path: PathBuf) -> anyhow::Result<Self> {
+        let gallery = Gallery::load(&gallery_path, embedder.id())?;
+        Ok(LocalClient {
+            embedder,
+            gallery: RwLock::new(gallery),
+            gallery_path,
+            unsaved: AtomicBool::new(false),
+            last_saved: Mutex::new(Instant::now()),
+            limit: 1,
+            recognition_criteria: RecognitionCriteria::default(),
+            keep_samples: false,
+        })
+    }
+
+    /// return up to limit subjects per recognized face
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// count a recognition as a success only when it matches the given criteria
+    pub fn with_recognition_criteria(mut self, recognition_criteria: RecognitionCriteria) -> Self {
+        self.recognition_criteria = recognition_criteria;
+        self
+    }
+
+    /// keep the raw subjects of each recognized file on the result, to evaluate them later
+    pub fn with_samples(mut self, keep_samples: bool) -> Self {
+        self.keep_samples = keep_samples;
+        self
+    }
+
+    /// save the gallery when the save interval has passed since the last save, or always when forced
+    /// The gallery is copied under the read lock, and written on the blocking thread pool,
+    /// so the recognitions and the trainings are not stalled by the file
+    async fn save_gallery(&self, force: bool) -> anyhow::Result<()> {
+        let mut last_saved = self.last_saved.lock().await;
+        if !force && last_saved.elapsed() < GALLERY_SAVE_INTERVAL {
+            return Ok(());
+        }
+        if !self.unsaved.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let gallery = self.gallery.read().await.clone();
+        let path = self.gallery_path.clone();
+        let saved = tokio::task::spawn_blocking(move || {
+            gallery.save(&path)?;
+            Ok::<_, anyhow::Error>(gallery.faces().len())
+        })
+        .await?;
+        match saved {
+            Ok(faces) => {
+                debug!(
+                    "gallery: {} has {} faces",
+                    self.gallery_path.display(),
+                    faces
+                );
+                *last_saved = Instant::now();
+                Ok(())
+            }
+            Err(e) => {
+                self.unsaved.store(true, Ordering::SeqCst);
+                Err(e)
+            }
+        }
+    }
+
+    async fn embed(&self, file_path: &Path) -> anyhow::Result<Vec<f32>> {
+        self.embed_content(tokio::fs::read(file_path).await?).await
+    }
+
+    /// decode the image and compute its embedding, on the blocking thread pool as it is cpu bound
+    async fn embed_content(&self, content: Vec<u8>) -> anyhow::Result<Vec<f32>> {
+        let embedder = self.embedder.clone();
+        tokio::task::spawn_blocking(move || {
+            let image = image::load_from_memory(&content)?;
+            embedder.embed(&image)
+        })
+        .await?
+    }
+}
+
+fn context(files: &[PathBuf]) -> String {
+    files
+        .first()
+        .and_then(|path| path.parent())
+        .map(|parent| parent.display().to_string())
+        .unwrap_or_default()
+}
+
+#[async_trait]
+impl Trainer for LocalClient {
+    async fn send_to_train(
+        &self,
+        name: &str,
+        files: Vec<PathBuf>,
+        progress_reporter_tx: Sender<ProgressReporter>,
+    ) -> anyhow::Result<FaceProcessingResult> {
+        let uploads = files.iter().map(|path| Upload::File(path)).collect();
+        self.train_uploads(name, uploads, progress_reporter_tx)
+            .await
+    }
+
+    async fn send_augmented_to_train(
+        &self,
+        name: &str,
+        images: Vec<AugmentedImage>,
+        progress_reporter_tx: Sender<ProgressReporter>,
+    ) -> anyhow::Result<FaceProcessingResult> {
+        let uploads = images.iter().map(Upload::Augmented).collect();
+        self.train_uploads(name, uploads, progress_reporter_tx)
+            .await
+    }
+
+    async fn flush(&self) -> anyhow::Result<()> {
+        self.save_gallery(true).await
+    }
+}
+
+impl LocalClient {
+    /// embed each image and add it to the gallery as a face of the given subject
+    async fn train_uploads(
+        &self,
+        name: &str,
+        uploads: Vec<Upload<'_>>,
+        progress_reporter_tx: Sender<ProgressReporter>,
+    ) -> anyhow::Result<FaceProcessingResult> {
+        let files: Vec<PathBuf> = uploads
+            .iter()
+            .map(|upload| upload.path().to_path_buf())
+            .collect();
+        let mut result = FaceProcessingResult::with_context(context(&files));
+        let mut faces = Vec::new();
+        for (upload, file_path) in uploads.into_iter().zip(files.clone()) {
+            result.total_count += 1;
+            let (embedding, hash) = match self.embed_upload(upload).await {
+                Ok(embedded) => embedded,
+                Err(e) => {
+                    error!("Failed to embed file: {}: {}", file_path.display(), e);
+                    result.missed_count += 1;
+                    result.missed_faces.push(MissedFace::new(file_path, e));
+                    progress_reporter_tx
+                        .send(ProgressReporter::Increase(1))
+                        .await?;
+                    continue;
+                }
+            };
+            // the same image trained again replaces its face
+            let image_id = format!("local-{}", &hash[..12]);
+            result.success_count += 1;
+            result.trained_faces.push(TrainedFace {
+                path: file_path.clone(),
+                image_id: image_id.clone(),
+                subject: name.to_string(),
+            });
+            faces.push(GalleryFace {
+                image_id,
+                subject: name.to_string(),
+                path: file_path,
+                embedding,
+            });
+            progress_reporter_tx
+                .send(ProgressReporter::Increase(1))
+                .await?;
+        }
+
+        {
+            let mut gallery = self.gallery.write().await;
+            for face in faces {
+                gallery.insert(face);
+            }
+        }
+        self.unsaved.store(true, Ordering::SeqCst);
+        // the whole gallery is rewritten on each save, so it is saved on an interval and at the end of the run
+        self.save_gallery(false).await?;
+        // the files are reported only once the gallery is saved with them, the files of an unsaved batch
+        // are trained again on resume, which replaces their faces
+        if !self.unsaved.load(Ordering::SeqCst) {
+            progress_reporter_tx
+                .send(ProgressReporter::Trained(files, result.clone()))
+                .await?;
+        }
+        Ok(result)
+    }
+
+    /// the embedding of the image and the hash of its content
+    async fn embed_upload(&self, upload: Upload<'_>) -> anyhow::Result<(Vec<f32>, String)> {
+        let content = upload.content().await?;
+        let hash = utils::content_hash(&content);
+        Ok((self.embed_content(content).await?, hash))
+    }
+}
+
+#[async_trait]
+impl Recognizer for LocalClient {
+    async fn recognize(
+        &self,
+        name: &str,
+        files: Vec<PathBuf>,
+        progress_reporter_tx: Sender<ProgressReporter>,
+    ) -> anyhow::Result<FaceProcessingResult> {
+        let mut result = FaceProcessingResult::with_context(context(&files));
+        for file_path in files {
+            result.total_count += 1;
+            match self.embed(&file_path).await {
+                Ok(embedding) => {
+                    let subjects = self.gallery.read().await.nearest(&embedding, self.limit);
+                    let faces = vec![RecognizedFace {
+                        r#box: None,
+                        subjects: subjects.clone(),
+                    }];
+                    result.confusion_matrix.record(
+                        name,
+                        self.recognition_criteria
+                            .prediction(&subjects)
+                            .map(|s| s.subject.as_str()),
+                    );
+                    if self.keep_samples {
+                        result.samples.push(RecognitionSample {
+                            path: file_path.clone(),
+                            expected: name.to_string(),
+                            subjects: subjects.clone(),
+                        });
+                    }
+                    if self.recognition_criteria.is_recognized(name, &faces) {
+                        result.success_count += 1;
+                    } else {
+                        result.failure_count += 1;
+                        result
+                            .failure_faces
+                            .push(FailureFace::Recognize(FaceWithMetadata {
+                                path: file_path,
+                                subjects,
+                                faces,
+                            }));
+                    }
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to recognize file: {} for name: {}: {}",
+                        file_path.display(),
+                        name,
+                        e
+                    );
+                    result.missed_count += 1;
+                    result.missed_faces.push(MissedFace::new(file_path, e));
+                }
+            }
+            progress_reporter_tx
+                .send(ProgressReporter::Increase(1))
+                .await?;
+        }
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl Verifier for LocalClient {
+    async fn verify(&self, source: &Path, target: &Path) -> anyhow::Result<Option<f64>> {
+        let source = self.embed(source).await?;
+        let target = self.embed(target).await?;
+        Ok(Some(cosine_similarity(&source, &target)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+    use tempfile::{tempdir, TempDir};
+    use tokio::sync::mpsc::channel;
+
+    use super::*;
+    use crate::embedder::PixelEmbedder;
+
+    /// write a gradient image, the pattern decides which images are similar
+    fn write_image(dir: &TempDir, name: &str, pattern: fn(u32, u32) -> u8) -> PathBuf {
+        let path = dir.path().join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        RgbImage::from_fn(48, 48, |x, y| {
+            let value = pattern(x, y);
+            Rgb([value, value, value])
+        })
+        .save(&path)
+        .unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_train_then_recognize_nearest_subject() {
+        let dir = tempdir().unwrap();
+        let alice = write_image(&dir, "alice/1.png", |x, _| (x * 5) as u8);
+        let bob = write_image(&dir, "bob/1.png", |_, y| (y * 5) as u8);
+        let broken = dir.path().join("bob/2.png");
+        std::fs::write(&broken, "not an image").unwrap();
+        let gallery_path = dir.path().join("gallery.json");
+        let (tx, _rx) = channel(100);
+
+        let client = LocalClient::new(Arc::new(PixelEmbedder), gallery_path.clone()).unwrap();
+        let trained = client
+            .send_to_train("alice", vec![alice.clone()], tx.clone())
+            .await
+            .unwrap();
+        assert_eq!(trained.success_count, 1);
+        assert!(trained.trained_faces[0].image_id.starts_with("local-"));
+        let trained = client
+            .send_to_train("bob", vec![bob.clone(), broken.clone()], tx.clone())
+            .await
+            .unwrap();
+        assert_eq!(trained.success_count, 1);
+        assert_eq!(trained.missed_count, 1);
+        // the gallery is saved at the end of the run, not on every batch
+        assert!(!gallery_path.exists());
+        client.flush().await.unwrap();
+        assert!(gallery_path.exists());
+
+        // a new client reads the saved gallery
+        let client = LocalClient::new(Arc::new(PixelEmbedder), gallery_path)
+            .unwrap()
+            .with_samples(true);
+        let recognized = client
+            .recognize("alice", vec![alice.clone(), bob.clone()], tx)
+            .await
+            .unwrap();
+        assert_eq!(recognized.success_count, 1);
+        assert_eq!(recognized.failure_count, 1);
+        assert_eq!(recognized.samples[0].subjects[0].subject, "alice");
+        assert!(recognized.samples[0].subjects[0].similarity > 0.99);
+        assert_eq!(recognized.samples[1].subjects[0].subject, "bob");
+
+        assert!(client.verify(&alice, &alice).await.unwrap().unwrap() > 0.99);
+    }
+}
@@ -0,0 +1,52 @@
+use std::path::PathBuf;
+
+/// The gallery file of the local backend, when it was not set
+pub const GALLERY_FILE_NAME: &str = "face-trainer-gallery.json";
+
+#[derive(Debug, clap::Parser, Clone, Default)]
+pub struct LocalConfig {
+    /// The ONNX face embedding model, e.g. ArcFace or MobileFaceNet, it runs on the CPU
+    /// The model gets a single 1x3xSIZExSIZE RGB image and returns the embedding of the face
+    /// Required when the client type is local, the images must be cropped to the face, as there is no detection step
+    #[clap(
+        long,
+        env = "LOCAL_MODEL",
+        required_if_eq("client_type", "local"),
+        help = "Local backend ONNX embedding model"
+    )]
+    pub local_model: Option<PathBuf>,
+
+    /// The width and height of the model input, the images are resized to it
+    /// The default value is 112
+    #[clap(long, env = "LOCAL_INPUT_SIZE")]
+    pub local_input_size: Option<u32>,
+
+    /// The on-disk gallery of the trained embeddings
+    /// The default value is face-trainer-gallery.json
+    #[clap(long, env = "LOCAL_GALLERY", help = "Local backend gallery file")]
+    pub local_gallery: Option<PathBuf>,
+
+    /// The maximum number of subjects to return per recognized face, ordered by similarity
+    /// The default value is 1, the single most similar subject like CompreFace
+    #[clap(long, env = "LOCAL_LIMIT")]
+    pub local_limit: Option<usize>,
+}
+
+impl LocalConfig {
+    /// the model input size, 112 when it was not set
+    pub fn input_size(&self) -> u32 {
+        self.local_input_size.unwrap_or(112)
+    }
+
+    /// the gallery file path, face-trainer-gallery.json when it was not set
+    pub fn gallery_path(&self) -> PathBuf {
+        self.local_gallery
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(GALLERY_FILE_NAME))
+    }
+
+    /// the maximum number of subjects per face, 1 when it was not set
+    pub fn limit(&self) -> usize {
+        self.local_limit.unwrap_or(1)
+    }
+}
@@ -0,0 +1,20 @@
+mod mock_client;
+use anyhow::anyhow;
+pub use mock_client::{Fixture, FixtureEntry, MockClient, Outcome};
+use shared_api::{Configuration, FaceBackend};
+use std::sync::Arc;
+
+/// create the mock backend from the configuration, the fixture file is read once
+pub fn create_backend(config: &Configuration) -> anyhow::Result<Arc<dyn FaceBackend>> {
+    let mock_fixture = config
+        .mock
+        .as_ref()
+        .and_then(|m| m.mock_fixture.as_ref())
+        .ok_or(anyhow!("missing mock fixture"))?;
+    let fixture = Fixture::load(mock_fixture)?;
+    Ok(Arc::new(
+        MockClient::new(fixture)
+            .with_recognition_criteria(config.recognition_criteria.clone())
+            .with_samples(config.keep_samples()),
+    ))
+}
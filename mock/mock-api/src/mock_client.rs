@@ -0,0 +1,344 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use shared_api::{
+    utils, ApiError, AugmentedImage, FaceProcessingResult, FaceWithMetadata, FailureFace,
+    MissedFace, ProgressReporter, RecognitionCriteria, RecognitionSample, RecognizedFace,
+    Recognizer, Subject, TrainedFace, Trainer,
+};
+use tokio::sync::mpsc::Sender;
+use tracing::debug;
+
+/// The scripted outcome of a single file
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "outcome", rename_all = "kebab-case")]
+pub enum Outcome {
+    /// trained, or recognized as the given subjects, by default the expected subject with similarity 1
+    /// The recognition counts as a success only when the subjects pass the recognition criteria
+    Success {
+        #[serde(default)]
+        subjects: Option<Vec<Subject>>,
+    },
+    /// not trained, or recognized as the given subjects, none by default
+    Failure {
+        #[serde(default)]
+        subjects: Vec<Subject>,
+    },
+    /// the request failed, with the status of the service or as a connection error without status
+    Error {
+        #[serde(default)]
+        status: Option<u16>,
+        message: String,
+    },
+}
+
+/// The outcome of a file, and the latency of its request
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct FixtureEntry {
+    #[serde(flatten)]
+    pub outcome: Outcome,
+    #[serde(default)]
+    pub latency_ms: u64,
+    /// the detected faces of the recognition, with their boxes and subjects,
+    /// by default a single face with the subjects of the outcome
+    #[serde(default)]
+    pub faces: Option<Vec<RecognizedFace>>,
+}
+
+impl Default for FixtureEntry {
+    fn default() -> Self {
+        FixtureEntry {
+            outcome: Outcome::Success { subjects: None },
+            latency_ms: 0,
+            faces: None,
+        }
+    }
+}
+
+/// Fixture holds the scripted outcome of the files, by the end of their path, e.g. alice/1.jpg
+/// Files without an entry get the default outcome, a success
+#[derive(Deserialize, Debug, Default)]
+pub struct Fixture {
+    #[serde(default)]
+    pub default: FixtureEntry,
+    #[serde(default)]
+    pub files: BTreeMap<String, FixtureEntry>,
+}
+
+impl Fixture {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// the entry of the longest path suffix that matches the file
+    pub fn entry(&self, file_path: &Path) -> &FixtureEntry {
+        self.files
+            .iter()
+            .filter(|(suffix, _)| file_path.ends_with(suffix))
+            .max_by_key(|(suffix, _)| suffix.len())
+            .map(|(_, entry)| entry)
+            .unwrap_or(&self.default)
+    }
+}
+
+/// Mock client returns the deterministic outcomes of the fixture, without any service
+pub struct MockClient {
+    fixture: Fixture,
+    /// decide which recognized subjects count as a success
+    recognition_criteria: RecognitionCriteria,
+    /// keep the raw subjects of each recognized file, only the evaluation reads them
+    keep_samples: bool,
+}
+
+impl MockClient {
+    pub fn new(fixture: Fixture) -> Self {
+        MockClient {
+            fixture,
+            recognition_criteria: RecognitionCriteria::default(),
+            keep_samples: false,
+        }
+    }
+
+    /// count a recognition as a success only when it matches the given criteria
+    pub fn with_recognition_criteria(mut self, recognition_criteria: RecognitionCriteria) -> Self {
+        self.recognition_criteria = recognition_criteria;
+        self
+    }
+
+    /// keep the raw subjects of each recognized file on the result, to evaluate them later
+    pub fn with_samples(mut self, keep_samples: bool) -> Self {
+        self.keep_samples = keep_samples;
+        self
+    }
+
+    /// wait for the latency of the file, and return its entry
+    async fn entry(&self, file_path: &Path) -> &FixtureEntry {
+        let entry = self.fixture.entry(file_path);
+        if entry.latency_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(entry.latency_ms)).await;
+        }
+        debug!("mock outcome of file: {}: {:?}", file_path.display(), entry);
+        entry
+    }
+}
+
+fn api_error(status: Option<u16>, message: &str) -> ApiError {
+    match status {
+        Some(status) => ApiError::Status {
+            status,
+            message: message.to_string(),
+        },
+        None => ApiError::Connect(message.to_string()),
+    }
+}
+
+fn context(files: &[PathBuf]) -> String {
+    files
+        .first()
+        .and_then(|path| path.parent())
+        .map(|parent| parent.display().to_string())
+        .unwrap_or_default()
+}
+
+#[async_trait]
+impl Trainer for MockClient {
+    async fn send_to_train(
+        &self,
+        name: &str,
+        files: Vec<PathBuf>,
+        progress_reporter_tx: Sender<ProgressReporter>,
+    ) -> anyhow::Result<FaceProcessingResult> {
+        let mut result = FaceProcessingResult::with_context(context(&files));
+        for file_path in files {
+            let mut file_result = FaceProcessingResult::with_context(result.context.clone());
+            file_result.total_count += 1;
+            match &self.entry(&file_path).await.outcome {
+                Outcome::Success { .. } => {
+                    file_result.success_count += 1;
+                    file_result.trained_faces.push(TrainedFace {
+                        image_id: format!(
+                            "mock-{}",
+                            &utils::text_hash(&file_path.to_string_lossy())[..12]
+                        ),
+                        subject: name.to_string(),
+                        path: file_path.clone(),
+                    });
+                }
+                Outcome::Failure { .. } => {
+                    file_result.failure_count += 1;
+                    file_result
+                        .failure_faces
+                        .push(FailureFace::Train(file_path.clone()));
+                }
+                Outcome::Error { status, message } => {
+                    file_result.missed_count += 1;
+                    file_result.missed_faces.push(MissedFace::new(
+                        file_path.clone(),
+                        api_error(*status, message),
+                    ));
+                }
+            }
+            progress_reporter_tx
+                .send(ProgressReporter::Increase(1))
+                .await?;
+            progress_reporter_tx
+                .send(ProgressReporter::Trained(
+                    vec![file_path],
+                    file_result.clone(),
+                ))
+                .await?;
+            result.add(file_result);
+        }
+        Ok(result)
+    }
+
+    /// the outcome of a variant is scripted by its virtual path, e.g. alice/1_aug_2.jpg
+    async fn send_augmented_to_train(
+        &self,
+        name: &str,
+        images: Vec<AugmentedImage>,
+        progress_reporter_tx: Sender<ProgressReporter>,
+    ) -> anyhow::Result<FaceProcessingResult> {
+        let files = images.into_iter().map(|image| image.path).collect();
+        self.send_to_train(name, files, progress_reporter_tx).await
+    }
+}
+
+#[async_trait]
+impl Recognizer for MockClient {
+    async fn recognize(
+        &self,
+        name: &str,
+        files: Vec<PathBuf>,
+        progress_reporter_tx: Sender<ProgressReporter>,
+    ) -> anyhow::Result<FaceProcessingResult> {
+        let mut result = FaceProcessingResult::with_context(context(&files));
+        for file_path in files {
+            result.total_count += 1;
+            let entry = self.entry(&file_path).await;
+            let subjects = match &entry.outcome {
+                Outcome::Success { subjects } => subjects.clone().unwrap_or_else(|| {
+                    vec![Subject {
+                        subject: name.to_string(),
+                        similarity: 1.0,
+                    }]
+                }),
+                Outcome::Failure { subjects } => subjects.clone(),
+                Outcome::Error { status, message } => {
+                    result.missed_count += 1;
+                    result
+                        .missed_faces
+                        .push(MissedFace::new(file_path, api_error(*status, message)));
+                    progress_reporter_tx
+                        .send(ProgressReporter::Increase(1))
+                        .await?;
+                    continue;
+                }
+            };
+
+            // the scripted subjects are judged by the criteria, the same way as the answer of a service
+            let faces = entry.faces.clone().unwrap_or_else(|| {
+                vec![RecognizedFace {
+                    r#box: None,
+                    subjects,
+                }]
+            });
+            let labeled_subjects = self.recognition_criteria.labeled_subjects(&faces);
+            result.confusion_matrix.record(
+                name,
+                self.recognition_criteria
+                    .prediction(&labeled_subjects)
+                    .map(|s| s.subject.as_str()),
+            );
+            if self.keep_samples {
+                result.samples.push(RecognitionSample {
+                    path: file_path.clone(),
+                    expected: name.to_string(),
+                    subjects: labeled_subjects,
+                });
+            }
+            if self.recognition_criteria.is_recognized(name, &faces) {
+                result.success_count += 1;
+            } else {
+                result.failure_count += 1;
+                result
+                    .failure_faces
+                    .push(FailureFace::Recognize(FaceWithMetadata {
+                        path: file_path,
+                        subjects: faces.iter().flat_map(|f| f.subjects.clone()).collect(),
+                        faces,
+                    }));
+            }
+            progress_reporter_tx
+                .send(ProgressReporter::Increase(1))
+                .await?;
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixture_entry_by_longest_suffix() {
+        let fixture: Fixture = serde_json::from_str(
+            r#"{
+                "default": {"outcome": "failure", "subjects": []},
+                "files": {
+                    "1.jpg": {"outcome": "success", "latency_ms": 5},
+                    "bob/1.jpg": {"outcome": "error", "status": 503, "message": "unavailable"}
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            fixture.entry(Path::new("/dataset/alice/1.jpg")),
+            &FixtureEntry {
+                outcome: Outcome::Success { subjects: None },
+                latency_ms: 5,
+                faces: None,
+            }
+        );
+        assert_eq!(
+            fixture.entry(Path::new("/dataset/bob/1.jpg")).outcome,
+            Outcome::Error {
+                status: Some(503),
+                message: "unavailable".to_string(),
+            }
+        );
+        assert_eq!(
+            fixture.entry(Path::new("/dataset/bob/2.jpg")).outcome,
+            Outcome::Failure {
+                subjects: Vec::new()
+            }
+        );
+    }
+    #[tokio::test]
+    async fn test_samples_are_kept_only_when_asked() {
+        let files = vec![PathBuf::from("/dataset/alice/1.jpg")];
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+
+        let recognized = MockClient::new(Fixture::default())
+            .recognize("alice", files.clone(), tx.clone())
+            .await
+            .unwrap();
+        assert_eq!(recognized.success_count, 1);
+        assert!(recognized.samples.is_empty());
+
+        let evaluated = MockClient::new(Fixture::default())
+            .with_samples(true)
+            .recognize("alice", files, tx)
+            .await
+            .unwrap();
+        assert_eq!(evaluated.samples.len(), 1);
+        assert_eq!(evaluated.samples[0].subjects[0].subject, "alice");
+    }
+}
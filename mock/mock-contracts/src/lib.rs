@@ -0,0 +1,14 @@
+use std::path::PathBuf;
+
+#[derive(Debug, clap::Parser, Clone)]
+pub struct MockConfig {
+    /// The fixture file of the mock backend, with the scripted outcome of each file
+    /// Required when the client type is mock
+    #[clap(
+        long,
+        env = "MOCK_FIXTURE",
+        required_if_eq("client_type", "mock"),
+        help = "Mock backend fixture file"
+    )]
+    pub mock_fixture: Option<PathBuf>,
+}
@@ -0,0 +1,301 @@
+use std::path::PathBuf;
+
+use clap::{builder::RangedU64ValueParser, Subcommand};
+use compreface_contracts::CompreFaceConfig;
+use double_take_contracts::DoubleTakeConfig;
+use local_contracts::LocalConfig;
+use mock_contracts::MockConfig;
+
+use crate::{
+    AugmentOptions, AugmentationSpec, ClientType, ConfigCommand, ErrorConfiguration,
+    EvaluationOptions, ExperimentOptions, FacesCommand, PreflightAction, RecognitionCriteria,
+    ReportFormat, RetryPolicy, SubjectsCommand, VerificationOptions, CONFIG_FILE_NAME,
+    TRAINING_MANIFEST_FILE_NAME,
+};
+
+/// The maximum size of a request, when --max-request-size is not set
+pub const DEFAULT_MAX_REQUEST_SIZE: u64 = 10485760;
+
+/// The number of requests in flight, when --concurrency is not set
+pub const DEFAULT_CONCURRENCY: usize = 1;
+
+/// The number of augmented variants of each image, when --augment-variants is not set
+pub const DEFAULT_AUGMENT_VARIANTS: usize = 3;
+
+/// The command line of the trainer, each command with its own arguments
+#[derive(Debug, clap::Parser, Clone)]
+#[clap(name = "face-recognition-trainer")]
+pub struct Arguments {
+    /// The configuration file with the options of every run and the named profiles
+    /// The default file is ignored when it does not exist
+    #[clap(long, env = "FACE_TRAINER_CONFIG", default_value = CONFIG_FILE_NAME, global = true)]
+    pub config: PathBuf,
+
+    /// The profile of the configuration file, its options override the top level options of the file
+    /// The command line and the environment variables override both
+    #[clap(long, env = "FACE_TRAINER_PROFILE", global = true)]
+    pub profile: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+/// The commands of the trainer
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Train the faces of the dataset, the folder of each image is its subject
+    Train(TrainArgs),
+    /// Recognize the faces of the dataset, a success is a face recognized as the subject of its folder
+    Recognize(RecognizeArgs),
+    /// Recognize the dataset once and sweep the similarity thresholds over the results
+    Evaluate(EvaluateArgs),
+    /// Make the remote collection mirror the dataset, uploading new images and deleting removed ones
+    Sync(SyncArgs),
+    /// Compare pairs of images of the dataset 1:1, without the trained subjects
+    Verify(VerifyArgs),
+    /// Manage the subjects
+    Subjects {
+        #[clap(flatten)]
+        backend: BackendArgs,
+
+        #[command(subcommand)]
+        action: SubjectsCommand,
+    },
+    /// Manage the stored example faces
+    Faces {
+        #[clap(flatten)]
+        backend: BackendArgs,
+
+        #[command(subcommand)]
+        action: FacesCommand,
+    },
+    /// Delete every face that was added by a training run, according to the training manifest
+    Rollback {
+        #[clap(flatten)]
+        backend: BackendArgs,
+
+        /// The run id that was printed at the end of the training
+        #[clap(long)]
+        run: String,
+
+        /// The training manifest of the run
+        #[clap(long, env = "TRAINING_MANIFEST", default_value = TRAINING_MANIFEST_FILE_NAME)]
+        training_manifest: PathBuf,
+    },
+    /// Write augmented variants of every dataset image, mirroring the person folders under the output directory
+    Augment {
+        #[clap(flatten)]
+        dataset: DatasetArgs,
+
+        #[clap(flatten)]
+        options: AugmentOptions,
+    },
+    /// Train and test the variants of an experiment file in sequence, and compare their results side by side
+    Experiment(ExperimentOptions),
+    /// Inspect the configuration of the command line, the environment variables and the configuration file
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+}
+
+/// The face service of the command, only the options of the selected client type are used
+#[derive(Debug, Clone, clap::Args)]
+pub struct BackendArgs {
+    /// The client type to use, compreface, double-take, mock or local
+    #[clap(long, value_enum)]
+    pub client_type: ClientType,
+
+    #[clap(flatten, next_help_heading = "CompreFace")]
+    pub compreface: CompreFaceConfig,
+
+    #[clap(flatten, next_help_heading = "Double-take")]
+    pub double_take: DoubleTakeConfig,
+
+    #[clap(flatten, next_help_heading = "Mock")]
+    pub mock: MockConfig,
+
+    #[clap(flatten, next_help_heading = "Local")]
+    pub local: LocalConfig,
+
+    /// retry options of the transient api errors
+    #[clap(flatten, next_help_heading = "Retry")]
+    pub retry_policy: RetryPolicy,
+}
+
+/// The dataset of the command and how it is sent to the service
+#[derive(Debug, Clone, clap::Args)]
+pub struct DatasetArgs {
+    /// The data set root folder with the face images
+    #[clap(long, env = "DATASET_PATH")]
+    pub dataset_path: String,
+
+    /// The maximum size of the request to send to the service
+    /// The service will be called when the total size of the files content reaches this size
+    /// The default value is 10MB
+    #[clap(long, env = "MAX_REQUEST_SIZE", default_value_t = DEFAULT_MAX_REQUEST_SIZE)]
+    pub max_request_size: u64,
+
+    /// The maximum number of requests to keep in flight at the same time, across files and folders
+    /// The default value is 1, which send the requests one by one
+    #[clap(long, env = "CONCURRENCY", default_value_t = DEFAULT_CONCURRENCY, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub concurrency: usize,
+
+    /// Optional name for all scanned faces
+    /// When set, it will ignore the folder name per image and use this name for all faces
+    #[clap(long, env = "OVERRIDE_TRAINED_NAME")]
+    pub override_trained_name: Option<String>,
+}
+
+/// The outputs of a dataset run
+#[derive(Debug, Clone, clap::Args)]
+pub struct OutputArgs {
+    /// Optional path to save a machine readable report of the run
+    /// The report contains the totals, the counters per subject, every failure with its candidate subjects
+    /// and every missed file with its error reason
+    #[clap(long, env = "REPORT")]
+    pub report: Option<PathBuf>,
+
+    /// The format of the report: json, csv or jsonl
+    /// The default value is json
+    #[clap(long, env = "REPORT_FORMAT", default_value = "json")]
+    pub report_format: ReportFormat,
+
+    /// error configuration options
+    #[clap(flatten)]
+    pub error_configuration: ErrorConfiguration,
+}
+
+/// train command arguments
+#[derive(Debug, Clone, clap::Args)]
+pub struct TrainArgs {
+    #[clap(flatten)]
+    pub backend: BackendArgs,
+
+    #[clap(flatten)]
+    pub dataset: DatasetArgs,
+
+    #[clap(flatten)]
+    pub output: OutputArgs,
+
+    /// What to do with the images that the pre-flight detection rejected: skip or quarantine
+    /// Quarantine moves them under the output directory, it requires --output-dir
+    /// The default value is skip
+    #[clap(
+        long,
+        env = "PREFLIGHT_ACTION",
+        default_value = "skip",
+        requires_if("quarantine", "output_dir")
+    )]
+    pub preflight_action: PreflightAction,
+
+    /// The id of this run, it is recorded with every trained face in the training manifest
+    /// so the faces of the run can be deleted later with the rollback command
+    /// The default value is a random uuid
+    #[clap(long, env = "RUN_ID")]
+    pub run_id: Option<String>,
+
+    /// The training manifest, it maps the run id, local path, content hash and subject
+    /// of every trained face to the image id returned by the service
+    #[clap(long, env = "TRAINING_MANIFEST", default_value = TRAINING_MANIFEST_FILE_NAME)]
+    pub training_manifest: PathBuf,
+
+    /// Resume a previous run, skipping the files that were already trained successfully
+    /// by the same backend, according to the journal under the output directory
+    #[clap(long, env = "RESUME", requires = "output_dir")]
+    pub resume: bool,
+
+    /// Train each image together with augmented variants of it, created in memory
+    /// The transforms of each variant, like the augment command --transforms, e.g. flip,rotate=15
+    /// The variants are tagged with their transforms in the journal and in the report
+    #[clap(long, env = "AUGMENT_ON_TRAIN")]
+    pub augment_on_train: Option<AugmentationSpec>,
+
+    /// The number of augmented variants of each image, with --augment-on-train
+    #[clap(long, env = "AUGMENT_VARIANTS", default_value_t = DEFAULT_AUGMENT_VARIANTS, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub augment_variants: usize,
+
+    /// The seed of the augmented variants, with --augment-on-train
+    /// The same seed creates the same variants as the augment command
+    #[clap(long, env = "AUGMENT_SEED", default_value = "0")]
+    pub augment_seed: u64,
+}
+
+/// recognize command arguments
+#[derive(Debug, Clone, clap::Args)]
+pub struct RecognizeArgs {
+    #[clap(flatten)]
+    pub backend: BackendArgs,
+
+    #[clap(flatten)]
+    pub dataset: DatasetArgs,
+
+    #[clap(flatten)]
+    pub output: OutputArgs,
+
+    /// Resume a previous run, skipping the files that were already recognized successfully
+    /// by the same backend, according to the journal under the output directory
+    #[clap(long, env = "RESUME", requires = "output_dir")]
+    pub resume: bool,
+
+    /// the criteria of a successful recognition
+    #[clap(flatten)]
+    pub recognition_criteria: RecognitionCriteria,
+}
+
+/// evaluate command arguments
+#[derive(Debug, Clone, clap::Args)]
+pub struct EvaluateArgs {
+    #[clap(flatten)]
+    pub backend: BackendArgs,
+
+    #[clap(flatten)]
+    pub dataset: DatasetArgs,
+
+    #[clap(flatten)]
+    pub output: OutputArgs,
+
+    /// the criteria of a successful recognition
+    #[clap(flatten)]
+    pub recognition_criteria: RecognitionCriteria,
+
+    /// evaluation options
+    #[clap(flatten)]
+    pub evaluation: EvaluationOptions,
+}
+
+/// sync command arguments
+#[derive(Debug, Clone, clap::Args)]
+pub struct SyncArgs {
+    #[clap(flatten)]
+    pub backend: BackendArgs,
+
+    #[clap(flatten)]
+    pub dataset: DatasetArgs,
+
+    #[clap(flatten)]
+    pub output: OutputArgs,
+
+    /// Only print the plan, without changing the remote collection
+    #[clap(long, env = "DRY_RUN")]
+    pub dry_run: bool,
+
+    /// The manifest of the sync, it maps the remote image ids to the local file hashes
+    /// The default value is .face-trainer-manifest.json under the dataset path
+    #[clap(long, env = "MANIFEST")]
+    pub manifest: Option<PathBuf>,
+}
+
+/// verify command arguments
+#[derive(Debug, Clone, clap::Args)]
+pub struct VerifyArgs {
+    #[clap(flatten)]
+    pub backend: BackendArgs,
+
+    #[clap(flatten)]
+    pub dataset: DatasetArgs,
+
+    /// verification options
+    #[clap(flatten)]
+    pub verification: VerificationOptions,
+}
@@ -0,0 +1,632 @@
+use std::{
+    fmt::{Display, Formatter},
+    io::Cursor,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::anyhow;
+use futures::{future, stream, StreamExt, TryStreamExt};
+use image::{imageops::FilterType, DynamicImage, ImageFormat, Rgb, RgbImage};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rand_distr::{Distribution, Normal};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use stream_utils::RecursiveFileStream;
+use tokio::fs;
+use tracing::{debug, error};
+
+use crate::{utils, Configuration, FaceProcessingResult, FailureFace, MissedFace, Outcome};
+
+/// The transforms of the augment command when none are given
+pub const DEFAULT_TRANSFORMS: &str = "flip,rotate=15,brightness=0.3,contrast=0.3,crop=0.1";
+
+/// A single transform of the augmentation, with its maximum strength
+/// Each variant draws its own strength between no change and the maximum
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transform {
+    /// mirror the image horizontally, on half of the variants
+    Flip,
+    /// rotate about the center, up to the given degrees to either side
+    Rotate(f32),
+    /// shift the brightness, up to the given fraction of the full range
+    Brightness(f32),
+    /// change the contrast, up to the given fraction
+    Contrast(f32),
+    /// gaussian blur, up to the given sigma
+    Blur(f32),
+    /// gaussian noise, up to the given standard deviation as a fraction of the full range
+    Noise(f32),
+    /// cut each side, up to the given fraction, and resize back to the original size
+    Crop(f32),
+}
+
+impl Transform {
+    fn name(&self) -> &'static str {
+        match self {
+            Transform::Flip => "flip",
+            Transform::Rotate(_) => "rotate",
+            Transform::Brightness(_) => "brightness",
+            Transform::Contrast(_) => "contrast",
+            Transform::Blur(_) => "blur",
+            Transform::Noise(_) => "noise",
+            Transform::Crop(_) => "crop",
+        }
+    }
+
+    fn strength(&self) -> Option<f32> {
+        match *self {
+            Transform::Flip => None,
+            Transform::Rotate(v)
+            | Transform::Brightness(v)
+            | Transform::Contrast(v)
+            | Transform::Blur(v)
+            | Transform::Noise(v)
+            | Transform::Crop(v) => Some(v),
+        }
+    }
+
+    /// apply the transform with a random strength,
+    /// returns the description of the applied change, None when the image is unchanged
+    fn apply(&self, image: RgbImage, rng: &mut ChaCha8Rng) -> (RgbImage, Option<String>) {
+        match *self {
+            Transform::Flip => {
+                if rng.gen_bool(0.5) {
+                    (
+                        image::imageops::flip_horizontal(&image),
+                        Some("flip".into()),
+                    )
+                } else {
+                    (image, None)
+                }
+            }
+            Transform::Rotate(max) => {
+                let degrees = rng.gen_range(-max..=max);
+                (
+                    rotate(&image, degrees),
+                    Some(format!("rotate={:.1}", degrees)),
+                )
+            }
+            Transform::Brightness(max) => {
+                let value = rng.gen_range(-max..=max);
+                (
+                    image::imageops::brighten(&image, (value * 255.0) as i32),
+                    Some(format!("brightness={:.2}", value)),
+                )
+            }
+            Transform::Contrast(max) => {
+                let value = rng.gen_range(-max..=max);
+                (
+                    image::imageops::contrast(&image, value * 100.0),
+                    Some(format!("contrast={:.2}", value)),
+                )
+            }
+            Transform::Blur(max) => {
+                let sigma = rng.gen_range(0.0..=max);
+                if sigma < 0.1 {
+                    return (image, None);
+                }
+                (
+                    image::imageops::blur(&image, sigma),
+                    Some(format!("blur={:.1}", sigma)),
+                )
+            }
+            Transform::Noise(max) => {
+                let deviation = rng.gen_range(0.0..=max);
+                (
+                    noise(image, deviation * 255.0, rng),
+                    Some(format!("noise={:.2}", deviation)),
+                )
+            }
+            Transform::Crop(max) => {
+                let (width, height) = image.dimensions();
+                let mut cut = || rng.gen_range(0.0..=max);
+                let (left, top) = (
+                    (width as f32 * cut()) as u32,
+                    (height as f32 * cut()) as u32,
+                );
+                let (right, bottom) = (
+                    (width as f32 * cut()) as u32,
+                    (height as f32 * cut()) as u32,
+                );
+                let cropped = image::imageops::crop_imm(
+                    &image,
+                    left,
+                    top,
+                    (width - left - right).max(1),
+                    (height - top - bottom).max(1),
+                )
+                .to_image();
+                (
+                    image::imageops::resize(&cropped, width, height, FilterType::Triangle),
+                    Some(format!("crop={}:{}:{}:{}", left, top, right, bottom)),
+                )
+            }
+        }
+    }
+}
+
+/// rotate about the center with bilinear sampling, the corners are filled from the nearest edge
+fn rotate(image: &RgbImage, degrees: f32) -> RgbImage {
+    let (width, height) = image.dimensions();
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (center_x, center_y) = (width as f32 / 2.0, height as f32 / 2.0);
+    let max_x = (width - 1) as f32;
+    let max_y = (height - 1) as f32;
+    RgbImage::from_fn(width, height, |x, y| {
+        let (dx, dy) = (x as f32 - center_x, y as f32 - center_y);
+        let source_x = (cos * dx + sin * dy + center_x).clamp(0.0, max_x);
+        let source_y = (-sin * dx + cos * dy + center_y).clamp(0.0, max_y);
+        let (x0, y0) = (source_x.floor(), source_y.floor());
+        let (fx, fy) = (source_x - x0, source_y - y0);
+        let (x0, y0) = (x0 as u32, y0 as u32);
+        let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+        let mut pixel = [0u8; 3];
+        for (channel, value) in pixel.iter_mut().enumerate() {
+            let top = image.get_pixel(x0, y0)[channel] as f32 * (1.0 - fx)
+                + image.get_pixel(x1, y0)[channel] as f32 * fx;
+            let bottom = image.get_pixel(x0, y1)[channel] as f32 * (1.0 - fx)
+                + image.get_pixel(x1, y1)[channel] as f32 * fx;
+            *value = (top * (1.0 - fy) + bottom * fy).round() as u8;
+        }
+        Rgb(pixel)
+    })
+}
+
+/// add gaussian noise with the given standard deviation to every channel
+fn noise(mut image: RgbImage, deviation: f32, rng: &mut ChaCha8Rng) -> RgbImage {
+    if let Ok(normal) = Normal::new(0.0, deviation) {
+        for value in image.iter_mut() {
+            *value = (*value as f32 + normal.sample(rng)).clamp(0.0, 255.0) as u8;
+        }
+    }
+    image
+}
+
+/// AugmentationSpec is the ordered list of transforms of every variant,
+/// written as comma separated names with an optional maximum strength, e.g. flip,rotate=15,blur=1.5
+#[derive(Debug, Clone, PartialEq)]
+pub struct AugmentationSpec {
+    pub transforms: Vec<Transform>,
+}
+
+impl FromStr for AugmentationSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let transforms = s
+            .split(',')
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(|t| {
+                let (name, value) = match t.split_once('=') {
+                    Some((name, value)) => (name.trim(), Some(value.trim())),
+                    None => (t, None),
+                };
+                let strength = |default: f32, max: f32| -> Result<f32, String> {
+                    let strength = match value {
+                        Some(value) => value
+                            .parse::<f32>()
+                            .map_err(|_| format!("invalid strength of transform: {}", t))?,
+                        None => default,
+                    };
+                    if !(0.0..=max).contains(&strength) {
+                        return Err(format!(
+                            "the strength of transform: {} must be between 0 and {}",
+                            name, max
+                        ));
+                    }
+                    Ok(strength)
+                };
+                match name {
+                    "flip" if value.is_none() => Ok(Transform::Flip),
+                    "rotate" => Ok(Transform::Rotate(strength(15.0, 180.0)?)),
+                    "brightness" => Ok(Transform::Brightness(strength(0.3, 1.0)?)),
+                    "contrast" => Ok(Transform::Contrast(strength(0.3, 1.0)?)),
+                    "blur" => Ok(Transform::Blur(strength(1.5, 10.0)?)),
+                    "noise" => Ok(Transform::Noise(strength(0.05, 1.0)?)),
+                    "crop" => Ok(Transform::Crop(strength(0.1, 0.4)?)),
+                    _ => Err(format!(
+                        "unknown transform: {}, expected flip, rotate, brightness, contrast, blur, noise or crop",
+                        t
+                    )),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if transforms.is_empty() {
+            return Err("at least one transform is required".into());
+        }
+        Ok(AugmentationSpec { transforms })
+    }
+}
+
+impl Display for AugmentationSpec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let transforms: Vec<String> = self
+            .transforms
+            .iter()
+            .map(|t| match t.strength() {
+                Some(strength) => format!("{}={}", t.name(), strength),
+                None => t.name().to_string(),
+            })
+            .collect();
+        write!(f, "{}", transforms.join(","))
+    }
+}
+
+/// Augmenter creates reproducible variants of an image,
+/// the randomness of a variant depends only on the seed, the image key and the variant number,
+/// so the same variants are created regardless of the processing order
+#[derive(Debug, Clone)]
+pub struct Augmenter {
+    spec: AugmentationSpec,
+    seed: u64,
+}
+
+impl Augmenter {
+    pub fn new(spec: AugmentationSpec, seed: u64) -> Self {
+        Augmenter { spec, seed }
+    }
+
+    /// the given variant of the image, with the description of the applied transforms
+    pub fn variant(&self, image: &DynamicImage, key: &str, variant: usize) -> (RgbImage, String) {
+        let digest = Sha256::digest(format!("{}:{}:{}", self.seed, key, variant));
+        let mut rng = ChaCha8Rng::from_seed(digest.into());
+        let mut image = image.to_rgb8();
+        let mut applied = Vec::new();
+        for transform in self.spec.transforms.iter() {
+            let (transformed, description) = transform.apply(image, &mut rng);
+            image = transformed;
+            applied.extend(description);
+        }
+        let description = if applied.is_empty() {
+            "none".to_string()
+        } else {
+            applied.join(",")
+        };
+        (image, description)
+    }
+}
+
+/// An augmented variant of a dataset image, created in memory while training
+#[derive(Debug, Clone)]
+pub struct AugmentedImage {
+    /// the virtual path of the variant next to its source, e.g. alice/1_aug_2.jpg, it is not on the disk
+    pub path: PathBuf,
+    /// the dataset image of the variant
+    pub source: PathBuf,
+    /// the applied transforms, e.g. flip,rotate=-7.3
+    pub transforms: String,
+    /// the encoded variant, in the format of its source
+    pub content: Vec<u8>,
+}
+
+/// A trained variant, tagged with its transforms and the outcome of its training
+#[derive(Debug, Clone, Serialize)]
+pub struct AugmentedFace {
+    pub path: PathBuf,
+    pub source: PathBuf,
+    pub transforms: String,
+    /// sha256 of the variant content
+    pub hash: String,
+    pub outcome: Outcome,
+    /// the error or reject reason, when the variant was not trained
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// create the variants of the file in memory, encoded in the format of the file
+/// The key decides the randomness of the variants, the path under the dataset
+/// creates the same variants as the augment command with the same seed
+pub async fn augment_in_memory(
+    augmenter: Arc<Augmenter>,
+    file_path: PathBuf,
+    key: String,
+    variants: usize,
+) -> anyhow::Result<Vec<AugmentedImage>> {
+    let content = fs::read(&file_path).await?;
+    tokio::task::spawn_blocking(move || {
+        let format = ImageFormat::from_path(&file_path)?;
+        let image = image::load_from_memory(&content)?;
+        let file_name = PathBuf::from(file_path.file_name().unwrap_or_default());
+        let folder = file_path.parent().unwrap_or(Path::new(""));
+        (1..=variants)
+            .map(|variant| {
+                let (augmented, transforms) = augmenter.variant(&image, &key, variant);
+                let mut content = Vec::new();
+                DynamicImage::ImageRgb8(augmented)
+                    .write_to(&mut Cursor::new(&mut content), format)?;
+                Ok(AugmentedImage {
+                    path: variant_path(folder, &file_name, variant),
+                    source: file_path.clone(),
+                    transforms,
+                    content,
+                })
+            })
+            .collect()
+    })
+    .await?
+}
+
+impl From<&AugmentedImage> for AugmentedFace {
+    /// the untagged variant, a success until the result of its training is known
+    fn from(image: &AugmentedImage) -> Self {
+        AugmentedFace {
+            path: image.path.clone(),
+            source: image.source.clone(),
+            transforms: image.transforms.clone(),
+            hash: utils::content_hash(&image.content),
+            outcome: Outcome::Success,
+            reason: None,
+        }
+    }
+}
+
+/// tag the trained variants with their outcome, and add them to the augmented faces of the result
+/// The variants are not on the disk so they are removed from the failure, missed and rejected lists
+/// that are copied, moved or quarantined, the counters still include them
+pub fn tag_variants(faces: Vec<AugmentedFace>, result: &mut FaceProcessingResult) {
+    let failures: Vec<PathBuf> = result
+        .failure_faces
+        .drain(..)
+        .map(|face| match face {
+            FailureFace::Train(path) => path,
+            FailureFace::Recognize(m) => m.path,
+        })
+        .collect();
+    let missed: Vec<MissedFace> = result.missed_faces.drain(..).collect();
+    let rejected = std::mem::take(&mut result.rejected_faces);
+    for mut face in faces {
+        if failures.contains(&face.path) {
+            face.outcome = Outcome::Failure;
+        } else if let Some(missed) = missed.iter().find(|m| m.path == face.path) {
+            face.outcome = Outcome::Missed;
+            face.reason = Some(missed.reason.clone());
+        } else if let Some(rejected) = rejected.iter().find(|r| r.path == face.path) {
+            face.outcome = Outcome::Rejected;
+            face.reason = Some(rejected.reason.to_string());
+        }
+        result.augmented_faces.push(face);
+    }
+}
+
+/// augment command options
+#[derive(Debug, Clone, clap::Args)]
+pub struct AugmentOptions {
+    /// The directory of the augmented images, the folders of the dataset are mirrored under it
+    #[clap(long)]
+    pub output: PathBuf,
+
+    /// The transforms of each variant, in order, with their maximum strength:
+    /// flip, rotate=degrees, brightness=fraction, contrast=fraction, blur=sigma, noise=fraction, crop=fraction
+    #[clap(long, default_value = DEFAULT_TRANSFORMS)]
+    pub transforms: AugmentationSpec,
+
+    /// The number of variants to create of each image
+    #[clap(long, default_value = "5")]
+    pub variants: usize,
+
+    /// The seed of the random strengths, the same seed creates the same variants
+    #[clap(long, default_value = "0")]
+    pub seed: u64,
+}
+
+/// The result of the augment command
+#[derive(Debug, Default)]
+pub struct AugmentResult {
+    pub images: usize,
+    pub variants: usize,
+    pub missed_faces: Vec<MissedFace>,
+}
+
+impl Display for AugmentResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "augmented {} images into {} variants, missed: {}",
+            self.images,
+            self.variants,
+            self.missed_faces.len()
+        )
+    }
+}
+
+/// the path of the variant under the output directory, mirroring its path under the dataset
+/// e.g. alice/1.jpg is written as alice/1_aug_3.jpg
+fn variant_path(output: &Path, relative_path: &Path, variant: usize) -> PathBuf {
+    let stem = relative_path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let extension = relative_path
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let file_name = format!("{}_aug_{}.{}", stem, variant, extension);
+    match relative_path.parent() {
+        Some(parent) => output.join(parent).join(file_name),
+        None => output.join(file_name),
+    }
+}
+
+/// write the variants of a single image, on the blocking thread pool as it is cpu bound
+async fn augment_file(
+    augmenter: Arc<Augmenter>,
+    output: PathBuf,
+    relative_path: PathBuf,
+    file_path: PathBuf,
+    variants: usize,
+) -> anyhow::Result<usize> {
+    let content = fs::read(&file_path).await?;
+    tokio::task::spawn_blocking(move || {
+        let image = image::load_from_memory(&content)?;
+        let key = relative_path.to_string_lossy().to_string();
+        for variant in 1..=variants {
+            let (augmented, description) = augmenter.variant(&image, &key, variant);
+            let path = variant_path(&output, &relative_path, variant);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            augmented.save(&path)?;
+            debug!("augmented: {} with: {}", path.display(), description);
+        }
+        Ok(variants)
+    })
+    .await?
+}
+
+/// write augmented variants of every dataset image under the output directory,
+/// walking the dataset like the train and recognize modes
+pub async fn run_augment(
+    config: &Configuration,
+    options: &AugmentOptions,
+) -> anyhow::Result<AugmentResult> {
+    let dataset_path = PathBuf::from(config.dataset_path());
+    let augmenter = Arc::new(Augmenter::new(options.transforms.clone(), options.seed));
+    let files: Vec<PathBuf> = RecursiveFileStream::new(config.dataset_path())
+        .try_filter(|path| future::ready(path.is_file() && utils::is_image(path)))
+        .try_collect()
+        .await?;
+
+    let results: Vec<(PathBuf, anyhow::Result<usize>)> = stream::iter(files)
+        .map(|file_path| {
+            let augmenter = augmenter.clone();
+            let output = options.output.clone();
+            let relative_path = file_path
+                .strip_prefix(&dataset_path)
+                .map(Path::to_path_buf)
+                .map_err(|_| anyhow!("{} is not under the dataset", file_path.display()));
+            async move {
+                let result = match relative_path {
+                    Ok(relative_path) => {
+                        augment_file(
+                            augmenter,
+                            output,
+                            relative_path,
+                            file_path.clone(),
+                            options.variants,
+                        )
+                        .await
+                    }
+                    Err(e) => Err(e),
+                };
+                (file_path, result)
+            }
+        })
+        .buffer_unordered(config.concurrency)
+        .collect()
+        .await;
+
+    let mut augment_result = AugmentResult::default();
+    for (file_path, result) in results {
+        match result {
+            Ok(variants) => {
+                augment_result.images += 1;
+                augment_result.variants += variants;
+            }
+            Err(e) => {
+                error!("Failed to augment file: {}: {}", file_path.display(), e);
+                augment_result
+                    .missed_faces
+                    .push(MissedFace::new(file_path, e));
+            }
+        }
+    }
+    Ok(augment_result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_spec() {
+        let spec: AugmentationSpec = "flip, rotate=10,blur".parse().unwrap();
+        assert_eq!(
+            spec.transforms,
+            vec![
+                Transform::Flip,
+                Transform::Rotate(10.0),
+                Transform::Blur(1.5)
+            ]
+        );
+        assert_eq!(spec.to_string(), "flip,rotate=10,blur=1.5");
+        assert_eq!(spec.to_string().parse::<AugmentationSpec>().unwrap(), spec);
+        assert!("sharpen".parse::<AugmentationSpec>().is_err());
+        assert!("crop=0.9".parse::<AugmentationSpec>().is_err());
+        assert!("".parse::<AugmentationSpec>().is_err());
+    }
+
+    #[test]
+    fn test_variants_are_reproducible() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(40, 30, |x, y| {
+            Rgb([(x * 6) as u8, (y * 8) as u8, 128])
+        }));
+        let spec: AugmentationSpec = "flip,rotate,brightness,contrast,blur,noise,crop"
+            .parse()
+            .unwrap();
+        let augmenter = Augmenter::new(spec.clone(), 7);
+        let (first, description) = augmenter.variant(&image, "alice/1.jpg", 1);
+        assert_eq!(first.dimensions(), (40, 30));
+        assert!(description.contains("rotate="));
+        assert_eq!(
+            Augmenter::new(spec.clone(), 7).variant(&image, "alice/1.jpg", 1),
+            (first.clone(), description)
+        );
+        assert_ne!(augmenter.variant(&image, "alice/1.jpg", 2).0, first);
+        assert_ne!(
+            Augmenter::new(spec, 8).variant(&image, "alice/1.jpg", 1).0,
+            first
+        );
+    }
+
+    #[test]
+    fn test_variant_path_mirrors_the_dataset() {
+        assert_eq!(
+            variant_path(Path::new("/augmented"), Path::new("alice/1.jpg"), 3),
+            PathBuf::from("/augmented/alice/1_aug_3.jpg")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_augment_in_memory_and_tag_variants() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("1.png");
+        let image = RgbImage::from_fn(40, 30, |x, y| Rgb([(x * 6) as u8, (y * 8) as u8, 128]));
+        image.save(&source).unwrap();
+        let augmenter = Arc::new(Augmenter::new("flip,rotate=10".parse().unwrap(), 7));
+
+        let images = augment_in_memory(augmenter.clone(), source.clone(), "alice/1.png".into(), 2)
+            .await
+            .unwrap();
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[1].path, dir.path().join("1_aug_2.png"));
+        assert!(!images[1].path.exists());
+        // png is lossless, so the content is the same variant as the augment command writes
+        let (expected, transforms) =
+            augmenter.variant(&DynamicImage::ImageRgb8(image), "alice/1.png", 2);
+        let decoded = image::load_from_memory(&images[1].content).unwrap();
+        assert_eq!(decoded.to_rgb8(), expected);
+        assert_eq!(images[1].transforms, transforms);
+
+        let mut result = FaceProcessingResult::with_context("alice".into());
+        result.total_count = 2;
+        result.success_count = 1;
+        result.missed_count = 1;
+        result
+            .missed_faces
+            .push(MissedFace::new(images[1].path.clone(), "timeout"));
+        tag_variants(
+            images.iter().map(AugmentedFace::from).collect(),
+            &mut result,
+        );
+        assert!(result.missed_faces.is_empty());
+        assert_eq!(result.missed_count, 1);
+        assert_eq!(result.augmented_faces[0].outcome, Outcome::Success);
+        assert_eq!(result.augmented_faces[1].outcome, Outcome::Missed);
+        assert_eq!(result.augmented_faces[1].reason.as_deref(), Some("timeout"));
+        assert_eq!(result.augmented_faces[1].source, source);
+    }
+}
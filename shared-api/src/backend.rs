@@ -0,0 +1,616 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::anyhow;
+use tokio::sync::{
+    mpsc::{self, Receiver, Sender},
+    Mutex,
+};
+use tracing::warn;
+
+use crate::{
+    augment_in_memory, process_files, quarantine, tag_variants, AugmentedFace, AugmentedImage,
+    Augmenter, ClientMode, ClientType, Configuration, FaceCollection, FaceProcessingResult,
+    Journal, PreflightAction, ProgressReporter, Recognizer, Trainer, TrainingManifest, Verifier,
+};
+
+/// FaceBackend trait
+/// A face service that supports both training and recognition,
+/// the generic drivers use it as a trait object so new services only have to implement the api calls
+pub trait FaceBackend: Trainer + Recognizer + Send + Sync {}
+
+impl<T> FaceBackend for T where T: Trainer + Recognizer + Send + Sync {}
+
+/// Create a backend instance from the configuration
+pub type BackendFactory = fn(&Configuration) -> anyhow::Result<Arc<dyn FaceBackend>>;
+
+/// Create a face collection instance from the configuration
+pub type CollectionFactory = fn(&Configuration) -> anyhow::Result<Arc<dyn FaceCollection>>;
+
+/// Create a verifier instance from the configuration
+pub type VerifierFactory = fn(&Configuration) -> anyhow::Result<Arc<dyn Verifier>>;
+
+/// BackendRegistry holds the backend factory per client type,
+/// and the collection and verifier factories of the client types that support them
+#[derive(Default)]
+pub struct BackendRegistry {
+    factories: HashMap<ClientType, BackendFactory>,
+    collection_factories: HashMap<ClientType, CollectionFactory>,
+    verifier_factories: HashMap<ClientType, VerifierFactory>,
+}
+
+impl BackendRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// register the factory of the given client type, replacing any previous registration
+    pub fn register(mut self, client_type: ClientType, factory: BackendFactory) -> Self {
+        self.factories.insert(client_type, factory);
+        self
+    }
+
+    /// create the backend that match the configured client type
+    pub fn create(&self, config: &Configuration) -> anyhow::Result<Arc<dyn FaceBackend>> {
+        let client_type = configured_client_type(config)?;
+        let factory = self.factories.get(&client_type).ok_or(anyhow!(
+            "no backend registered for client type: {:?}",
+            client_type
+        ))?;
+        factory(config)
+    }
+
+    /// register the collection factory of the given client type, replacing any previous registration
+    pub fn register_collection(
+        mut self,
+        client_type: ClientType,
+        factory: CollectionFactory,
+    ) -> Self {
+        self.collection_factories.insert(client_type, factory);
+        self
+    }
+
+    /// create the face collection that match the configured client type
+    pub fn create_collection(
+        &self,
+        config: &Configuration,
+    ) -> anyhow::Result<Arc<dyn FaceCollection>> {
+        let client_type = configured_client_type(config)?;
+        let factory = self.collection_factories.get(&client_type).ok_or(anyhow!(
+            "client type: {:?} does not support managing subjects and faces",
+            client_type
+        ))?;
+        factory(config)
+    }
+
+    /// register the verifier factory of the given client type, replacing any previous registration
+    pub fn register_verifier(mut self, client_type: ClientType, factory: VerifierFactory) -> Self {
+        self.verifier_factories.insert(client_type, factory);
+        self
+    }
+
+    /// create the verifier that match the configured client type
+    pub fn create_verifier(&self, config: &Configuration) -> anyhow::Result<Arc<dyn Verifier>> {
+        let client_type = configured_client_type(config)?;
+        let factory = self.verifier_factories.get(&client_type).ok_or(anyhow!(
+            "client type: {:?} does not support the verify mode",
+            client_type
+        ))?;
+        factory(config)
+    }
+}
+
+/// the configured client type, it is only missing when the command does not use a backend
+fn configured_client_type(config: &Configuration) -> anyhow::Result<ClientType> {
+    config
+        .client_type
+        .ok_or(anyhow!("--client-type is required to create a backend"))
+}
+
+/// train all the dataset faces with the given backend
+pub async fn run_train(
+    config: &Configuration,
+    backend: Arc<dyn FaceBackend>,
+    progress_reporter_tx: Sender<ProgressReporter>,
+) -> anyhow::Result<FaceProcessingResult> {
+    run(config, backend, ClientMode::Train, progress_reporter_tx).await
+}
+
+/// recognize all the dataset faces with the given backend
+pub async fn run_recognize(
+    config: &Configuration,
+    backend: Arc<dyn FaceBackend>,
+    progress_reporter_tx: Sender<ProgressReporter>,
+) -> anyhow::Result<FaceProcessingResult> {
+    run(config, backend, ClientMode::Recognize, progress_reporter_tx).await
+}
+
+/// recognize all the dataset faces with the given backend, the backend of an evaluate configuration
+/// keeps the raw samples for the evaluation
+pub async fn run_evaluate(
+    config: &Configuration,
+    backend: Arc<dyn FaceBackend>,
+    progress_reporter_tx: Sender<ProgressReporter>,
+) -> anyhow::Result<FaceProcessingResult> {
+    run(config, backend, ClientMode::Evaluate, progress_reporter_tx).await
+}
+
+/// scan the dataset and send each group of files to the backend,
+/// accumulating the partial results into a single result
+async fn run(
+    config: &Configuration,
+    backend: Arc<dyn FaceBackend>,
+    mode: ClientMode,
+    progress_reporter_tx: Sender<ProgressReporter>,
+) -> anyhow::Result<FaceProcessingResult> {
+    let state = Arc::new(Mutex::new(FaceProcessingResult::with_context(
+        config.dataset_path().to_string(),
+    )));
+    let state_result = state.clone();
+    let flush_backend = backend.clone();
+    let process_progress_reporter_tx = progress_reporter_tx.clone();
+    let api_progress_reporter_tx = progress_reporter_tx.clone();
+
+    // the journal is kept under the output directory, it is required to resume the run later
+    let journal = match config.journal_path() {
+        Some(path) => Some(Arc::new(
+            Journal::open(&path, mode, config.backend_id(), config.resume).await?,
+        )),
+        None => None,
+    };
+    let api_journal = journal.clone();
+    // the trained image ids are kept to rollback the run later
+    let training_manifest = match (mode, config.run_id.as_ref()) {
+        (ClientMode::Train, Some(run_id)) => Some(TrainingManifest::new(
+            config.training_manifest.clone(),
+            run_id.clone(),
+            config.backend_id(),
+        )),
+        _ => None,
+    };
+    let recorder = Arc::new(TrainRecorder {
+        journal: journal.clone(),
+        training_manifest,
+        variants: Mutex::new(HashMap::new()),
+    });
+    let preflight_action = config.preflight_action;
+    let output_dir = config.error_configuration.output_dir.clone();
+    let augmenter = match (mode, config.augment_on_train.as_ref()) {
+        (ClientMode::Train, Some(spec)) => {
+            Some(Arc::new(Augmenter::new(spec.clone(), config.augment_seed)))
+        }
+        _ => None,
+    };
+    let augment_options = AugmentOnTrain {
+        dataset_path: PathBuf::from(config.dataset_path()),
+        variants: config.augment_variants,
+        max_request_size: config.max_request_size,
+    };
+
+    let processed = process_files(
+        config,
+        journal,
+        process_progress_reporter_tx,
+        move |name: String, files: Vec<PathBuf>, process_progress_reporter_tx| {
+            let backend = Arc::clone(&backend);
+            let cloned_result = state.clone();
+            let cloned_tx = api_progress_reporter_tx.clone();
+            let journal = api_journal.clone();
+            let recorder = recorder.clone();
+            let output_dir = output_dir.clone();
+            let augmenter = augmenter.clone();
+            let augment_options = augment_options.clone();
+            async move {
+                // the trained files are recorded by the recorder, one request at a time
+                let journal_files = match mode {
+                    ClientMode::Recognize | ClientMode::Evaluate => {
+                        journal.as_ref().map(|_| files.clone())
+                    }
+                    _ => None,
+                };
+                let mut partial_result = match mode {
+                    // the sync mode trains through run_sync, only the new files are sent
+                    ClientMode::Train | ClientMode::Sync => {
+                        let augment_files = augmenter.as_ref().map(|_| files.clone());
+                        // the client reports each request on this channel, the trained files are recorded
+                        // as soon as they are reported and the other messages are forwarded
+                        let (train_tx, train_rx) = mpsc::channel(2);
+                        let train = async {
+                            let mut result = backend
+                                .send_to_train(&name, files, train_tx.clone())
+                                .await?;
+                            if let (Some(augmenter), Some(augment_files)) =
+                                (augmenter, augment_files)
+                            {
+                                result.add(
+                                    train_augmented(
+                                        backend.as_ref(),
+                                        &recorder,
+                                        augmenter,
+                                        &augment_options,
+                                        &name,
+                                        augment_files,
+                                        train_tx,
+                                    )
+                                    .await?,
+                                );
+                            }
+                            Ok::<_, anyhow::Error>(result)
+                        };
+                        let (trained, recorded) = tokio::join!(
+                            train,
+                            recorder.forward(train_rx, process_progress_reporter_tx)
+                        );
+                        recorded?;
+                        trained?
+                    }
+                    ClientMode::Recognize | ClientMode::Evaluate => {
+                        backend
+                            .recognize(&name, files, process_progress_reporter_tx)
+                            .await?
+                    }
+                    ClientMode::Verify => {
+                        return Err(anyhow!(
+                            "the verify mode compares pairs of images, see run_verify"
+                        ))
+                    }
+                };
+
+                partial_result.summarize_subject(&name);
+
+                // record the journal before the failures are handled, as they may be moved
+                if let (Some(journal), Some(journal_files)) = (journal, journal_files) {
+                    journal.record(&journal_files, &partial_result).await?;
+                }
+
+                if let (PreflightAction::Quarantine, Some(output_dir)) =
+                    (preflight_action, output_dir.as_ref())
+                {
+                    quarantine(Path::new(output_dir), &partial_result.rejected_faces).await?;
+                }
+
+                if matches!(mode, ClientMode::Recognize | ClientMode::Evaluate) {
+                    // send the partial result, before accumulating it
+                    cloned_tx
+                        .send(ProgressReporter::PartialStructedMessage(
+                            partial_result.clone(),
+                        ))
+                        .await?;
+                }
+
+                // accumulate the result
+                let mut guard = cloned_result.lock().await;
+                guard.add(partial_result);
+                let report = guard.without_samples();
+                cloned_tx
+                    .send(ProgressReporter::AccumulatedStructedMessage(report))
+                    .await?;
+
+                Ok(())
+            }
+        },
+    )
+    .await;
+    // the faces that were trained before a failure are kept too
+    flush_backend.flush().await?;
+    processed?;
+    let state_result = state_result.lock().await.clone();
+    Ok(state_result)
+}
+
+/// TrainRecorder records the trained files on the journal and on the training manifest as soon as
+/// the client reports them, so the files that were stored before an interruption are not trained again
+/// on resume, and their faces are deleted by the rollback of the run
+struct TrainRecorder {
+    journal: Option<Arc<Journal>>,
+    training_manifest: Option<TrainingManifest>,
+    /// the variants that are being trained by their virtual path, they are tagged before they are recorded
+    variants: Mutex<HashMap<PathBuf, AugmentedFace>>,
+}
+
+impl TrainRecorder {
+    /// record the trained files reported on the channel, and forward the other messages
+    async fn forward(
+        &self,
+        mut rx: Receiver<ProgressReporter>,
+        tx: Sender<ProgressReporter>,
+    ) -> anyhow::Result<()> {
+        while let Some(message) = rx.recv().await {
+            match message {
+                ProgressReporter::Trained(files, result) => self.record(files, result).await?,
+                message => tx.send(message).await?,
+            }
+        }
+        Ok(())
+    }
+
+    /// the variants are trained on their own requests, they are tagged with their source when reported
+    async fn expect_variants(&self, faces: &[AugmentedFace]) {
+        let mut variants = self.variants.lock().await;
+        for face in faces {
+            variants.insert(face.path.clone(), face.clone());
+        }
+    }
+
+    async fn record(
+        &self,
+        files: Vec<PathBuf>,
+        mut result: FaceProcessingResult,
+    ) -> anyhow::Result<()> {
+        let faces: Vec<AugmentedFace> = {
+            let mut variants = self.variants.lock().await;
+            files
+                .iter()
+                .filter_map(|path| variants.remove(path))
+                .collect()
+        };
+        let files = if faces.is_empty() {
+            files
+        } else {
+            tag_variants(faces, &mut result);
+            Vec::new()
+        };
+        if let Some(ref journal) = self.journal {
+            journal.record(&files, &result).await?;
+        }
+        if let Some(ref training_manifest) = self.training_manifest {
+            training_manifest
+                .record(&result.trained_faces, &result.augmented_faces)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// the options of the augmented variants that are trained with each batch
+#[derive(Clone)]
+struct AugmentOnTrain {
+    /// the variants are keyed by the path under the dataset, like the augment command
+    dataset_path: PathBuf,
+    variants: usize,
+    max_request_size: u64,
+}
+
+/// train the augmented variants of the files, in batches bounded by the max request size
+/// A file that can not be decoded is trained without variants, its own result already reports it
+async fn train_augmented(
+    backend: &dyn FaceBackend,
+    recorder: &TrainRecorder,
+    augmenter: Arc<Augmenter>,
+    options: &AugmentOnTrain,
+    name: &str,
+    files: Vec<PathBuf>,
+    progress_reporter_tx: Sender<ProgressReporter>,
+) -> anyhow::Result<FaceProcessingResult> {
+    let mut images = Vec::new();
+    for file_path in files {
+        let key = file_path
+            .strip_prefix(&options.dataset_path)
+            .unwrap_or(&file_path)
+            .to_string_lossy()
+            .to_string();
+        match augment_in_memory(augmenter.clone(), file_path.clone(), key, options.variants).await {
+            Ok(variants) => images.extend(variants),
+            Err(e) => warn!("Failed to augment file: {}: {}", file_path.display(), e),
+        }
+    }
+    progress_reporter_tx
+        .send(ProgressReporter::IncreaseLength(images.len() as u64))
+        .await?;
+
+    let mut result = FaceProcessingResult::with_context(name.to_string());
+    let mut batch: Vec<AugmentedImage> = Vec::new();
+    let mut total_size = 0;
+    let mut images = images.into_iter().peekable();
+    while let Some(image) = images.next() {
+        total_size += image.content.len() as u64;
+        batch.push(image);
+        let is_full = images
+            .peek()
+            .is_some_and(|next| total_size + next.content.len() as u64 > options.max_request_size);
+        if is_full || images.peek().is_none() {
+            let faces: Vec<AugmentedFace> = batch.iter().map(AugmentedFace::from).collect();
+            recorder.expect_variants(&faces).await;
+            let mut batch_result = backend
+                .send_augmented_to_train(
+                    name,
+                    std::mem::take(&mut batch),
+                    progress_reporter_tx.clone(),
+                )
+                .await?;
+            tag_variants(faces, &mut batch_result);
+            result.add(batch_result);
+            total_size = 0;
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{Recognizer, TrainedFace, Trainer};
+
+    /// trains the files one by one, and is interrupted on the file with the given name
+    #[derive(Default)]
+    struct InterruptedTrainer {
+        interrupt_on: Option<String>,
+        trained: Mutex<Vec<PathBuf>>,
+    }
+
+    #[async_trait]
+    impl Trainer for InterruptedTrainer {
+        async fn send_to_train(
+            &self,
+            name: &str,
+            files: Vec<PathBuf>,
+            progress_reporter_tx: Sender<ProgressReporter>,
+        ) -> anyhow::Result<FaceProcessingResult> {
+            let mut result = FaceProcessingResult::with_context(name.to_string());
+            for file_path in files {
+                if file_path.file_name().and_then(|name| name.to_str())
+                    == self.interrupt_on.as_deref()
+                {
+                    return Err(anyhow!("interrupted"));
+                }
+                self.trained.lock().await.push(file_path.clone());
+                let mut file_result = FaceProcessingResult::with_context(name.to_string());
+                file_result.total_count = 1;
+                file_result.success_count = 1;
+                file_result.trained_faces.push(TrainedFace {
+                    path: file_path.clone(),
+                    image_id: file_path.display().to_string(),
+                    subject: name.to_string(),
+                });
+                progress_reporter_tx
+                    .send(ProgressReporter::Trained(
+                        vec![file_path],
+                        file_result.clone(),
+                    ))
+                    .await?;
+                result.add(file_result);
+            }
+            Ok(result)
+        }
+
+        async fn send_augmented_to_train(
+            &self,
+            _name: &str,
+            _images: Vec<AugmentedImage>,
+            _progress_reporter_tx: Sender<ProgressReporter>,
+        ) -> anyhow::Result<FaceProcessingResult> {
+            Err(anyhow!("not supported"))
+        }
+    }
+
+    #[async_trait]
+    impl Recognizer for InterruptedTrainer {
+        async fn recognize(
+            &self,
+            _name: &str,
+            _files: Vec<PathBuf>,
+            _progress_reporter_tx: Sender<ProgressReporter>,
+        ) -> anyhow::Result<FaceProcessingResult> {
+            Err(anyhow!("not supported"))
+        }
+    }
+
+    fn config(client_type: &str) -> Configuration {
+        Configuration::try_get_without_config_file([
+            "face-recognition-trainer",
+            "train",
+            "--client-type",
+            client_type,
+            "--dataset-path",
+            "/tmp/dataset",
+            "--compreface-api-key",
+            "key",
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_create_without_registered_client_type() {
+        let registry = BackendRegistry::new();
+        let error = registry.create(&config("compreface")).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "no backend registered for client type: Compreface"
+        );
+    }
+
+    #[test]
+    fn test_create_calls_the_registered_factory() {
+        let registry = BackendRegistry::new()
+            .register(ClientType::Compreface, |_| {
+                Err(anyhow!("compreface factory"))
+            })
+            .register(ClientType::DoubleTake, |_| {
+                Err(anyhow!("double-take factory"))
+            });
+        let error = registry.create(&config("double-take")).err().unwrap();
+        assert_eq!(error.to_string(), "double-take factory");
+    }
+
+    #[test]
+    fn test_create_collection_of_unsupported_client_type() {
+        let registry = BackendRegistry::new()
+            .register_collection(ClientType::Compreface, |_| Err(anyhow!("compreface")));
+        let error = registry
+            .create_collection(&config("double-take"))
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "client type: DoubleTake does not support managing subjects and faces"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resume_an_interrupted_batch() {
+        let dir = tempdir().unwrap();
+        let dataset = dir.path().join("dataset");
+        std::fs::create_dir_all(dataset.join("alice")).unwrap();
+        for i in 0..3 {
+            std::fs::write(
+                dataset.join(format!("alice/{}.jpg", i)),
+                format!("alice {}", i),
+            )
+            .unwrap();
+        }
+        let output_dir = dir.path().join("output");
+        let manifest = dir.path().join("training.jsonl");
+        let config = |resume: bool| {
+            let mut args = vec![
+                "face-recognition-trainer",
+                "train",
+                "--client-type",
+                "mock",
+                "--mock-fixture",
+                "fixture.json",
+                "--dataset-path",
+                dataset.to_str().unwrap(),
+                "--output-dir",
+                output_dir.to_str().unwrap(),
+                "--training-manifest",
+                manifest.to_str().unwrap(),
+            ];
+            if resume {
+                args.push("--resume");
+            }
+            Configuration::try_get_without_config_file(args).unwrap()
+        };
+        let train = |backend: Arc<InterruptedTrainer>, config: Configuration| async move {
+            let (tx, mut rx) = mpsc::channel(2);
+            let drain = tokio::spawn(async move { while rx.recv().await.is_some() {} });
+            let result = run_train(&config, backend, tx).await;
+            drain.await.unwrap();
+            result
+        };
+
+        // the whole folder is a single batch, it is interrupted on its last file
+        let interrupted = Arc::new(InterruptedTrainer {
+            interrupt_on: Some("2.jpg".to_string()),
+            ..Default::default()
+        });
+        assert!(train(interrupted.clone(), config(false)).await.is_err());
+        assert_eq!(interrupted.trained.lock().await.len(), 2);
+        // the faces stored before the interruption are rolled back with the run
+        let entries = crate::read_training_manifest(&manifest).await.unwrap();
+        assert_eq!(entries.len(), 2);
+
+        let resumed = Arc::new(InterruptedTrainer::default());
+        let result = train(resumed.clone(), config(true)).await.unwrap();
+        assert_eq!(result.success_count, 1);
+        assert_eq!(
+            *resumed.trained.lock().await,
+            vec![dataset.join("alice/2.jpg")]
+        );
+    }
+}
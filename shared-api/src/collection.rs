@@ -0,0 +1,85 @@
+use async_trait::async_trait;
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+
+/// A single stored example face of a subject
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct StoredFace {
+    pub image_id: String,
+    pub subject: String,
+}
+
+/// A single page of the stored faces
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FacesPage {
+    pub faces: Vec<StoredFace>,
+    pub page_number: u32,
+    pub page_size: u32,
+    pub total_pages: u32,
+    pub total_elements: u64,
+}
+
+/// FaceCollection trait
+/// Manage the subjects and the stored example faces of a face service
+#[async_trait]
+pub trait FaceCollection: Send + Sync {
+    async fn list_subjects(&self) -> anyhow::Result<Vec<String>>;
+
+    async fn add_subject(&self, subject: &str) -> anyhow::Result<()>;
+
+    /// rename the subject, its faces are kept
+    async fn rename_subject(&self, subject: &str, new_name: &str) -> anyhow::Result<()>;
+
+    /// delete the subject with all its faces
+    async fn delete_subject(&self, subject: &str) -> anyhow::Result<()>;
+
+    /// list a single page of the stored faces, of all the subjects when subject is None
+    async fn list_faces(
+        &self,
+        subject: Option<&str>,
+        page: u32,
+        size: u32,
+    ) -> anyhow::Result<FacesPage>;
+
+    async fn delete_face(&self, image_id: &str) -> anyhow::Result<StoredFace>;
+
+    /// delete all the faces of the subject, the subject itself is kept
+    /// returns the number of deleted faces
+    async fn delete_subject_faces(&self, subject: &str) -> anyhow::Result<u64>;
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum SubjectsCommand {
+    /// List all the subjects
+    List,
+    /// Add a new subject without faces
+    Add { subject: String },
+    /// Rename a subject, its faces are kept
+    Rename { subject: String, new_name: String },
+    /// Delete a subject with all its faces
+    Delete { subject: String },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum FacesCommand {
+    /// List the stored faces, page by page
+    List {
+        /// Only the faces of this subject
+        #[clap(long)]
+        subject: Option<String>,
+        /// The page number, starting from 0
+        #[clap(long, default_value = "0")]
+        page: u32,
+        /// The number of faces per page
+        #[clap(long, default_value = "20")]
+        size: u32,
+    },
+    /// Delete a single face by its image id
+    Delete { image_id: String },
+    /// Delete all the faces of a subject, the subject itself is kept
+    Purge {
+        /// The subject whose faces are deleted
+        #[clap(long)]
+        subject: String,
+    },
+}
@@ -0,0 +1,551 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use clap::{parser::ValueSource, Arg, ArgMatches, Command, CommandFactory, Subcommand};
+use serde::Deserialize;
+
+use crate::{experiment::option_args, Arguments, OptionValue, Options};
+
+/// The default configuration file, under the current directory
+pub const CONFIG_FILE_NAME: &str = "face-trainer.toml";
+
+/// the options of every command, they select the configuration file so the file can not set them
+const GLOBAL_OPTIONS: [&str; 2] = ["--config", "--profile"];
+
+/// The configuration file, its top level options apply to every run,
+/// and the options of the selected profile override them
+#[derive(Deserialize, Debug, Default)]
+pub struct ConfigFile {
+    /// the named profiles, selected with --profile
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Options>,
+
+    /// the options of every run, by their long name without the dashes, e.g. compreface-url
+    #[serde(flatten)]
+    pub options: Options,
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            anyhow!(
+                "failed to read the configuration file: {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+        toml::from_str(&content)
+            .map_err(|e| anyhow!("invalid configuration file: {}: {}", path.display(), e))
+    }
+
+    /// the top level options with the options of the profile over them, each with its source
+    pub fn options(
+        &self,
+        profile: Option<&str>,
+    ) -> anyhow::Result<BTreeMap<String, (OptionValue, OptionSource)>> {
+        let mut options: BTreeMap<_, _> = self
+            .options
+            .iter()
+            .map(|(name, value)| (name.clone(), (value.clone(), OptionSource::ConfigFile)))
+            .collect();
+        if let Some(profile) = profile {
+            let profile_options = self
+                .profiles
+                .get(profile)
+                .ok_or(anyhow!("the profile: {} is not defined", profile))?;
+            options.extend(profile_options.iter().map(|(name, value)| {
+                (
+                    name.clone(),
+                    (value.clone(), OptionSource::Profile(profile.to_string())),
+                )
+            }));
+        }
+        Ok(options)
+    }
+}
+
+/// Where the command line and the configuration file read the environment variables from
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Environment {
+    /// the environment variables of the process
+    Process,
+    /// only the given variables, the command line ignores the environment,
+    /// so the tests do not depend on the environment of the process nor change it
+    #[cfg(test)]
+    Variables(&'static [(&'static str, &'static str)]),
+}
+
+impl Environment {
+    pub(crate) fn var(&self, name: &str) -> Option<String> {
+        match self {
+            Environment::Process => std::env::var(name).ok(),
+            #[cfg(test)]
+            Environment::Variables(variables) => variables
+                .iter()
+                .find(|(variable, _)| *variable == name)
+                .map(|(_, value)| value.to_string()),
+        }
+    }
+
+    /// the command line arguments of every command
+    pub(crate) fn command(&self) -> Command {
+        match self {
+            Environment::Process => Arguments::command(),
+            #[cfg(test)]
+            Environment::Variables(_) => without_env(Arguments::command()),
+        }
+    }
+}
+
+/// the arguments without their environment variables
+#[cfg(test)]
+fn without_env(command: Command) -> Command {
+    command
+        .mut_args(|arg| arg.env(None))
+        .mut_subcommands(without_env)
+}
+
+/// The command line arguments with the options of the configuration file
+pub(crate) struct ConfigFileArgs {
+    pub args: Vec<OsString>,
+    /// the source of the options that were added from the configuration file
+    pub sources: BTreeMap<String, OptionSource>,
+}
+
+/// add the options of the configuration file to the arguments of the command,
+/// only the options that were not set on the command line or by an environment variable
+/// The options of the other commands are skipped, so a profile can be shared by several commands
+pub(crate) fn apply_config_file(
+    args: Vec<OsString>,
+    env: Environment,
+) -> anyhow::Result<ConfigFileArgs> {
+    let unchanged = |args| {
+        Ok(ConfigFileArgs {
+            args,
+            sources: BTreeMap::new(),
+        })
+    };
+    let arguments = env.command();
+    // the invalid arguments are reported by the parsing of the final arguments
+    let Ok(matches) = arguments
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(args.clone())
+    else {
+        return unchanged(args);
+    };
+    let Some((name, command_matches)) = matches.subcommand() else {
+        return unchanged(args);
+    };
+    let path = matches
+        .get_one::<PathBuf>("config")
+        .cloned()
+        .unwrap_or_else(|| PathBuf::from(CONFIG_FILE_NAME));
+    let profile = matches.get_one::<String>("profile");
+    // the default file is optional, unless a profile of it is selected
+    if !path.exists()
+        && matches.value_source("config") == Some(ValueSource::DefaultValue)
+        && profile.is_none()
+    {
+        return unchanged(args);
+    }
+
+    let file = ConfigFile::load(&path)?;
+    let command = arguments
+        .find_subcommand(name)
+        .ok_or(anyhow!("unknown command: {}", name))?;
+    let mut options = Options::new();
+    let mut sources = BTreeMap::new();
+    for (name, (value, source)) in file
+        .options(profile.map(String::as_str))
+        .map_err(|e| anyhow!("{} in the configuration file: {}", e, path.display()))?
+    {
+        let Some(arg) = command_argument(&arguments, command, &name)
+            .map_err(|e| anyhow!("{} in the configuration file: {}", e, path.display()))?
+        else {
+            continue;
+        };
+        if matches!(
+            command_matches.value_source(arg.get_id().as_str()),
+            Some(ValueSource::CommandLine) | Some(ValueSource::EnvVariable)
+        ) {
+            continue;
+        }
+        options.insert(name.clone(), value);
+        sources.insert(name, source);
+    }
+
+    let mut args = args;
+    let position = command_position(&args, name);
+    args.splice(
+        position..position,
+        option_args(options, env)?.into_iter().map(OsString::from),
+    );
+    Ok(ConfigFileArgs { args, sources })
+}
+
+/// the options that the command has, the options of the other commands are skipped
+pub(crate) fn command_options(name: &str, options: Options) -> anyhow::Result<Options> {
+    let arguments = Arguments::command();
+    let command = arguments
+        .find_subcommand(name)
+        .ok_or(anyhow!("unknown command: {}", name))?;
+    let mut command_options = Options::new();
+    for (option, value) in options {
+        if command_argument(&arguments, command, &option)?.is_some() {
+            command_options.insert(option, value);
+        }
+    }
+    Ok(command_options)
+}
+
+/// the argument of the command by its long name, None when only another command has it
+fn command_argument<'a>(
+    arguments: &Command,
+    command: &'a Command,
+    name: &str,
+) -> anyhow::Result<Option<&'a Arg>> {
+    let is_named = |arg: &&Arg| arg.get_long() == Some(name);
+    if let Some(arg) = command.get_arguments().find(is_named) {
+        return Ok(Some(arg));
+    }
+    if arguments
+        .get_subcommands()
+        .any(|command| command.get_arguments().any(|arg| is_named(&arg)))
+    {
+        return Ok(None);
+    }
+    Err(anyhow!("unknown option: {}", name))
+}
+
+/// the position right after the command name, the global options before it take a value
+fn command_position(args: &[OsString], name: &str) -> usize {
+    let mut position = 1;
+    while position < args.len() {
+        let arg = args[position].to_string_lossy();
+        position += 1;
+        if arg == name {
+            break;
+        }
+        if GLOBAL_OPTIONS.contains(&arg.as_ref()) {
+            position += 1;
+        }
+    }
+    position.min(args.len())
+}
+
+/// configuration file commands
+#[derive(Subcommand, Debug, Clone)]
+pub enum ConfigCommand {
+    /// Print the effective value and the source of every option of the given command line,
+    /// e.g. config show train, the api keys are masked
+    Show {
+        /// The command and its arguments
+        #[clap(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+}
+
+/// Where the value of an option was taken from, by precedence
+#[derive(Debug, Clone, PartialEq)]
+pub enum OptionSource {
+    CommandLine,
+    Environment,
+    /// the selected profile of the configuration file
+    Profile(String),
+    /// the top level options of the configuration file
+    ConfigFile,
+    Default,
+}
+
+impl Display for OptionSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OptionSource::CommandLine => write!(f, "command line"),
+            OptionSource::Environment => write!(f, "environment"),
+            OptionSource::Profile(profile) => write!(f, "profile: {}", profile),
+            OptionSource::ConfigFile => write!(f, "configuration file"),
+            OptionSource::Default => write!(f, "default"),
+        }
+    }
+}
+
+/// The value of a single option and its source
+#[derive(Debug, Clone, PartialEq)]
+pub struct EffectiveOption {
+    pub name: String,
+    pub value: String,
+    pub source: OptionSource,
+}
+
+/// The options of a command that have a value after applying the command line,
+/// the environment variables, the configuration file and the defaults
+#[derive(Debug, Clone, Default)]
+pub struct EffectiveConfiguration {
+    pub options: Vec<EffectiveOption>,
+}
+
+impl EffectiveConfiguration {
+    /// the options of the command line of config show, the global options are taken from
+    /// the command line of the config command itself
+    pub(crate) fn new(
+        matches: &ArgMatches,
+        args: &[String],
+        env: Environment,
+    ) -> anyhow::Result<Self> {
+        let mut command_args = vec![OsString::from("face-recognition-trainer")];
+        for name in GLOBAL_OPTIONS {
+            if matches.value_source(&name[2..]) == Some(ValueSource::CommandLine) {
+                let value = matches.get_raw(&name[2..]).into_iter().flatten();
+                command_args.push(OsString::from(name));
+                command_args.extend(value.map(OsString::from));
+            }
+        }
+        command_args.extend(args.iter().map(OsString::from));
+        let file_args = apply_config_file(command_args, env)?;
+        // the missing options are shown as missing, instead of failing
+        let arguments = env.command().ignore_errors(true);
+        let command_matches = arguments
+            .clone()
+            .try_get_matches_from(file_args.args)
+            .map_err(|e| anyhow!("{}", e))?;
+
+        let mut options = effective_options(&arguments, matches, &file_args.sources);
+        if let Some((name, command_matches)) = command_matches.subcommand() {
+            let command = arguments
+                .find_subcommand(name)
+                .ok_or(anyhow!("unknown command: {}", name))?;
+            options.extend(effective_options(
+                command,
+                command_matches,
+                &file_args.sources,
+            ));
+        }
+        Ok(EffectiveConfiguration { options })
+    }
+}
+
+/// the options of the command that have a value, with their source
+fn effective_options(
+    command: &Command,
+    matches: &ArgMatches,
+    sources: &BTreeMap<String, OptionSource>,
+) -> Vec<EffectiveOption> {
+    command
+        .get_arguments()
+        .filter_map(|arg| {
+            let name = arg.get_long()?;
+            let id = arg.get_id().as_str();
+            let source = match matches.value_source(id)? {
+                ValueSource::CommandLine => sources
+                    .get(name)
+                    .cloned()
+                    .unwrap_or(OptionSource::CommandLine),
+                ValueSource::EnvVariable => OptionSource::Environment,
+                _ => OptionSource::Default,
+            };
+            let value = matches
+                .get_raw(id)?
+                .map(|value| value.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(",");
+            Some(EffectiveOption {
+                name: name.to_string(),
+                value,
+                source,
+            })
+        })
+        .collect()
+}
+
+impl Display for EffectiveConfiguration {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let width = self
+            .options
+            .iter()
+            .map(|option| option.name.len())
+            .max()
+            .unwrap_or_default();
+        for option in self.options.iter() {
+            let value = if option.name.ends_with("api-key") {
+                mask(&option.value)
+            } else {
+                option.value.clone()
+            };
+            writeln!(
+                f,
+                "{:width$} = {} ({})",
+                option.name,
+                value,
+                option.source,
+                width = width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// hide the secret, only its last 4 characters are kept so different keys can be told apart
+fn mask(secret: &str) -> String {
+    let chars: Vec<char> = secret.chars().collect();
+    if chars.len() <= 8 {
+        return "****".to_string();
+    }
+    let suffix: String = chars[chars.len() - 4..].iter().collect();
+    format!("****{}", suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{ClientType, Configuration};
+
+    const CONFIG_FILE: &str = r#"
+client-type = "compreface"
+compreface-url = "http://compreface:8000"
+concurrency = 2
+local-limit = 3
+
+[profiles.famous-unknowns]
+compreface-api-key = "${CONFIG_FILE_TEST_KEY}"
+override-trained-name = "unknown"
+concurrency = 4
+dry-run = true
+"#;
+
+    #[test]
+    fn test_precedence_of_the_configuration_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE_NAME);
+        std::fs::write(&path, CONFIG_FILE).unwrap();
+        let parse = |args: Vec<&str>| {
+            Configuration::try_get_with_env(
+                args,
+                Environment::Variables(&[(
+                    "CONFIG_FILE_TEST_KEY",
+                    "0f3cb33e-fbdf-4fb7-aea5-f293deeb339d",
+                )]),
+            )
+            .unwrap()
+        };
+        let path = path.to_str().unwrap();
+
+        let args = [
+            "sync",
+            "--dataset-path",
+            "faces",
+            "--override-trained-name",
+            "famous",
+        ];
+        let global_args = [
+            "face-recognition-trainer",
+            "--config",
+            path,
+            "--profile",
+            "famous-unknowns",
+        ];
+        let config = parse(global_args.iter().chain(&args).copied().collect());
+        assert_eq!(config.client_type, Some(ClientType::Compreface));
+        let compreface = config.compreface.as_ref().unwrap();
+        assert_eq!(compreface.compreface_url, "http://compreface:8000");
+        assert_eq!(compreface.api_key(), "0f3cb33e-fbdf-4fb7-aea5-f293deeb339d");
+        assert_eq!(config.concurrency, 4);
+        assert_eq!(config.override_trained_name.as_deref(), Some("famous"));
+        assert_eq!(config.local.as_ref().unwrap().local_limit, Some(3));
+        assert!(config.dry_run);
+
+        // the options of the other commands are skipped
+        let config = parse(
+            global_args
+                .iter()
+                .chain(&["train", "--dataset-path", "faces"])
+                .copied()
+                .collect(),
+        );
+        assert!(!config.dry_run);
+
+        let config = parse(
+            global_args
+                .iter()
+                .chain(&["config", "show"])
+                .chain(&args)
+                .copied()
+                .collect(),
+        );
+        let shown = config.effective.to_string();
+        let line = |name: &str| {
+            shown
+                .lines()
+                .find(|line| line.split_whitespace().next() == Some(name))
+                .unwrap()
+                .split(" = ")
+                .nth(1)
+                .unwrap()
+                .to_string()
+        };
+        assert!(!shown.contains("0f3cb33e"));
+        assert_eq!(
+            line("compreface-api-key"),
+            "****339d (profile: famous-unknowns)"
+        );
+        assert_eq!(
+            line("compreface-url"),
+            "http://compreface:8000 (configuration file)"
+        );
+        assert_eq!(line("concurrency"), "4 (profile: famous-unknowns)");
+        assert_eq!(line("override-trained-name"), "famous (command line)");
+        assert_eq!(line("local-limit"), "3 (configuration file)");
+        assert_eq!(line("max-request-size"), "10485760 (default)");
+
+        // without a profile only the top level options apply
+        let config = parse(vec![
+            "face-recognition-trainer",
+            "--config",
+            path,
+            "sync",
+            "--compreface-api-key",
+            "key",
+            "--dataset-path",
+            "faces",
+        ]);
+        assert_eq!(config.concurrency, 2);
+        assert!(config.override_trained_name.is_none());
+        assert!(!config.dry_run);
+    }
+
+    #[test]
+    fn test_invalid_configuration_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE_NAME);
+        std::fs::write(&path, "compreface-key = \"key\"\n[profiles.empty]\n").unwrap();
+        let path = path.to_str().unwrap();
+        let error = |args: &[&str]| {
+            let mut all_args = vec!["face-recognition-trainer"];
+            all_args.extend(args);
+            all_args.extend(["train", "--dataset-path", "faces"]);
+            Configuration::try_get_with_env(all_args, Environment::Variables(&[]))
+                .err()
+                .unwrap()
+        };
+
+        assert!(error(&["--config", path]).contains("unknown option: compreface-key"));
+        assert!(error(&["--config", path, "--profile", "missing"])
+            .contains("the profile: missing is not defined"));
+        let missing = dir.path().join("missing.toml");
+        assert!(error(&["--config", missing.to_str().unwrap()])
+            .contains("failed to read the configuration file"));
+    }
+
+    #[test]
+    fn test_mask() {
+        assert_eq!(mask("short"), "****");
+        assert_eq!(mask("0f3cb33e-fbdf"), "****fbdf");
+    }
+}
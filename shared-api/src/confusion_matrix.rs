@@ -0,0 +1,106 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Formatter},
+};
+
+use serde::Serialize;
+
+/// The predicted name when no subject was recognized
+pub const NO_PREDICTION: &str = "<none>";
+
+/// ConfusionMatrix counts the top predicted subject per expected subject
+/// It is kept sparse, as most of the pairs of a large dataset are never predicted
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct ConfusionMatrix {
+    counts: BTreeMap<String, BTreeMap<String, usize>>,
+}
+
+impl ConfusionMatrix {
+    /// count a single prediction, None when no subject was recognized
+    pub fn record(&mut self, expected: &str, predicted: Option<&str>) {
+        *self
+            .counts
+            .entry(expected.to_string())
+            .or_default()
+            .entry(predicted.unwrap_or(NO_PREDICTION).to_string())
+            .or_default() += 1;
+    }
+
+    pub fn add(&mut self, other: &ConfusionMatrix) {
+        for (expected, predictions) in other.counts.iter() {
+            let row = self.counts.entry(expected.clone()).or_default();
+            for (predicted, count) in predictions {
+                *row.entry(predicted.clone()).or_default() += count;
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// iterate all the (expected, predicted, count) cells
+    pub fn cells(&self) -> impl Iterator<Item = (&str, &str, usize)> {
+        self.counts.iter().flat_map(|(expected, predictions)| {
+            predictions
+                .iter()
+                .map(move |(predicted, count)| (expected.as_str(), predicted.as_str(), *count))
+        })
+    }
+}
+
+impl Display for ConfusionMatrix {
+    /// one line per expected subject, with its predictions ordered by count
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Confusion matrix (expected -> predicted: count)")?;
+        for (expected, predictions) in self.counts.iter() {
+            let mut predictions: Vec<(&String, &usize)> = predictions.iter().collect();
+            predictions.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+            let predictions: Vec<String> = predictions
+                .iter()
+                .map(|(predicted, count)| format!("{}: {}", predicted, count))
+                .collect();
+            writeln!(f, "{} -> {}", expected, predictions.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_add() {
+        let mut matrix = ConfusionMatrix::default();
+        matrix.record("alice", Some("alice"));
+        matrix.record("alice", Some("bob"));
+        let mut other = ConfusionMatrix::default();
+        other.record("alice", Some("alice"));
+        other.record("bob", None);
+        matrix.add(&other);
+
+        let cells: Vec<(&str, &str, usize)> = matrix.cells().collect();
+        assert_eq!(
+            cells,
+            vec![
+                ("alice", "alice", 2),
+                ("alice", "bob", 1),
+                ("bob", NO_PREDICTION, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_display_orders_predictions_by_count() {
+        let mut matrix = ConfusionMatrix::default();
+        matrix.record("alice", Some("bob"));
+        matrix.record("alice", Some("alice"));
+        matrix.record("alice", Some("alice"));
+        assert_eq!(
+            matrix.to_string(),
+            "Confusion matrix (expected -> predicted: count)\nalice -> alice: 2, bob: 1\n"
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use std::fmt::{Display, Formatter};
+
+use serde::Deserialize;
+
+/// ApiError enum to classify the failures of a single api call
+/// Transient errors are worth another attempt, all the others are permanent
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiError {
+    /// The service could not be reached, for example connection refused or reset
+    Connect(String),
+    /// The request or the response did not complete in time
+    Timeout(String),
+    /// The service answered with a non success status code
+    Status { status: u16, message: String },
+    /// The service answered with a body that could not be parsed
+    InvalidResponse(String),
+    /// The request could not be built or sent, for example an invalid url
+    Request(String),
+    /// The local file could not be read
+    Io(String),
+}
+
+impl ApiError {
+    /// true when the same request may succeed on another attempt:
+    /// connection errors, timeouts, server errors (5xx) and too many requests (429)
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ApiError::Connect(_) | ApiError::Timeout(_) => true,
+            ApiError::Status { status, .. } => *status >= 500 || *status == 429,
+            ApiError::InvalidResponse(_) | ApiError::Request(_) | ApiError::Io(_) => false,
+        }
+    }
+
+    /// true when the service never received the request, so even a request that is not idempotent,
+    /// like adding a face, can be sent again without storing it twice
+    /// After a timeout or a server error the service may have stored it already
+    pub fn is_unsent(&self) -> bool {
+        matches!(self, ApiError::Connect(_))
+    }
+
+    /// build the status error from the response body,
+    /// the body is used as is when it is not the expected json error
+    pub fn from_status(status: u16, body: &str) -> Self {
+        let message = match serde_json::from_str::<ErrorBody>(body) {
+            Ok(error) => error.message,
+            Err(_) => body.to_string(),
+        };
+        ApiError::Status { status, message }
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::Connect(e) => write!(f, "connection error: {}", e),
+            ApiError::Timeout(e) => write!(f, "timeout: {}", e),
+            ApiError::Status { status, message } => {
+                write!(f, "status code: {}, message: {}", status, message)
+            }
+            ApiError::InvalidResponse(e) => write!(f, "invalid response: {}", e),
+            ApiError::Request(e) => write!(f, "request error: {}", e),
+            ApiError::Io(e) => write!(f, "io error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            ApiError::Timeout(e.to_string())
+        } else if e.is_connect() {
+            ApiError::Connect(e.to_string())
+        } else if e.is_decode() {
+            ApiError::InvalidResponse(e.to_string())
+        } else if let Some(status) = e.status() {
+            ApiError::Status {
+                status: status.as_u16(),
+                message: e.to_string(),
+            }
+        } else {
+            // the request or its body could not be built, retrying it would fail the same way
+            ApiError::Request(e.to_string())
+        }
+    }
+}
+
+impl From<std::io::Error> for ApiError {
+    fn from(e: std::io::Error) -> Self {
+        ApiError::Io(e.to_string())
+    }
+}
+
+/// return the response when its status is success, otherwise the status error with the response body
+pub async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, ApiError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(ApiError::from_status(status.as_u16(), &body))
+}
+
+/// the error body that the services return, for example: {"message": "No face is found", "code": 28}
+#[derive(Deserialize)]
+struct ErrorBody {
+    message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_transient_for_server_errors_and_too_many_requests() {
+        assert!(ApiError::from_status(500, "").is_transient());
+        assert!(ApiError::from_status(503, "").is_transient());
+        assert!(ApiError::from_status(429, "").is_transient());
+        assert!(!ApiError::from_status(400, "").is_transient());
+        assert!(!ApiError::from_status(404, "").is_transient());
+    }
+
+    #[test]
+    fn test_request_builder_error_is_permanent() {
+        let error = reqwest::Client::new()
+            .get("not a url")
+            .build()
+            .err()
+            .unwrap();
+        let error = ApiError::from(error);
+        assert!(matches!(error, ApiError::Request(_)));
+        assert!(!error.is_transient());
+    }
+
+    #[test]
+    fn test_from_status_with_json_body() {
+        assert_eq!(
+            ApiError::from_status(
+                400,
+                r#"{"message":"No face is found in the given image","code":28}"#
+            ),
+            ApiError::Status {
+                status: 400,
+                message: "No face is found in the given image".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_from_status_with_non_json_body() {
+        assert_eq!(
+            ApiError::from_status(502, "<html>Bad Gateway</html>"),
+            ApiError::Status {
+                status: 502,
+                message: "<html>Bad Gateway</html>".to_string()
+            }
+        );
+    }
+}
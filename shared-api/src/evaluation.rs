@@ -0,0 +1,312 @@
+use std::{
+    collections::HashSet,
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{top_subject, ReportFormat, Subject};
+
+// evaluation options
+#[derive(Debug, clap::Parser, Clone)]
+#[clap(name = "evaluation-options")]
+pub struct EvaluationOptions {
+    /// The folder names of faces that are not trained, their images are counted as negatives
+    /// The default value is unknown
+    #[clap(
+        long,
+        env = "UNKNOWN_SUBJECTS",
+        default_value = "unknown",
+        value_delimiter = ','
+    )]
+    pub unknown_subjects: Vec<String>,
+
+    /// The step between two thresholds of the sweep, between 0 and 1
+    /// The default value is 0.01, which evaluate 101 thresholds
+    #[clap(long, env = "THRESHOLD_STEP", default_value = "0.01")]
+    pub threshold_step: f64,
+
+    /// Optional path to save the evaluation curve
+    #[clap(long, env = "EVALUATION_OUTPUT")]
+    pub evaluation_output: Option<PathBuf>,
+
+    /// The format of the evaluation curve: json, csv or jsonl
+    /// The default value is json
+    #[clap(long, env = "EVALUATION_FORMAT", default_value = "json")]
+    pub evaluation_format: ReportFormat,
+}
+
+impl Default for EvaluationOptions {
+    fn default() -> Self {
+        EvaluationOptions {
+            unknown_subjects: vec!["unknown".to_string()],
+            threshold_step: 0.01,
+            evaluation_output: None,
+            evaluation_format: ReportFormat::Json,
+        }
+    }
+}
+
+/// The raw recognition of a single file, kept to evaluate any threshold after the run
+#[derive(Serialize, Debug, Clone)]
+pub struct RecognitionSample {
+    pub path: PathBuf,
+    /// the folder name or the override name
+    pub expected: String,
+    /// all the recognized subjects, regardless of their similarity
+    pub subjects: Vec<Subject>,
+}
+
+/// The metrics of a single threshold, a prediction is the top subject with similarity at or above it
+/// A known face is a true positive when its prediction is the expected subject, otherwise a false negative
+/// Any prediction of another subject is a false positive, an unknown face without a prediction is a true negative
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CurvePoint {
+    pub threshold: f64,
+    pub true_positives: usize,
+    pub false_positives: usize,
+    pub true_negatives: usize,
+    pub false_negatives: usize,
+    /// true positive rate, the same as the recall
+    pub tpr: f64,
+    /// the rate of unknown faces that were accepted as a known subject
+    pub fpr: f64,
+    pub precision: f64,
+    pub recall: f64,
+    pub f1: f64,
+}
+
+/// Evaluation holds the threshold sweep and the recommended operating threshold
+#[derive(Serialize, Debug)]
+pub struct Evaluation {
+    pub positives: usize,
+    pub negatives: usize,
+    /// the threshold with the best F1, the highest one on a tie
+    pub recommended: Option<CurvePoint>,
+    pub curve: Vec<CurvePoint>,
+}
+
+/// the thresholds from 0 by the given step, the strictest threshold 1 is always the last one
+fn thresholds(step: f64) -> Vec<f64> {
+    // the epsilon keeps a whole number of steps from being rounded down, e.g. 1 / 0.1
+    let steps = (1.0 / step + 1e-9).floor() as usize;
+    let mut thresholds: Vec<f64> = (0..=steps).map(|i| (i as f64 * step).min(1.0)).collect();
+    if thresholds.last().is_some_and(|last| *last < 1.0 - 1e-9) {
+        thresholds.push(1.0);
+    }
+    thresholds
+}
+
+impl Evaluation {
+    /// sweep the thresholds from 0 to 1 over the samples
+    pub fn new(samples: &[RecognitionSample], options: &EvaluationOptions) -> Self {
+        let unknown: HashSet<&str> = options
+            .unknown_subjects
+            .iter()
+            .map(|s| s.as_str())
+            .collect();
+        let negatives = samples
+            .iter()
+            .filter(|s| unknown.contains(s.expected.as_str()))
+            .count();
+        let curve: Vec<CurvePoint> = thresholds(options.threshold_step)
+            .into_iter()
+            .map(|threshold| curve_point(samples, &unknown, threshold))
+            .collect();
+        let recommended = curve
+            .iter()
+            .filter(|p| p.true_positives > 0)
+            .fold(None, |best: Option<&CurvePoint>, p| match best {
+                Some(best) if best.f1 > p.f1 => Some(best),
+                _ => Some(p),
+            })
+            .cloned();
+        Evaluation {
+            positives: samples.len() - negatives,
+            negatives,
+            recommended,
+            curve,
+        }
+    }
+
+    fn to_csv(&self) -> anyhow::Result<Vec<u8>> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for point in self.curve.iter() {
+            writer.serialize(point)?;
+        }
+        Ok(writer.into_inner()?)
+    }
+
+    fn to_jsonl(&self) -> anyhow::Result<Vec<u8>> {
+        let mut content = Vec::new();
+        for point in self.curve.iter() {
+            serde_json::to_writer(&mut content, point)?;
+            content.push(b'\n');
+        }
+        Ok(content)
+    }
+}
+
+impl Display for Evaluation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Evaluated {} known and {} unknown faces, ",
+            self.positives, self.negatives
+        )?;
+        match self.recommended {
+            Some(ref p) => write!(
+                f,
+                "recommended threshold: {:.2} (F1: {:.4}, precision: {:.4}, recall: {:.4}, FPR: {:.4})",
+                p.threshold, p.f1, p.precision, p.recall, p.fpr
+            ),
+            None => write!(f, "no threshold recognized any known face"),
+        }
+    }
+}
+
+fn curve_point(
+    samples: &[RecognitionSample],
+    unknown: &HashSet<&str>,
+    threshold: f64,
+) -> CurvePoint {
+    let (mut tp, mut fp, mut tn, mut fn_) = (0, 0, 0, 0);
+    for sample in samples {
+        let prediction = top_subject(&sample.subjects).filter(|s| s.similarity >= threshold);
+        let is_unknown = unknown.contains(sample.expected.as_str());
+        match prediction {
+            Some(s) if !is_unknown && s.subject == sample.expected => tp += 1,
+            Some(_) => {
+                fp += 1;
+                if !is_unknown {
+                    fn_ += 1;
+                }
+            }
+            None if is_unknown => tn += 1,
+            None => fn_ += 1,
+        }
+    }
+    let precision = ratio(tp, tp + fp);
+    let recall = ratio(tp, tp + fn_);
+    let f1 = if precision + recall > 0.0 {
+        2.0 * precision * recall / (precision + recall)
+    } else {
+        0.0
+    };
+    let unknown_accepted = samples
+        .iter()
+        .filter(|s| unknown.contains(s.expected.as_str()))
+        .count()
+        - tn;
+    CurvePoint {
+        threshold,
+        true_positives: tp,
+        false_positives: fp,
+        true_negatives: tn,
+        false_negatives: fn_,
+        tpr: recall,
+        fpr: ratio(unknown_accepted, unknown_accepted + tn),
+        precision,
+        recall,
+        f1,
+    }
+}
+
+fn ratio(numerator: usize, denominator: usize) -> f64 {
+    if denominator == 0 {
+        return 0.0;
+    }
+    numerator as f64 / denominator as f64
+}
+
+/// write the evaluation to the given path, json keeps the recommendation, csv and jsonl keep only the curve
+pub async fn write_evaluation(
+    evaluation: &Evaluation,
+    path: &Path,
+    format: ReportFormat,
+) -> anyhow::Result<()> {
+    let content = match format {
+        ReportFormat::Json => serde_json::to_vec_pretty(evaluation)?,
+        ReportFormat::Csv => evaluation.to_csv()?,
+        ReportFormat::Jsonl => evaluation.to_jsonl()?,
+    };
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(path, content).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(expected: &str, subjects: &[(&str, f64)]) -> RecognitionSample {
+        RecognitionSample {
+            path: PathBuf::from(format!("/dataset/{}/1.jpg", expected)),
+            expected: expected.to_string(),
+            subjects: subjects
+                .iter()
+                .map(|(subject, similarity)| Subject {
+                    subject: subject.to_string(),
+                    similarity: *similarity,
+                })
+                .collect(),
+        }
+    }
+
+    fn options() -> EvaluationOptions {
+        EvaluationOptions {
+            unknown_subjects: vec!["unknown".to_string()],
+            threshold_step: 0.1,
+            evaluation_output: None,
+            evaluation_format: ReportFormat::Json,
+        }
+    }
+
+    fn samples() -> Vec<RecognitionSample> {
+        vec![
+            sample("alice", &[("alice", 0.95)]),
+            sample("alice", &[("bob", 0.65), ("alice", 0.6)]),
+            sample("bob", &[("bob", 0.85)]),
+            sample("unknown", &[("alice", 0.72)]),
+            sample("unknown", &[]),
+        ]
+    }
+
+    #[test]
+    fn test_curve_point_counts() {
+        let evaluation = Evaluation::new(&samples(), &options());
+        assert_eq!(evaluation.positives, 3);
+        assert_eq!(evaluation.negatives, 2);
+        assert_eq!(evaluation.curve.len(), 11);
+
+        let point = &evaluation.curve[5];
+        assert_eq!(point.true_positives, 2);
+        assert_eq!(point.false_positives, 2);
+        assert_eq!(point.true_negatives, 1);
+        assert_eq!(point.false_negatives, 1);
+        assert_eq!(point.fpr, 0.5);
+    }
+
+    #[test]
+    fn test_thresholds_end_at_one() {
+        assert_eq!(thresholds(0.1).len(), 11);
+        assert_eq!(thresholds(0.5), vec![0.0, 0.5, 1.0]);
+        let thresholds = thresholds(0.3);
+        assert_eq!(thresholds.len(), 5);
+        assert!((thresholds[3] - 0.9).abs() < 1e-9);
+        assert_eq!(thresholds[4], 1.0);
+    }
+
+    #[test]
+    fn test_recommend_the_best_f1_threshold() {
+        let evaluation = Evaluation::new(&samples(), &options());
+        let recommended = evaluation.recommended.unwrap();
+        // above 0.72 the unknown face is rejected, and the known faces above 0.85 are still accepted
+        assert!((recommended.threshold - 0.8).abs() < 1e-9);
+        assert_eq!(recommended.true_positives, 2);
+        assert_eq!(recommended.false_positives, 0);
+    }
+}
@@ -0,0 +1,696 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, Sender};
+use tracing::info;
+
+use crate::{
+    config_file::{command_options, Environment},
+    run_evaluate, run_recognize, run_train, write_report, BackendRegistry, ClientMode,
+    Configuration, CurvePoint, Evaluation, FaceProcessingResult, ProgressReporter, ReportFormat,
+    SubjectSummary,
+};
+
+/// experiment command options
+#[derive(Debug, Clone, clap::Args)]
+pub struct ExperimentOptions {
+    /// The experiment file with the variants to compare, toml or yaml by its extension
+    pub file: PathBuf,
+
+    /// Optional path to save the comparison of the variants
+    #[clap(long)]
+    pub output: Option<PathBuf>,
+
+    /// The format of the comparison: json, csv or jsonl
+    #[clap(long, default_value = "json")]
+    pub format: ReportFormat,
+}
+
+/// The value of a command line option of the experiment or the configuration file
+/// A true flag is passed without a value, a false flag is not passed at all
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum OptionValue {
+    Flag(bool),
+    Integer(i64),
+    Float(f64),
+    Text(String),
+}
+
+/// The command line options by their long name without the dashes, e.g. compreface-api-key
+/// A text may refer to environment variables as ${NAME}, so the api keys can stay out of the file
+pub type Options = BTreeMap<String, OptionValue>;
+
+/// A dataset of a variant, trained or tested with its own options
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ExperimentDataset {
+    pub dataset_path: String,
+    /// the name of all the faces of the dataset, e.g. unknown
+    #[serde(default)]
+    pub override_trained_name: Option<String>,
+    /// the mode of the test set, recognize or evaluate, the training sets are always trained
+    #[serde(default)]
+    pub mode: Option<ClientMode>,
+    /// the options of the dataset, the options that its command does not have are skipped
+    #[serde(default)]
+    pub options: Options,
+}
+
+/// A single variant of the experiment, its training sets are trained in order before its test set
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ExperimentVariant {
+    pub name: String,
+    /// the options of the variant, e.g. its api key
+    #[serde(default)]
+    pub options: Options,
+    /// empty when the service of the variant was already trained
+    #[serde(default)]
+    pub train: Vec<ExperimentDataset>,
+    /// recognized after the training, or evaluated when its mode is evaluate
+    pub test: ExperimentDataset,
+}
+
+/// Experiment file lists the variants to compare
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Experiment {
+    /// the options of every variant, e.g. the client type and the service url
+    #[serde(default)]
+    pub options: Options,
+    pub variants: Vec<ExperimentVariant>,
+}
+
+/// The parsing of the command line arguments of a dataset into its configuration
+type Parse = fn(Vec<String>) -> Result<Configuration, String>;
+
+/// The configurations of a single variant, one per training set and one of the test set
+#[derive(Debug)]
+pub struct VariantPlan {
+    pub name: String,
+    pub train: Vec<Configuration>,
+    pub test: Configuration,
+}
+
+impl Experiment {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("failed to read experiment: {}: {}", path.display(), e))?;
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+        let experiment: Experiment = match extension.as_ref() {
+            "toml" => toml::from_str(&content)?,
+            "yaml" | "yml" => serde_yaml::from_str(&content)?,
+            _ => {
+                return Err(anyhow!(
+                    "the experiment: {} must be a toml or yaml file",
+                    path.display()
+                ))
+            }
+        };
+        if experiment.variants.is_empty() {
+            return Err(anyhow!("the experiment has no variants"));
+        }
+        let mut names = BTreeSet::new();
+        for variant in experiment.variants.iter() {
+            if !names.insert(variant.name.as_str()) {
+                return Err(anyhow!("the variant name: {} is not unique", variant.name));
+            }
+        }
+        Ok(experiment)
+    }
+
+    /// the configurations of every variant, they are all validated before anything runs
+    pub fn plan(&self) -> anyhow::Result<Vec<VariantPlan>> {
+        self.plan_with(Configuration::try_get_from, Environment::Process)
+    }
+
+    /// the configurations of every variant, parsed from their command line arguments by the given function,
+    /// the options are expanded with the variables of the given environment
+    fn plan_with(&self, parse: Parse, env: Environment) -> anyhow::Result<Vec<VariantPlan>> {
+        self.variants
+            .iter()
+            .map(|variant| {
+                let train = variant
+                    .train
+                    .iter()
+                    .map(|dataset| match dataset.mode {
+                        None | Some(ClientMode::Train) => {
+                            self.configuration(variant, dataset, ClientMode::Train, parse, env)
+                        }
+                        Some(mode) => Err(anyhow!(
+                            "variant: {}: the training sets are trained, not: {:?}",
+                            variant.name,
+                            mode
+                        )),
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                let test = match variant.test.mode.unwrap_or(ClientMode::Recognize) {
+                    mode @ (ClientMode::Recognize | ClientMode::Evaluate) => {
+                        self.configuration(variant, &variant.test, mode, parse, env)?
+                    }
+                    mode => {
+                        return Err(anyhow!(
+                            "variant: {}: the test set is recognized or evaluated, not: {:?}",
+                            variant.name,
+                            mode
+                        ))
+                    }
+                };
+                Ok(VariantPlan {
+                    name: variant.name.clone(),
+                    train,
+                    test,
+                })
+            })
+            .collect()
+    }
+
+    /// the configuration of a dataset of the variant, the options of the dataset override the options
+    /// of the variant, that override the options of the experiment
+    /// The command of the dataset is its mode, the options of the other commands are skipped
+    fn configuration(
+        &self,
+        variant: &ExperimentVariant,
+        dataset: &ExperimentDataset,
+        mode: ClientMode,
+        parse: Parse,
+        env: Environment,
+    ) -> anyhow::Result<Configuration> {
+        let mut options = self.options.clone();
+        options.extend(variant.options.clone());
+        options.extend(dataset.options.clone());
+        options.insert(
+            "dataset-path".into(),
+            OptionValue::Text(dataset.dataset_path.clone()),
+        );
+        if let Some(ref name) = dataset.override_trained_name {
+            options.insert(
+                "override-trained-name".into(),
+                OptionValue::Text(name.clone()),
+            );
+        }
+
+        let command = mode
+            .to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default();
+        let options = command_options(&command, options)
+            .map_err(|e| anyhow!("variant: {}: {}", variant.name, e))?;
+        let mut args = vec!["face-recognition-trainer".to_string(), command];
+        args.extend(option_args(options, env)?);
+        parse(args).map_err(|e| anyhow!("variant: {}: {}", variant.name, e.trim()))
+    }
+}
+
+/// the command line arguments of the options
+pub(crate) fn option_args(options: Options, env: Environment) -> anyhow::Result<Vec<String>> {
+    let mut args = Vec::new();
+    for (name, value) in options {
+        match value {
+            OptionValue::Flag(true) => args.push(format!("--{}", name)),
+            OptionValue::Flag(false) => {}
+            OptionValue::Integer(value) => args.extend([format!("--{}", name), value.to_string()]),
+            OptionValue::Float(value) => args.extend([format!("--{}", name), value.to_string()]),
+            OptionValue::Text(value) => {
+                args.extend([format!("--{}", name), expand_env(&value, env)?])
+            }
+        }
+    }
+    Ok(args)
+}
+
+/// replace every ${NAME} of the text with the value of the environment variable
+fn expand_env(text: &str, env: Environment) -> anyhow::Result<String> {
+    let mut expanded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..]
+            .find('}')
+            .ok_or(anyhow!("unclosed environment variable in: {}", text))?;
+        let name = &rest[start + 2..start + end];
+        let value = env
+            .var(name)
+            .ok_or_else(|| anyhow!("the environment variable: {} is not set", name))?;
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&value);
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// The metrics of a single variant
+#[derive(Serialize, Debug, Clone)]
+pub struct VariantComparison {
+    pub name: String,
+    /// the counters of all the training sets
+    pub trained: SubjectSummary,
+    /// the counters of the test set
+    pub tested: SubjectSummary,
+    /// the counters of the test set per subject
+    pub subjects: BTreeMap<String, SubjectSummary>,
+    /// the threshold with the best F1, only when the test set is evaluated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recommended: Option<CurvePoint>,
+}
+
+fn summary(result: &FaceProcessingResult) -> SubjectSummary {
+    SubjectSummary {
+        total_count: result.total_count,
+        success_count: result.success_count,
+        failure_count: result.failure_count,
+        missed_count: result.missed_count,
+        rejected_count: result.rejected_count,
+    }
+}
+
+/// Comparison holds the metrics of every variant, in the order of the experiment file
+#[derive(Serialize, Debug, Default)]
+pub struct Comparison {
+    pub variants: Vec<VariantComparison>,
+}
+
+/// A single record of the csv and jsonl comparison
+#[derive(Serialize)]
+struct ComparisonRecord<'a> {
+    variant: &'a str,
+    /// trained, tested or subject
+    record: &'a str,
+    subject: &'a str,
+    #[serde(flatten)]
+    summary: &'a SubjectSummary,
+    success_rate: f64,
+}
+
+impl Comparison {
+    fn records(&self) -> Vec<ComparisonRecord<'_>> {
+        let mut records = Vec::new();
+        for variant in self.variants.iter() {
+            let totals = [
+                ("trained", "", &variant.trained),
+                ("tested", "", &variant.tested),
+            ];
+            let subjects = variant
+                .subjects
+                .iter()
+                .map(|(subject, summary)| ("subject", subject.as_str(), summary));
+            for (record, subject, summary) in totals.into_iter().chain(subjects) {
+                records.push(ComparisonRecord {
+                    variant: &variant.name,
+                    record,
+                    subject,
+                    summary,
+                    success_rate: summary.success_rate(),
+                });
+            }
+        }
+        records
+    }
+
+    fn to_csv(&self) -> anyhow::Result<Vec<u8>> {
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(Vec::new());
+        writer.write_record([
+            "variant",
+            "record",
+            "subject",
+            "total_count",
+            "success_count",
+            "failure_count",
+            "missed_count",
+            "rejected_count",
+            "success_rate",
+        ])?;
+        for record in self.records() {
+            writer.write_record([
+                record.variant.to_string(),
+                record.record.to_string(),
+                record.subject.to_string(),
+                record.summary.total_count.to_string(),
+                record.summary.success_count.to_string(),
+                record.summary.failure_count.to_string(),
+                record.summary.missed_count.to_string(),
+                record.summary.rejected_count.to_string(),
+                format!("{:.2}", record.success_rate),
+            ])?;
+        }
+        Ok(writer.into_inner()?)
+    }
+
+    fn to_jsonl(&self) -> anyhow::Result<Vec<u8>> {
+        let mut content = Vec::new();
+        for record in self.records() {
+            serde_json::to_writer(&mut content, &record)?;
+            content.push(b'\n');
+        }
+        Ok(content)
+    }
+}
+
+/// a single metric of the test set counters
+type Metric = fn(&SubjectSummary) -> String;
+
+/// a side by side table, a column per variant
+impl Display for Comparison {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut rows: Vec<(String, Vec<String>)> = vec![
+            (
+                "variant".into(),
+                self.variants.iter().map(|v| v.name.clone()).collect(),
+            ),
+            (
+                "trained".into(),
+                self.variants
+                    .iter()
+                    .map(|v| format!("{}/{}", v.trained.success_count, v.trained.total_count))
+                    .collect(),
+            ),
+        ];
+        let metrics: [(&str, Metric); 5] = [
+            ("tested", |s| s.total_count.to_string()),
+            ("success", |s| s.success_count.to_string()),
+            ("failure", |s| s.failure_count.to_string()),
+            ("missed", |s| s.missed_count.to_string()),
+            ("success rate", |s| format!("{:.2}%", s.success_rate())),
+        ];
+        for (label, metric) in metrics {
+            rows.push((
+                label.into(),
+                self.variants.iter().map(|v| metric(&v.tested)).collect(),
+            ));
+        }
+        if self.variants.iter().any(|v| v.recommended.is_some()) {
+            rows.push((
+                "recommended threshold".into(),
+                self.variants
+                    .iter()
+                    .map(|v| match v.recommended {
+                        Some(ref point) => format!("{:.2} (f1 {:.3})", point.threshold, point.f1),
+                        None => "-".into(),
+                    })
+                    .collect(),
+            ));
+        }
+        let subjects: BTreeSet<&String> = self
+            .variants
+            .iter()
+            .flat_map(|v| v.subjects.keys())
+            .collect();
+        for subject in subjects {
+            rows.push((
+                subject.clone(),
+                self.variants
+                    .iter()
+                    .map(|v| match v.subjects.get(subject) {
+                        Some(summary) => format!("{:.2}%", summary.success_rate()),
+                        None => "-".into(),
+                    })
+                    .collect(),
+            ));
+        }
+
+        let label_width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+        let widths: Vec<usize> = (0..self.variants.len())
+            .map(|column| {
+                rows.iter()
+                    .map(|(_, cells)| cells[column].len())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        for (label, cells) in rows.iter() {
+            write!(f, "{:<width$}", label, width = label_width)?;
+            for (cell, width) in cells.iter().zip(widths.iter()) {
+                write!(f, "  {:>width$}", cell, width = width)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// a progress channel whose updates are dropped, the experiment only reports each step
+fn progress() -> Sender<ProgressReporter> {
+    let (tx, mut rx) = mpsc::channel(2);
+    tokio::spawn(async move { while rx.recv().await.is_some() {} });
+    tx
+}
+
+/// run every variant of the experiment in sequence, each step starts when the previous one returned,
+/// so the test set is recognized only after all the training sets of the variant were sent
+pub async fn run_experiment(
+    options: &ExperimentOptions,
+    backend_registry: &BackendRegistry,
+) -> anyhow::Result<Comparison> {
+    let experiment = Experiment::load(&options.file)?;
+    let plans = experiment.plan()?;
+    let mut comparison = Comparison::default();
+    for plan in plans {
+        let mut trained = FaceProcessingResult::with_context(plan.name.clone());
+        for config in plan.train.iter() {
+            info!(
+                "variant: {}, training: {}",
+                plan.name,
+                config.dataset_path()
+            );
+            let backend = backend_registry.create(config)?;
+            trained.add(run_train(config, backend, progress()).await?);
+        }
+
+        let config = &plan.test;
+        info!("variant: {}, testing: {}", plan.name, config.dataset_path());
+        let backend = backend_registry.create(config)?;
+        let tested = match config.client_mode {
+            ClientMode::Evaluate => run_evaluate(config, backend, progress()).await?,
+            _ => run_recognize(config, backend, progress()).await?,
+        };
+        if let Some(ref report_path) = config.report {
+            write_report(&tested, report_path, config.report_format).await?;
+        }
+        let recommended = match config.client_mode {
+            ClientMode::Evaluate => {
+                Evaluation::new(&tested.samples, &config.evaluation).recommended
+            }
+            _ => None,
+        };
+        comparison.variants.push(VariantComparison {
+            name: plan.name,
+            trained: summary(&trained),
+            tested: summary(&tested),
+            subjects: tested.subjects.clone(),
+            recommended,
+        });
+    }
+    Ok(comparison)
+}
+
+/// write the comparison to the given path, in the given format
+pub async fn write_comparison(
+    comparison: &Comparison,
+    path: &Path,
+    format: ReportFormat,
+) -> anyhow::Result<()> {
+    let content = match format {
+        ReportFormat::Json => serde_json::to_vec_pretty(comparison)?,
+        ReportFormat::Csv => comparison.to_csv()?,
+        ReportFormat::Jsonl => comparison.to_jsonl()?,
+    };
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(path, content).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPERIMENT: &str = r#"
+[options]
+client-type = "mock"
+mock-fixture = "fixture.json"
+concurrency = 2
+
+[[variants]]
+name = "originals"
+train = [
+    { dataset-path = "known" },
+    { dataset-path = "famous", override-trained-name = "unknown" },
+]
+test = { dataset-path = "test" }
+
+[[variants]]
+name = "augmented"
+options = { mock-fixture = "${EXPERIMENT_TEST_FIXTURE}", resume = false, augment-on-train = "flip" }
+train = [{ dataset-path = "known" }]
+test = { dataset-path = "test", mode = "evaluate", options = { threshold-step = 0.05 } }
+"#;
+
+    fn experiment(content: &str, extension: &str) -> anyhow::Result<Experiment> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(format!("experiment.{}", extension));
+        std::fs::write(&path, content).unwrap();
+        Experiment::load(&path)
+    }
+
+    #[test]
+    fn test_plan_merges_the_options() {
+        let env = Environment::Variables(&[("EXPERIMENT_TEST_FIXTURE", "augmented.json")]);
+        let plans = experiment(EXPERIMENT, "toml")
+            .unwrap()
+            .plan_with(Configuration::try_get_without_config_file, env)
+            .unwrap();
+        assert_eq!(plans.len(), 2);
+
+        let originals = &plans[0];
+        assert_eq!(originals.train.len(), 2);
+        assert_eq!(originals.train[0].client_mode, ClientMode::Train);
+        assert_eq!(originals.train[0].concurrency, 2);
+        assert_eq!(originals.train[1].dataset_path(), "famous");
+        assert_eq!(
+            originals.train[1].override_trained_name.as_deref(),
+            Some("unknown")
+        );
+        assert_eq!(originals.test.client_mode, ClientMode::Recognize);
+
+        let augmented = &plans[1];
+        assert_eq!(augmented.train[0].client_mode, ClientMode::Train);
+        assert!(augmented.train[0].augment_on_train.is_some());
+        assert!(!augmented.train[0].resume);
+        // the options of the train command are skipped by the evaluate command
+        assert_eq!(augmented.test.client_mode, ClientMode::Evaluate);
+        assert!(augmented.test.augment_on_train.is_none());
+        assert_eq!(augmented.test.evaluation.threshold_step, 0.05);
+        assert_eq!(
+            augmented.test.mock.as_ref().unwrap().mock_fixture,
+            Some(PathBuf::from("augmented.json"))
+        );
+    }
+
+    #[test]
+    fn test_load_yaml_and_reject_invalid_experiments() {
+        let yaml = r#"
+options:
+  client-type: mock
+  mock-fixture: fixture.json
+variants:
+  - name: baseline
+    test:
+      dataset-path: test
+"#;
+        let plans = experiment(yaml, "yaml")
+            .unwrap()
+            .plan_with(
+                Configuration::try_get_without_config_file,
+                Environment::Variables(&[]),
+            )
+            .unwrap();
+        assert!(plans[0].train.is_empty());
+
+        let duplicate = format!(
+            "{}\n[[variants]]\nname = \"originals\"\ntest = {{ dataset-path = \"test\" }}\n",
+            EXPERIMENT
+        );
+        let error = experiment(&duplicate, "toml").err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "the variant name: originals is not unique"
+        );
+        assert!(experiment(EXPERIMENT, "json").is_err());
+
+        let unknown_option = yaml.replace("mock-fixture", "mock-fixtures");
+        let error = experiment(&unknown_option, "yml")
+            .unwrap()
+            .plan_with(
+                Configuration::try_get_without_config_file,
+                Environment::Variables(&[]),
+            )
+            .err()
+            .unwrap();
+        assert!(error.to_string().starts_with("variant: baseline:"));
+
+        let sync_test = yaml.replace("dataset-path: test", "dataset-path: test\n      mode: sync");
+        let error = experiment(&sync_test, "yaml")
+            .unwrap()
+            .plan_with(
+                Configuration::try_get_without_config_file,
+                Environment::Variables(&[]),
+            )
+            .err()
+            .unwrap();
+        assert!(error
+            .to_string()
+            .starts_with("variant: baseline: the test set is recognized or evaluated"));
+    }
+
+    #[test]
+    fn test_expand_env() {
+        let env = Environment::Variables(&[("EXPERIMENT_TEST_KEY", "secret")]);
+        assert_eq!(
+            expand_env("key-${EXPERIMENT_TEST_KEY}-${EXPERIMENT_TEST_KEY}", env).unwrap(),
+            "key-secret-secret"
+        );
+        assert_eq!(expand_env("plain", env).unwrap(), "plain");
+        assert!(expand_env("${EXPERIMENT_TEST_MISSING}", env).is_err());
+        assert!(expand_env("${EXPERIMENT_TEST_KEY", env).is_err());
+    }
+
+    #[test]
+    fn test_comparison_side_by_side() {
+        let variant = |name: &str, success_count: usize| VariantComparison {
+            name: name.to_string(),
+            trained: SubjectSummary {
+                total_count: 4,
+                success_count: 4,
+                ..Default::default()
+            },
+            tested: SubjectSummary {
+                total_count: 4,
+                success_count,
+                failure_count: 4 - success_count,
+                ..Default::default()
+            },
+            subjects: BTreeMap::from([(
+                "alice".to_string(),
+                SubjectSummary {
+                    total_count: 4,
+                    success_count,
+                    failure_count: 4 - success_count,
+                    ..Default::default()
+                },
+            )]),
+            recommended: None,
+        };
+        let comparison = Comparison {
+            variants: vec![variant("originals", 2), variant("augmented", 3)],
+        };
+        assert_eq!(
+            comparison.to_string(),
+            "\
+variant       originals  augmented
+trained             4/4        4/4
+tested                4          4
+success               2          3
+failure               2          1
+missed                0          0
+success rate     50.00%     75.00%
+alice            50.00%     75.00%
+"
+        );
+        let csv = String::from_utf8(comparison.to_csv().unwrap()).unwrap();
+        assert_eq!(csv.lines().count(), 7);
+        assert_eq!(
+            csv.lines().nth(2).unwrap(),
+            "originals,tested,,4,2,2,0,0,50.00"
+        );
+    }
+}
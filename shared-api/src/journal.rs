@@ -0,0 +1,315 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{self, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+};
+use tracing::{debug, info};
+
+use crate::{utils, ClientMode, FaceProcessingResult, FailureFace};
+
+/// The journal file name, under the output directory
+pub const JOURNAL_FILE_NAME: &str = "journal.jsonl";
+
+/// The outcome of a single processed file
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    Success,
+    Failure,
+    Missed,
+    /// rejected by the pre-flight detection
+    Rejected,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Failure => "failure",
+            Outcome::Missed => "missed",
+            Outcome::Rejected => "rejected",
+        }
+    }
+}
+
+/// A single line of the journal
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JournalEntry {
+    pub path: PathBuf,
+    /// sha256 of the file content, so a renamed or moved file is still recognized
+    pub hash: String,
+    pub mode: ClientMode,
+    /// the backend identity, see Configuration::backend_id
+    pub backend: String,
+    pub outcome: Outcome,
+    /// the dataset image of an augmented variant, the variant itself is not on the disk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<PathBuf>,
+    /// the transforms of an augmented variant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transforms: Option<String>,
+}
+
+/// Journal records the outcome of each processed file in a jsonl file,
+/// so an interrupted run can be resumed without processing the same files again
+pub struct Journal {
+    mode: ClientMode,
+    backend: String,
+    /// the hashes of the files that were already processed successfully by the same mode and backend
+    completed: HashSet<String>,
+    /// the hashes that were calculated on this run, to avoid reading the same file twice
+    hashes: Mutex<HashMap<PathBuf, String>>,
+    writer: Mutex<fs::File>,
+}
+
+impl Journal {
+    /// open the journal file for append, when resume is set the previous successful entries are loaded
+    pub async fn open(
+        path: &Path,
+        mode: ClientMode,
+        backend: String,
+        resume: bool,
+    ) -> anyhow::Result<Self> {
+        let mut completed = HashSet::new();
+        if resume && fs::try_exists(path).await? {
+            let content = fs::read_to_string(path).await?;
+            for line in content.lines().filter(|line| !line.trim().is_empty()) {
+                let entry: JournalEntry = serde_json::from_str(line)?;
+                // the variants are created again with their source, they are never skipped on their own
+                if entry.mode == mode
+                    && entry.backend == backend
+                    && entry.outcome == Outcome::Success
+                    && entry.source.is_none()
+                {
+                    completed.insert(entry.hash);
+                }
+            }
+            info!(
+                "resume from journal: {}, {} files were already processed",
+                path.display(),
+                completed.len()
+            );
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let writer = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        Ok(Journal {
+            mode,
+            backend,
+            completed,
+            hashes: Mutex::new(HashMap::new()),
+            writer: Mutex::new(writer),
+        })
+    }
+
+    /// true when the file content was already processed successfully on a previous run
+    pub async fn is_completed(&self, path: &Path) -> anyhow::Result<bool> {
+        if self.completed.is_empty() {
+            return Ok(false);
+        }
+        let hash = self.hash(path).await?;
+        Ok(self.completed.contains(&hash))
+    }
+
+    /// append the outcome of each of the given files, based on the result of processing them
+    pub async fn record(
+        &self,
+        files: &[PathBuf],
+        result: &FaceProcessingResult,
+    ) -> anyhow::Result<()> {
+        let failures: HashSet<&Path> = result
+            .failure_faces
+            .iter()
+            .map(|face| match face {
+                FailureFace::Train(path) => path.as_path(),
+                FailureFace::Recognize(m) => m.path.as_path(),
+            })
+            .collect();
+        let missed: HashSet<&Path> = result
+            .missed_faces
+            .iter()
+            .map(|m| m.path.as_path())
+            .collect();
+
+        let rejected: HashSet<&Path> = result
+            .rejected_faces
+            .iter()
+            .map(|r| r.path.as_path())
+            .collect();
+
+        let mut lines = String::new();
+        for path in files {
+            let outcome = if failures.contains(path.as_path()) {
+                Outcome::Failure
+            } else if missed.contains(path.as_path()) {
+                Outcome::Missed
+            } else if rejected.contains(path.as_path()) {
+                Outcome::Rejected
+            } else {
+                Outcome::Success
+            };
+            let entry = JournalEntry {
+                path: path.clone(),
+                hash: self.hash(path).await?,
+                mode: self.mode,
+                backend: self.backend.clone(),
+                outcome,
+                source: None,
+                transforms: None,
+            };
+            lines.push_str(&serde_json::to_string(&entry)?);
+            lines.push('\n');
+        }
+        for augmented in result.augmented_faces.iter() {
+            let entry = JournalEntry {
+                path: augmented.path.clone(),
+                hash: augmented.hash.clone(),
+                mode: self.mode,
+                backend: self.backend.clone(),
+                outcome: augmented.outcome,
+                source: Some(augmented.source.clone()),
+                transforms: Some(augmented.transforms.clone()),
+            };
+            lines.push_str(&serde_json::to_string(&entry)?);
+            lines.push('\n');
+        }
+
+        let mut writer = self.writer.lock().await;
+        writer.write_all(lines.as_bytes()).await?;
+        writer.flush().await?;
+        debug!(
+            "journal recorded {} files and {} augmented variants",
+            files.len(),
+            result.augmented_faces.len()
+        );
+        Ok(())
+    }
+
+    /// the content hash of the file, calculated once per run
+    async fn hash(&self, path: &Path) -> anyhow::Result<String> {
+        if let Some(hash) = self.hashes.lock().await.get(path) {
+            return Ok(hash.clone());
+        }
+        let hash = utils::file_hash(path).await?;
+        self.hashes
+            .lock()
+            .await
+            .insert(path.to_path_buf(), hash.clone());
+        Ok(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::AugmentedFace;
+
+    #[tokio::test]
+    async fn test_resume_skips_only_successful_files_of_the_same_backend() {
+        let dir = tempdir().unwrap();
+        let journal_path = dir.path().join("output").join(JOURNAL_FILE_NAME);
+        let success = dir.path().join("success.jpg");
+        let failure = dir.path().join("failure.jpg");
+        std::fs::write(&success, "success").unwrap();
+        std::fs::write(&failure, "failure").unwrap();
+
+        let journal = Journal::open(&journal_path, ClientMode::Train, "a".into(), false)
+            .await
+            .unwrap();
+        let mut result = FaceProcessingResult::with_context("test".into());
+        result
+            .failure_faces
+            .push(FailureFace::Train(failure.clone()));
+        journal
+            .record(&[success.clone(), failure.clone()], &result)
+            .await
+            .unwrap();
+
+        let resumed = Journal::open(&journal_path, ClientMode::Train, "a".into(), true)
+            .await
+            .unwrap();
+        assert!(resumed.is_completed(&success).await.unwrap());
+        assert!(!resumed.is_completed(&failure).await.unwrap());
+
+        let other_backend = Journal::open(&journal_path, ClientMode::Train, "b".into(), true)
+            .await
+            .unwrap();
+        assert!(!other_backend.is_completed(&success).await.unwrap());
+
+        let other_mode = Journal::open(&journal_path, ClientMode::Recognize, "a".into(), true)
+            .await
+            .unwrap();
+        assert!(!other_mode.is_completed(&success).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_augmented_variants_are_tagged_but_never_completed() {
+        let dir = tempdir().unwrap();
+        let journal_path = dir.path().join(JOURNAL_FILE_NAME);
+        let source = dir.path().join("alice/1.jpg");
+        let variant = dir.path().join("variant.jpg");
+        std::fs::write(&variant, "variant").unwrap();
+
+        let journal = Journal::open(&journal_path, ClientMode::Train, "a".into(), false)
+            .await
+            .unwrap();
+        let mut result = FaceProcessingResult::with_context("test".into());
+        result.augmented_faces.push(AugmentedFace {
+            path: dir.path().join("alice/1_aug_1.jpg"),
+            source: source.clone(),
+            transforms: "flip".to_string(),
+            hash: utils::content_hash(b"variant"),
+            outcome: Outcome::Success,
+            reason: None,
+        });
+        journal.record(&[], &result).await.unwrap();
+
+        let content = std::fs::read_to_string(&journal_path).unwrap();
+        let entry: JournalEntry = serde_json::from_str(content.trim()).unwrap();
+        assert_eq!(entry.source, Some(source));
+        assert_eq!(entry.transforms.as_deref(), Some("flip"));
+
+        let resumed = Journal::open(&journal_path, ClientMode::Train, "a".into(), true)
+            .await
+            .unwrap();
+        assert!(!resumed.is_completed(&variant).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_without_resume_nothing_is_completed() {
+        let dir = tempdir().unwrap();
+        let journal_path = dir.path().join(JOURNAL_FILE_NAME);
+        let success = dir.path().join("success.jpg");
+        std::fs::write(&success, "success").unwrap();
+
+        let journal = Journal::open(&journal_path, ClientMode::Train, "a".into(), false)
+            .await
+            .unwrap();
+        journal
+            .record(
+                std::slice::from_ref(&success),
+                &FaceProcessingResult::with_context("test".into()),
+            )
+            .await
+            .unwrap();
+
+        let journal = Journal::open(&journal_path, ClientMode::Train, "a".into(), false)
+            .await
+            .unwrap();
+        assert!(!journal.is_completed(&success).await.unwrap());
+    }
+}
@@ -1,21 +1,89 @@
 use async_trait::async_trait;
-use clap::{Parser, ValueEnum};
+use clap::{ArgMatches, CommandFactory, FromArgMatches, ValueEnum};
 use compreface_contracts::CompreFaceConfig;
+use config_file::{apply_config_file, Environment};
 use double_take_contracts::DoubleTakeConfig;
-use futures::StreamExt;
-use serde::Deserialize;
+use futures::{future, stream, StreamExt, TryStreamExt};
+use local_contracts::LocalConfig;
+use mock_contracts::MockConfig;
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     fmt::{Display, Formatter},
     future::Future,
     path::PathBuf,
+    sync::Arc,
 };
 use stream_utils::{BufferUntilCondition, RecursiveFileStream};
 use tokio::{fs, sync::mpsc::Sender};
+use tracing::debug;
 
+mod arguments;
+mod augmentation;
+mod backend;
+mod collection;
+mod config_file;
+mod confusion_matrix;
+mod error;
+mod evaluation;
+mod experiment;
+mod journal;
+mod preflight;
+mod recognition;
+mod report;
+mod retry;
+mod sync;
+mod training_manifest;
+mod upload;
 pub mod utils;
+mod verification;
+pub use arguments::{
+    Arguments, BackendArgs, Command, DatasetArgs, EvaluateArgs, OutputArgs, RecognizeArgs,
+    SyncArgs, TrainArgs, VerifyArgs, DEFAULT_AUGMENT_VARIANTS, DEFAULT_CONCURRENCY,
+    DEFAULT_MAX_REQUEST_SIZE,
+};
+pub use augmentation::{
+    augment_in_memory, run_augment, tag_variants, AugmentOptions, AugmentResult, AugmentationSpec,
+    AugmentedFace, AugmentedImage, Augmenter, Transform, DEFAULT_TRANSFORMS,
+};
+pub use backend::{
+    run_evaluate, run_recognize, run_train, BackendFactory, BackendRegistry, CollectionFactory,
+    FaceBackend, VerifierFactory,
+};
+pub use collection::{FaceCollection, FacesCommand, FacesPage, StoredFace, SubjectsCommand};
+pub use config_file::{
+    ConfigCommand, ConfigFile, EffectiveConfiguration, EffectiveOption, OptionSource,
+    CONFIG_FILE_NAME,
+};
+pub use confusion_matrix::{ConfusionMatrix, NO_PREDICTION};
+pub use error::{check_status, ApiError};
+pub use evaluation::{
+    write_evaluation, CurvePoint, Evaluation, EvaluationOptions, RecognitionSample,
+};
+pub use experiment::{
+    run_experiment, write_comparison, Comparison, Experiment, ExperimentDataset, ExperimentOptions,
+    ExperimentVariant, OptionValue, Options, VariantComparison, VariantPlan,
+};
+pub use journal::{Journal, JournalEntry, Outcome, JOURNAL_FILE_NAME};
+pub use preflight::{classify_faces, quarantine, PreflightAction, RejectReason, RejectedFace};
+pub use recognition::{MultiFacePolicy, RecognitionCriteria};
+pub use report::{write_report, Report, ReportFormat};
+pub use retry::RetryPolicy;
+pub use sync::{run_sync, LocalFace, Manifest, ManifestEntry, SyncPlan, MANIFEST_FILE_NAME};
+pub use training_manifest::{
+    read_training_manifest, rollback, RollbackResult, TrainingManifest, TrainingManifestEntry,
+    TRAINING_MANIFEST_FILE_NAME,
+};
+pub use upload::{file_part, Upload};
+pub use verification::{
+    generate_pairs, parse_pairs, run_verify, write_verification, VerificationOptions,
+    VerificationPair, VerificationResult, VerificationSample, Verifier,
+};
 /// Trainer trait
 /// This trait is used to train a model with a set of images and a name
 /// The function send instructions to the destination to train the model, but the train itself is async
+/// Each request is reported with ProgressReporter::Trained as soon as the service answered,
+/// so the stored files are recorded even when the rest of the batch is interrupted
 #[async_trait]
 pub trait Trainer {
     async fn send_to_train(
@@ -24,6 +92,20 @@ pub trait Trainer {
         files: Vec<PathBuf>,
         progress_reporter_tx: Sender<ProgressReporter>,
     ) -> anyhow::Result<FaceProcessingResult>;
+
+    /// train the in-memory augmented variants of the images, the same way as the files
+    /// The paths of the result are the virtual paths of the variants
+    async fn send_augmented_to_train(
+        &self,
+        name: &str,
+        images: Vec<AugmentedImage>,
+        progress_reporter_tx: Sender<ProgressReporter>,
+    ) -> anyhow::Result<FaceProcessingResult>;
+
+    /// persist what the trainer keeps in memory, it is called once at the end of a run
+    async fn flush(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -87,7 +169,32 @@ pub struct FaceProcessingResult {
     pub missed_count: usize,
 
     /// The list of faces that were not recognized because of an error
-    pub missed_faces: Vec<PathBuf>,
+    pub missed_faces: Vec<MissedFace>,
+
+    /// The number of images that were rejected by the pre-flight detection, and were not sent to train
+    pub rejected_count: usize,
+
+    /// The list of the rejected images with the reason
+    pub rejected_faces: Vec<RejectedFace>,
+
+    /// The remote image id of each trained file, when the service reports it
+    pub trained_faces: Vec<TrainedFace>,
+
+    /// The number of trained files whose image id could not be read from the response,
+    /// they are missing from the trained faces so the rollback does not delete them
+    pub untracked_count: usize,
+
+    /// The counters per subject (the folder name or the override name)
+    pub subjects: BTreeMap<String, SubjectSummary>,
+
+    /// The top predicted subject per expected subject, only on recognition mode
+    pub confusion_matrix: ConfusionMatrix,
+
+    /// The raw subjects of each recognized file, only on evaluate mode
+    pub samples: Vec<RecognitionSample>,
+
+    /// The trained augmented variants with their transforms, only with augment on train
+    pub augmented_faces: Vec<AugmentedFace>,
 
     pub context: String,
 }
@@ -104,6 +211,76 @@ pub struct FaceWithMetadata {
     pub path: PathBuf,
     /// one or more subjects that were recognized for this face
     pub subjects: Vec<Subject>,
+    /// every detected face of the image, with its box and its own subjects
+    pub faces: Vec<RecognizedFace>,
+}
+
+/// A file that was trained, with the id of the image that the service stored
+#[derive(Debug, Clone, Serialize)]
+pub struct TrainedFace {
+    pub path: PathBuf,
+    pub image_id: String,
+    pub subject: String,
+}
+
+/// A face that was unable to be processed, with the reason of the error
+#[derive(Debug, Clone, Serialize)]
+pub struct MissedFace {
+    /// The path to the image file
+    pub path: PathBuf,
+    /// The error that caused the file to be missed
+    pub reason: String,
+}
+
+impl MissedFace {
+    pub fn new(path: PathBuf, reason: impl Display) -> Self {
+        MissedFace {
+            path,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+/// The counters of a single subject
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SubjectSummary {
+    pub total_count: usize,
+    pub success_count: usize,
+    pub failure_count: usize,
+    pub missed_count: usize,
+    pub rejected_count: usize,
+}
+
+impl SubjectSummary {
+    pub fn add(&mut self, other: &SubjectSummary) {
+        self.total_count += other.total_count;
+        self.success_count += other.success_count;
+        self.failure_count += other.failure_count;
+        self.missed_count += other.missed_count;
+        self.rejected_count += other.rejected_count;
+    }
+
+    /// the percent of the successful faces out of the total
+    pub fn success_rate(&self) -> f64 {
+        if self.total_count == 0 {
+            return 0.0;
+        }
+        self.success_count as f64 * 100.0 / self.total_count as f64
+    }
+}
+
+impl Display for SubjectSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.2}% Total: {}, Success: {}, Failure: {}, missing: {}",
+            self.success_rate(),
+            self.total_count,
+            self.success_count,
+            self.failure_count,
+            self.missed_count
+        )
+    }
 }
 
 impl Display for FaceProcessingResult {
@@ -128,10 +305,33 @@ impl FaceProcessingResult {
             failure_faces: Vec::new(),
             missed_count: 0,
             missed_faces: Vec::new(),
+            rejected_count: 0,
+            rejected_faces: Vec::new(),
+            trained_faces: Vec::new(),
+            untracked_count: 0,
+            subjects: BTreeMap::new(),
+            confusion_matrix: ConfusionMatrix::default(),
+            samples: Vec::new(),
+            augmented_faces: Vec::new(),
             context,
         }
     }
 
+    /// account all the counters of this result to the given subject
+    /// This is used on the result of a single api call, where all the files belong to the same subject
+    pub fn summarize_subject(&mut self, subject: &str) {
+        self.subjects
+            .entry(subject.to_string())
+            .or_default()
+            .add(&SubjectSummary {
+                total_count: self.total_count,
+                success_count: self.success_count,
+                failure_count: self.failure_count,
+                missed_count: self.missed_count,
+                rejected_count: self.rejected_count,
+            });
+    }
+
     /// add other RecognizeResult to this one
     /// This is used to merge the results of multiple RecognizeResult
     pub fn add(&mut self, other: FaceProcessingResult) {
@@ -141,6 +341,16 @@ impl FaceProcessingResult {
         self.failure_faces.extend(other.failure_faces);
         self.missed_count += other.missed_count;
         self.missed_faces.extend(other.missed_faces);
+        self.rejected_count += other.rejected_count;
+        self.rejected_faces.extend(other.rejected_faces);
+        self.trained_faces.extend(other.trained_faces);
+        self.untracked_count += other.untracked_count;
+        for (subject, summary) in other.subjects {
+            self.subjects.entry(subject).or_default().add(&summary);
+        }
+        self.confusion_matrix.add(&other.confusion_matrix);
+        self.samples.extend(other.samples);
+        self.augmented_faces.extend(other.augmented_faces);
     }
 }
 
@@ -153,8 +363,10 @@ impl ProcessProgress for FaceProcessingResult {
         self.success_count
     }
 }
-impl Clone for FaceProcessingResult {
-    fn clone(&self) -> Self {
+impl FaceProcessingResult {
+    /// a copy of the result without the raw subjects of each recognized file,
+    /// they are only read at the end of the run, so the progress messages leave them out
+    pub fn without_samples(&self) -> Self {
         FaceProcessingResult {
             total_count: self.total_count,
             success_count: self.success_count,
@@ -162,10 +374,27 @@ impl Clone for FaceProcessingResult {
             failure_faces: self.failure_faces.clone(),
             missed_count: self.missed_count,
             missed_faces: self.missed_faces.clone(),
+            rejected_count: self.rejected_count,
+            rejected_faces: self.rejected_faces.clone(),
+            trained_faces: self.trained_faces.clone(),
+            untracked_count: self.untracked_count,
+            subjects: self.subjects.clone(),
+            confusion_matrix: self.confusion_matrix.clone(),
+            samples: Vec::new(),
+            augmented_faces: self.augmented_faces.clone(),
             context: self.context.clone(),
         }
     }
 }
+
+impl Clone for FaceProcessingResult {
+    fn clone(&self) -> Self {
+        FaceProcessingResult {
+            samples: self.samples.clone(),
+            ..self.without_samples()
+        }
+    }
+}
 /// ProgressReporter enum to report the progress of the training or recognition operation
 pub enum ProgressReporter {
     /// Increase the progress fill by the given value
@@ -178,87 +407,432 @@ pub enum ProgressReporter {
     PartialStructedMessage(FaceProcessingResult),
     /// Send the accumulated result of the progress
     AccumulatedStructedMessage(FaceProcessingResult),
+    /// Send the files of a single train request with their result, as soon as the service answered
+    Trained(Vec<PathBuf>, FaceProcessingResult),
     /// Finish the progress with the given message
     FinishWithMessage(String),
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct Subject {
     pub subject: String,
     #[allow(unused)]
     pub similarity: f64,
 }
 
-#[derive(Debug, clap::Parser, Clone)]
-#[clap(name = "face-recognition-trainer")]
+/// The bounding box of a detected face, in pixels
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct FaceBox {
+    /// the detection probability, when the service reports it
+    pub probability: Option<f64>,
+    pub x_min: u32,
+    pub y_min: u32,
+    pub x_max: u32,
+    pub y_max: u32,
+}
+
+impl FaceBox {
+    pub fn area(&self) -> u64 {
+        self.x_max.saturating_sub(self.x_min) as u64 * self.y_max.saturating_sub(self.y_min) as u64
+    }
+}
+
+/// A single detected face of an image with the subjects that were recognized for it
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct RecognizedFace {
+    /// None when the service does not report the face location
+    pub r#box: Option<FaceBox>,
+    pub subjects: Vec<Subject>,
+}
+
+/// the subject with the highest similarity, None when there are no subjects
+pub fn top_subject(subjects: &[Subject]) -> Option<&Subject> {
+    subjects
+        .iter()
+        .max_by(|a, b| a.similarity.total_cmp(&b.similarity))
+}
+
+/// The configuration of a run, resolved from the arguments of its command
+/// The options that the command does not have keep their default value
+#[derive(Debug, Clone)]
 pub struct Configuration {
-    /// The client type to use, Compreface or DoubleTake
-    #[arg(long, value_enum)]
-    pub client_type: ClientType,
+    /// the configuration file with the options of every run and the named profiles
+    pub config: PathBuf,
+
+    /// the selected profile of the configuration file
+    pub profile: Option<String>,
+
+    /// the client type of the backend, None when the command does not use a backend
+    pub client_type: Option<ClientType>,
 
-    /// The client mode to run, train or recognize
-    /// The default value is train
-    #[clap(long, short, default_value = "train")]
+    /// the mode of the dataset commands, train for the other commands
     pub client_mode: ClientMode,
 
-    #[clap(flatten)]
     pub double_take: Option<DoubleTakeConfig>,
 
-    #[clap(flatten)]
     pub compreface: Option<CompreFaceConfig>,
 
-    /// The data set root folder with the face images
-    #[clap(long, env = "DATASET_PATH")]
-    pub dataset_path: String,
+    pub mock: Option<MockConfig>,
 
-    /// The maximum size of the request to send to the double-take service
-    /// The service will be called when the total size of the files content reaches this size
-    /// The default value is 10MB
-    #[clap(long, env = "MAX_REQUEST_SIZE", default_value = "10485760")]
+    pub local: Option<LocalConfig>,
+
+    /// the data set root folder, None when the command does not read the dataset
+    pub dataset_path: Option<String>,
+
+    /// the maximum size of the request to send to the service
     pub max_request_size: u64,
 
-    /// Optional name for all scanned faces
-    /// When set, it will ignore the folder name per image and use this name for all faces
-    #[clap(long, env = "OVERRIDE_TRAINED_NAME")]
+    /// the maximum number of requests to keep in flight at the same time
+    pub concurrency: usize,
+
+    /// optional name for all scanned faces, instead of the folder name
     pub override_trained_name: Option<String>,
 
-    /// error configuration options
-    #[clap(flatten)]
+    /// optional path to save a machine readable report of the run
+    pub report: Option<PathBuf>,
+
+    pub report_format: ReportFormat,
+
+    /// what to do with the images that the pre-flight detection rejected, only on train mode
+    pub preflight_action: PreflightAction,
+
+    /// only print the plan of the sync mode
+    pub dry_run: bool,
+
+    /// the manifest of the sync mode
+    pub manifest: Option<PathBuf>,
+
+    /// the id of this run, recorded with every trained face in the training manifest
+    pub run_id: Option<String>,
+
+    /// the training manifest of the train mode and the rollback command
+    pub training_manifest: PathBuf,
+
+    /// resume a previous run according to the journal, only on train and recognize modes
+    pub resume: bool,
+
+    /// the transforms of the in-memory variants of each trained image, only on train mode
+    pub augment_on_train: Option<AugmentationSpec>,
+
+    pub augment_variants: usize,
+
+    pub augment_seed: u64,
+
     pub error_configuration: ErrorConfiguration,
+
+    pub retry_policy: RetryPolicy,
+
+    /// the criteria of a successful recognition, only on recognize and evaluate modes
+    pub recognition_criteria: RecognitionCriteria,
+
+    /// evaluation options, only on evaluate mode
+    pub evaluation: EvaluationOptions,
+
+    /// verification options, only on verify mode
+    pub verification: VerificationOptions,
+
+    /// the command with its own arguments
+    pub command: Command,
+
+    /// the value and the source of every option of the config show command line
+    pub effective: EffectiveConfiguration,
 }
 
 impl Configuration {
     pub fn get() -> Result<Self, String> {
-        let config = Configuration::parse();
-        match config.client_type {
-            ClientType::Compreface => {
-                if config.compreface.is_none() {
-                    return Err(
-                        "--compreface-url & compreface-api-key are required when client_mode is CompreFace".into(),
-                    );
-                }
+        let file_args = apply_config_file(std::env::args_os().collect(), Environment::Process)
+            .map_err(|e| e.to_string())?;
+        let matches = Arguments::command().get_matches_from(file_args.args);
+        Self::from_matches(&matches, Environment::Process)
+    }
+
+    /// parse and validate the configuration of the given command line arguments,
+    /// the first argument is the program name, the environment variables and the configuration file still apply
+    pub fn try_get_from<I, T>(args: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        Self::try_get_with_env(args, Environment::Process)
+    }
+
+    /// parse and validate the configuration of the given command line arguments,
+    /// with the environment variables of the given environment
+    pub(crate) fn try_get_with_env<I, T>(args: I, env: Environment) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        let file_args = apply_config_file(args.into_iter().map(Into::into).collect(), env)
+            .map_err(|e| e.to_string())?;
+        let matches = env
+            .command()
+            .try_get_matches_from(file_args.args)
+            .map_err(|e| e.to_string())?;
+        Self::from_matches(&matches, env)
+    }
+
+    /// parse and validate the configuration of the given command line arguments without the configuration file,
+    /// so the result does not depend on the working directory nor on the selected profile
+    #[cfg(test)]
+    pub(crate) fn try_get_without_config_file<I, T>(args: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        let matches = Arguments::command()
+            .try_get_matches_from(args)
+            .map_err(|e| e.to_string())?;
+        Self::from_matches(&matches, Environment::Process)
+    }
+
+    fn from_matches(matches: &ArgMatches, env: Environment) -> Result<Self, String> {
+        let arguments = Arguments::from_arg_matches(matches).map_err(|e| e.to_string())?;
+        let mut config = Configuration::new(arguments);
+        if let Command::Config {
+            action: ConfigCommand::Show { ref args },
+        } = config.command
+        {
+            config.effective =
+                EffectiveConfiguration::new(matches, args, env).map_err(|e| e.to_string())?;
+        }
+        Self::validate(config)
+    }
+
+    /// resolve the configuration of the command, the command line parsing already validated
+    /// the required arguments of the command and of its client type
+    fn new(arguments: Arguments) -> Self {
+        let config = Configuration {
+            config: arguments.config,
+            profile: arguments.profile,
+            client_type: None,
+            client_mode: ClientMode::Train,
+            double_take: None,
+            compreface: None,
+            mock: None,
+            local: None,
+            dataset_path: None,
+            max_request_size: DEFAULT_MAX_REQUEST_SIZE,
+            concurrency: DEFAULT_CONCURRENCY,
+            override_trained_name: None,
+            report: None,
+            report_format: ReportFormat::Json,
+            preflight_action: PreflightAction::default(),
+            dry_run: false,
+            manifest: None,
+            run_id: None,
+            training_manifest: PathBuf::from(TRAINING_MANIFEST_FILE_NAME),
+            resume: false,
+            augment_on_train: None,
+            augment_variants: DEFAULT_AUGMENT_VARIANTS,
+            augment_seed: 0,
+            error_configuration: ErrorConfiguration::default(),
+            retry_policy: RetryPolicy::default(),
+            recognition_criteria: RecognitionCriteria::default(),
+            evaluation: EvaluationOptions::default(),
+            verification: VerificationOptions::default(),
+            command: arguments.command.clone(),
+            effective: EffectiveConfiguration::default(),
+        };
+        match arguments.command {
+            Command::Train(args) => Configuration {
+                preflight_action: args.preflight_action,
+                run_id: args.run_id,
+                training_manifest: args.training_manifest,
+                resume: args.resume,
+                augment_on_train: args.augment_on_train,
+                augment_variants: args.augment_variants,
+                augment_seed: args.augment_seed,
+                ..config
+                    .with_backend(args.backend)
+                    .with_dataset(args.dataset)
+                    .with_output(args.output)
+            },
+            Command::Recognize(args) => Configuration {
+                client_mode: ClientMode::Recognize,
+                resume: args.resume,
+                recognition_criteria: args.recognition_criteria,
+                ..config
+                    .with_backend(args.backend)
+                    .with_dataset(args.dataset)
+                    .with_output(args.output)
+            },
+            Command::Evaluate(args) => Configuration {
+                client_mode: ClientMode::Evaluate,
+                recognition_criteria: args.recognition_criteria,
+                evaluation: args.evaluation,
+                ..config
+                    .with_backend(args.backend)
+                    .with_dataset(args.dataset)
+                    .with_output(args.output)
+            },
+            Command::Sync(args) => Configuration {
+                client_mode: ClientMode::Sync,
+                dry_run: args.dry_run,
+                manifest: args.manifest,
+                ..config
+                    .with_backend(args.backend)
+                    .with_dataset(args.dataset)
+                    .with_output(args.output)
+            },
+            Command::Verify(args) => Configuration {
+                client_mode: ClientMode::Verify,
+                verification: args.verification,
+                ..config.with_backend(args.backend).with_dataset(args.dataset)
+            },
+            Command::Subjects { backend, .. } | Command::Faces { backend, .. } => {
+                config.with_backend(backend)
             }
-            ClientType::DoubleTake => {
-                if config.double_take.is_none() {
-                    return Err(
-                        "--doubletake-url is required when client_mode is DoubleTake".into(),
-                    );
-                }
+            Command::Rollback {
+                backend,
+                training_manifest,
+                ..
+            } => Configuration {
+                training_manifest,
+                ..config.with_backend(backend)
+            },
+            Command::Augment { dataset, .. } => config.with_dataset(dataset),
+            Command::Experiment(_) | Command::Config { .. } => config,
+        }
+    }
+
+    fn with_backend(mut self, backend: BackendArgs) -> Self {
+        self.client_type = Some(backend.client_type);
+        self.compreface = Some(backend.compreface);
+        self.double_take = Some(backend.double_take);
+        self.mock = Some(backend.mock);
+        self.local = Some(backend.local);
+        self.retry_policy = backend.retry_policy;
+        self
+    }
+
+    fn with_dataset(mut self, dataset: DatasetArgs) -> Self {
+        self.dataset_path = Some(dataset.dataset_path);
+        self.max_request_size = dataset.max_request_size;
+        self.concurrency = dataset.concurrency;
+        self.override_trained_name = dataset.override_trained_name;
+        self
+    }
+
+    fn with_output(mut self, output: OutputArgs) -> Self {
+        self.report = output.report;
+        self.report_format = output.report_format;
+        self.error_configuration = output.error_configuration;
+        self
+    }
+
+    /// the validations that depend on the values of the arguments
+    fn validate(mut config: Configuration) -> Result<Self, String> {
+        if let Command::Augment { ref options, .. } = config.command {
+            // the variants would be augmented again when the output is under the dataset
+            if options.output.starts_with(config.dataset_path()) {
+                return Err("the augment --output must not be under the --dataset-path".into());
             }
         }
+        if !(0.0..=1.0).contains(&config.recognition_criteria.min_similarity) {
+            return Err("--min-similarity must be between 0 and 1".into());
+        }
+        if config.client_mode == ClientMode::Evaluate
+            && !(config.evaluation.threshold_step > 0.0 && config.evaluation.threshold_step <= 1.0)
+        {
+            return Err("--threshold-step must be greater than 0 and up to 1".into());
+        }
+        if config.client_mode == ClientMode::Verify
+            && !(0.0..=1.0).contains(&config.verification.verification_threshold)
+        {
+            return Err("--verification-threshold must be between 0 and 1".into());
+        }
+        if config.run_id.is_none() {
+            config.run_id = Some(uuid::Uuid::new_v4().to_string());
+        }
         Ok(config)
     }
+
+    /// identify the backend service and credentials,
+    /// so journal entries of different services or api keys are not mixed
+    pub fn backend_id(&self) -> String {
+        let Some(client_type) = self.client_type else {
+            return "none".to_string();
+        };
+        match client_type {
+            ClientType::Compreface => match self.compreface {
+                Some(ref compreface) => format!(
+                    "compreface:{}:{}",
+                    compreface.compreface_url,
+                    &utils::text_hash(compreface.api_key())[..12]
+                ),
+                None => "compreface".to_string(),
+            },
+            ClientType::DoubleTake => match self.double_take {
+                Some(ref double_take) => format!("double-take:{}", double_take.doubletake_url),
+                None => "double-take".to_string(),
+            },
+            ClientType::Mock => match self.mock {
+                Some(MockConfig {
+                    mock_fixture: Some(ref mock_fixture),
+                }) => format!("mock:{}", mock_fixture.display()),
+                _ => "mock".to_string(),
+            },
+            ClientType::Local => {
+                let local = self.local.clone().unwrap_or_default();
+                let model = match local.local_model {
+                    Some(ref model) => model.display().to_string(),
+                    None => "none".to_string(),
+                };
+                format!("local:{}:{}", model, local.gallery_path().display())
+            }
+        }
+    }
+
+    /// the raw subjects of each recognized file are kept only to evaluate them
+    pub fn keep_samples(&self) -> bool {
+        self.client_mode == ClientMode::Evaluate
+    }
+
+    /// the dataset root folder, empty when it was not set
+    pub fn dataset_path(&self) -> &str {
+        self.dataset_path.as_deref().unwrap_or_default()
+    }
+
+    /// the manifest file path of the sync mode
+    pub fn manifest_path(&self) -> PathBuf {
+        match self.manifest {
+            Some(ref manifest) => manifest.clone(),
+            None => PathBuf::from(self.dataset_path()).join(MANIFEST_FILE_NAME),
+        }
+    }
+
+    /// the id of this run, empty when it was not generated
+    pub fn run_id(&self) -> &str {
+        self.run_id.as_deref().unwrap_or_default()
+    }
+
+    /// the journal file path, when the output directory is set
+    pub fn journal_path(&self) -> Option<PathBuf> {
+        self.error_configuration
+            .output_dir
+            .as_ref()
+            .map(|output_dir| PathBuf::from(output_dir).join(JOURNAL_FILE_NAME))
+    }
 }
-#[derive(ValueEnum, Clone, Debug)]
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum ClientType {
     Compreface,
     DoubleTake,
+    /// scripted outcomes from a fixture file, for development and tests without a service
+    Mock,
+    /// face embeddings computed on this machine, matched against an on-disk gallery
+    Local,
 }
 
-#[derive(ValueEnum, Clone, Debug)]
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum ClientMode {
     Train,
     Recognize,
+    Evaluate,
+    Sync,
+    Verify,
 }
 
 // error configuration options
@@ -276,7 +850,11 @@ pub struct ErrorConfiguration {
     #[clap(long, env = "ERROR_BEHAVIOR", default_value = "ignore")]
     pub error_behavior: ErrorBehavior,
 
-    #[clap(long, env = "POST_RECOGNIZE_STRATEGY", default_value = "MaxSimilarity")]
+    #[clap(
+        long,
+        env = "POST_RECOGNIZE_STRATEGY",
+        default_value = "max-similarity"
+    )]
     pub post_recognize_strategy: PostRecognizeStrategy,
 
     /// The threshold to use when the PostRecognizeStrategy is AboveThreshold
@@ -284,6 +862,17 @@ pub struct ErrorConfiguration {
     pub above_threshold: Option<f64>,
 }
 
+impl Default for ErrorConfiguration {
+    fn default() -> Self {
+        ErrorConfiguration {
+            output_dir: None,
+            error_behavior: ErrorBehavior::Ignore,
+            post_recognize_strategy: PostRecognizeStrategy::MaxSimilarity,
+            above_threshold: Some(0.95),
+        }
+    }
+}
+
 #[derive(ValueEnum, Clone, Debug, PartialEq, Copy)]
 pub enum ErrorBehavior {
     Copy,
@@ -317,8 +906,12 @@ pub enum PostRecognizeStrategy {
     AboveThreshold,
 }
 
+/// scan the dataset and call the api action for each batch of files of the same folder
+/// up to `concurrency` batches are kept in flight, across all the folders
+/// when a journal is given, the files it already completed are skipped
 pub async fn process_files<F, Fut>(
     config: &Configuration,
+    journal: Option<Arc<Journal>>,
     tx: Sender<ProgressReporter>,
     api_action: F,
 ) -> anyhow::Result<()>
@@ -328,68 +921,275 @@ where
 {
     tx.send(ProgressReporter::Message(format!(
         "Start processing directory: {}",
-        &config.dataset_path
+        config.dataset_path()
     )))
     .await?;
 
-    let files = RecursiveFileStream::new(&config.dataset_path);
-    let mut files_groups = BufferUntilCondition::new(files, |path| path.as_ref().unwrap().is_dir());
+    let files = RecursiveFileStream::new(config.dataset_path());
+    let files_groups = BufferUntilCondition::new(files, |path| path.as_ref().unwrap().is_dir());
 
-    while let Some(group) = files_groups.next().await {
-        let name = match config.override_trained_name {
-            Some(ref name) => name.to_string(),
-            None => utils::get_directory_name(&group)?,
-        };
+    // the batches are built from owned values, so the batching futures do not borrow the configuration
+    let override_trained_name = config.override_trained_name.clone();
+    let max_request_size = config.max_request_size;
+    files_groups
+        .then(|group| {
+            split_to_batches(
+                group,
+                override_trained_name.clone(),
+                max_request_size,
+                journal.clone(),
+                tx.clone(),
+            )
+        })
+        .map_ok(|batches| stream::iter(batches.into_iter().map(Ok)))
+        .try_flatten()
+        .map_ok(|(name, files)| api_action(name, files, tx.clone()))
+        .try_buffer_unordered(config.concurrency)
+        .try_for_each(|_| future::ok(()))
+        .await
+}
 
-        // increase the progress length by the number of files in the group, ignoring directories or errors
-        let files_count = group
-            .iter()
-            .filter(|path| path.is_ok() && path.as_ref().unwrap().is_file())
-            .count();
-        tx.send(ProgressReporter::IncreaseLength(files_count as u64))
-            .await?;
-        tx.send(ProgressReporter::Message(format!(
-            "processing directory: {}",
-            &name
-        )))
+/// split the files of a single folder into batches,
+/// each batch total files size is bounded by the max request size
+async fn split_to_batches(
+    group: Vec<Result<PathBuf, std::io::Error>>,
+    override_trained_name: Option<String>,
+    max_request_size: u64,
+    journal: Option<Arc<Journal>>,
+    tx: Sender<ProgressReporter>,
+) -> anyhow::Result<Vec<(String, Vec<PathBuf>)>> {
+    let name = match override_trained_name {
+        Some(name) => name,
+        None => utils::get_directory_name(&group)?,
+    };
+
+    // increase the progress length by the number of files in the group, ignoring directories or errors
+    let files_count = group
+        .iter()
+        .filter(|path| path.is_ok() && path.as_ref().unwrap().is_file())
+        .count();
+    tx.send(ProgressReporter::IncreaseLength(files_count as u64))
         .await?;
+    tx.send(ProgressReporter::Message(format!(
+        "processing directory: {}",
+        &name
+    )))
+    .await?;
 
-        let mut files_content: Vec<PathBuf> = Vec::new();
-        let mut total_size = 0;
-
-        for path in group.into_iter() {
-            let path_buf = path?;
-            if path_buf.is_dir() {
-                tx.send(ProgressReporter::Message(format!(
-                    "{}",
-                    path_buf.file_stem().unwrap().to_string_lossy()
-                )))
-                .await?;
-                continue;
-            }
+    let mut batches = Vec::new();
+    let mut files_content: Vec<PathBuf> = Vec::new();
+    let mut total_size = 0;
 
-            if !utils::is_image(&path_buf) {
-                continue;
-            }
+    for path in group.into_iter() {
+        let path_buf = path?;
+        if path_buf.is_dir() {
+            tx.send(ProgressReporter::Message(format!(
+                "{}",
+                path_buf.file_stem().unwrap().to_string_lossy()
+            )))
+            .await?;
+            continue;
+        }
 
-            let file_len = fs::metadata(path_buf.clone()).await?.len();
-            if total_size + file_len > config.max_request_size {
-                api_action(name.clone(), files_content.clone(), tx.clone()).await?;
-                files_content.clear();
-                total_size = 0;
-            }
+        if !utils::is_image(&path_buf) {
+            continue;
+        }
 
-            total_size += file_len;
-            files_content.push(path_buf);
+        if let Some(ref journal) = journal {
+            if journal.is_completed(&path_buf).await? {
+                debug!("skip completed file: {}", path_buf.display());
+                tx.send(ProgressReporter::Increase(1)).await?;
+                continue;
+            }
         }
 
-        if !files_content.is_empty() {
-            api_action(name, files_content, tx.clone()).await?;
+        let file_len = fs::metadata(path_buf.clone()).await?.len();
+        if total_size + file_len > max_request_size && !files_content.is_empty() {
+            batches.push((name.clone(), std::mem::take(&mut files_content)));
+            total_size = 0;
         }
+
+        total_size += file_len;
+        files_content.push(path_buf);
+    }
+
+    if !files_content.is_empty() {
+        batches.push((name, files_content));
     }
 
-    Ok(())
+    Ok(batches)
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use std::{collections::BTreeMap, fs, sync::Arc};
+
+    use tempfile::tempdir;
+    use tokio::sync::{mpsc, Mutex};
+
+    use super::*;
+
+    fn config(dataset_path: &str, concurrency: &str, max_request_size: &str) -> Configuration {
+        Configuration::try_get_without_config_file([
+            "face-recognition-trainer",
+            "train",
+            "--client-type",
+            "compreface",
+            "--compreface-api-key",
+            "key",
+            "--dataset-path",
+            dataset_path,
+            "--concurrency",
+            concurrency,
+            "--max-request-size",
+            max_request_size,
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_command_does_not_require_dataset_path() {
+        let config = Configuration::try_get_without_config_file([
+            "face-recognition-trainer",
+            "subjects",
+            "--client-type",
+            "compreface",
+            "--compreface-api-key",
+            "key",
+            "rename",
+            "alice",
+            "alice-smith",
+        ])
+        .unwrap();
+        assert!(config.dataset_path.is_none());
+        assert!(matches!(
+            config.command,
+            Command::Subjects {
+                action: SubjectsCommand::Rename { ref subject, ref new_name },
+                ..
+            } if subject == "alice" && new_name == "alice-smith"
+        ));
+    }
+
+    #[test]
+    fn test_commands_validate_their_arguments() {
+        let error = |args: &[&str]| {
+            Configuration::try_get_without_config_file(
+                ["face-recognition-trainer"].iter().chain(args),
+            )
+            .err()
+            .unwrap()
+        };
+        // the api key of the selected backend is required
+        assert!(error(&[
+            "train",
+            "--client-type",
+            "compreface",
+            "--dataset-path",
+            "faces"
+        ])
+        .contains("--compreface-api-key"));
+        // the local client has no embedding without its model
+        assert!(
+            error(&["train", "--client-type", "local", "--dataset-path", "faces"])
+                .contains("--local-model")
+        );
+        // the dataset runs require the dataset
+        assert!(error(&[
+            "train",
+            "--client-type",
+            "compreface",
+            "--compreface-api-key",
+            "key"
+        ])
+        .contains("--dataset-path"));
+        // the options of another command are rejected
+        assert!(error(&[
+            "recognize",
+            "--client-type",
+            "compreface",
+            "--compreface-api-key",
+            "key",
+            "--dataset-path",
+            "faces",
+            "--augment-on-train",
+            "flip",
+        ])
+        .contains("--augment-on-train"));
+        // quarantine needs the output directory
+        assert!(error(&[
+            "train",
+            "--client-type",
+            "compreface",
+            "--compreface-api-key",
+            "key",
+            "--dataset-path",
+            "faces",
+            "--preflight-action",
+            "quarantine",
+        ])
+        .contains("--output-dir"));
+    }
+
+    /// create a dataset with the given number of 10 bytes images per person
+    fn create_dataset(root: &std::path::Path, persons: &[(&str, usize)]) {
+        for (person, count) in persons {
+            let folder = root.join(person);
+            fs::create_dir(&folder).unwrap();
+            for i in 0..*count {
+                fs::write(folder.join(format!("{}.jpg", i)), [0u8; 10]).unwrap();
+            }
+        }
+    }
+
+    async fn collect_batches(config: &Configuration) -> BTreeMap<String, Vec<usize>> {
+        let (tx, mut rx) = mpsc::channel(2);
+        let drain = tokio::spawn(async move { while rx.recv().await.is_some() {} });
+        let batches = Arc::new(Mutex::new(BTreeMap::<String, Vec<usize>>::new()));
+        let cloned_batches = batches.clone();
+        process_files(config, None, tx, move |name, files, _tx| {
+            let batches = cloned_batches.clone();
+            async move {
+                batches
+                    .lock()
+                    .await
+                    .entry(name)
+                    .or_default()
+                    .push(files.len());
+                Ok(())
+            }
+        })
+        .await
+        .unwrap();
+        drain.await.unwrap();
+        let mut batches = batches.lock().await.clone();
+        batches.values_mut().for_each(|sizes| sizes.sort());
+        batches
+    }
+
+    #[tokio::test]
+    async fn test_process_files_concurrently_covers_all_folders() {
+        let dir = tempdir().unwrap();
+        create_dataset(dir.path(), &[("alice", 3), ("bob", 2), ("carol", 1)]);
+        let batches = collect_batches(&config(dir.path().to_str().unwrap(), "4", "1000")).await;
+        assert_eq!(
+            batches,
+            BTreeMap::from([
+                ("alice".to_string(), vec![3]),
+                ("bob".to_string(), vec![2]),
+                ("carol".to_string(), vec![1]),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_files_split_folder_by_max_request_size() {
+        let dir = tempdir().unwrap();
+        create_dataset(dir.path(), &[("alice", 5)]);
+        let batches = collect_batches(&config(dir.path().to_str().unwrap(), "2", "20")).await;
+        assert_eq!(
+            batches,
+            BTreeMap::from([("alice".to_string(), vec![1, 2, 2])])
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use serde::Serialize;
+use tracing::info;
+
+/// What to do with the images that the pre-flight detection rejected
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum PreflightAction {
+    /// keep the image in place and only report it
+    #[default]
+    Skip,
+    /// move the image under the output directory, so it is not trained again on the next run
+    Quarantine,
+}
+
+/// The reason of an image to be rejected before training
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RejectReason {
+    NoFace,
+    MultiFace,
+}
+
+impl Display for RejectReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RejectReason::NoFace => write!(f, "no-face"),
+            RejectReason::MultiFace => write!(f, "multi-face"),
+        }
+    }
+}
+
+/// An image that was rejected by the pre-flight detection, and was not sent to train
+#[derive(Debug, Clone, Serialize)]
+pub struct RejectedFace {
+    pub path: PathBuf,
+    pub reason: RejectReason,
+    /// the detection probability of each detected face
+    pub probabilities: Vec<f64>,
+}
+
+/// classify the detection probabilities of an image, None when the image has a single face
+pub fn classify_faces(probabilities: &[f64]) -> Option<RejectReason> {
+    match probabilities.len() {
+        0 => Some(RejectReason::NoFace),
+        1 => None,
+        _ => Some(RejectReason::MultiFace),
+    }
+}
+
+/// move the rejected images to {output_dir}/rejected_faces/{reason}/{person}/{file}
+pub async fn quarantine(output_dir: &Path, rejected_faces: &[RejectedFace]) -> anyhow::Result<()> {
+    for rejected in rejected_faces {
+        let person_folder = output_dir
+            .join("rejected_faces")
+            .join(rejected.reason.to_string())
+            .join(rejected.path.parent().unwrap().file_stem().unwrap());
+        tokio::fs::create_dir_all(&person_folder).await?;
+        let new_path = person_folder.join(rejected.path.file_name().unwrap());
+        tokio::fs::rename(&rejected.path, &new_path).await?;
+        info!(
+            "quarantined {} image: {} to: {}",
+            rejected.reason,
+            rejected.path.display(),
+            new_path.display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_classify_faces() {
+        assert_eq!(classify_faces(&[]), Some(RejectReason::NoFace));
+        assert_eq!(classify_faces(&[0.99]), None);
+        assert_eq!(classify_faces(&[0.99, 0.8]), Some(RejectReason::MultiFace));
+    }
+
+    #[tokio::test]
+    async fn test_quarantine_moves_by_reason_and_person() {
+        let dir = tempdir().unwrap();
+        let person = dir.path().join("dataset").join("alice");
+        std::fs::create_dir_all(&person).unwrap();
+        let image = person.join("1.jpg");
+        std::fs::write(&image, "image").unwrap();
+
+        let output_dir = dir.path().join("output");
+        quarantine(
+            &output_dir,
+            &[RejectedFace {
+                path: image.clone(),
+                reason: RejectReason::MultiFace,
+                probabilities: vec![0.99, 0.9],
+            }],
+        )
+        .await
+        .unwrap();
+
+        assert!(!image.exists());
+        assert!(output_dir
+            .join("rejected_faces")
+            .join("multi-face")
+            .join("alice")
+            .join("1.jpg")
+            .exists());
+    }
+}
@@ -0,0 +1,184 @@
+use clap::ValueEnum;
+
+use crate::{top_subject, RecognizedFace, Subject};
+
+/// Decide which of the detected faces of an image is the labeled one
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum MultiFacePolicy {
+    /// the face with the largest detection box is the labeled one
+    LargestFace,
+    /// any of the faces may be the labeled one
+    #[default]
+    AnyFace,
+    /// every face of the image must be recognized as the labeled one
+    AllFaces,
+    /// an image with more than one face is never a success
+    RejectMulti,
+}
+
+// recognition criteria options
+#[derive(Debug, clap::Parser, Clone, Default)]
+#[clap(name = "recognition-options")]
+pub struct RecognitionCriteria {
+    /// The minimum similarity, between 0 and 1, for a recognized subject to count as a prediction
+    /// Subjects below it are ignored, so a weak match of the expected subject is counted as a failure
+    /// The default value is 0, which accept any similarity
+    #[clap(long, env = "MIN_SIMILARITY", default_value = "0")]
+    pub min_similarity: f64,
+
+    /// Count a success only when the expected subject is the highest similarity candidate
+    #[clap(long, env = "TOP1_ONLY")]
+    pub top1_only: bool,
+
+    /// Which face of an image with multiple faces is the labeled one: largest-face, any-face, all-faces or reject-multi
+    /// The default value is any-face
+    #[clap(long, env = "MULTI_FACE_POLICY", default_value = "any-face")]
+    pub multi_face_policy: MultiFacePolicy,
+}
+
+impl RecognitionCriteria {
+    /// the top subject that passes the minimum similarity, None when no subject passes it
+    pub fn prediction<'a>(&self, subjects: &'a [Subject]) -> Option<&'a Subject> {
+        top_subject(subjects).filter(|s| s.similarity >= self.min_similarity)
+    }
+
+    /// true when the recognized subjects count as a successful recognition of the expected name
+    pub fn is_success(&self, name: &str, subjects: &[Subject]) -> bool {
+        if self.top1_only {
+            return self.prediction(subjects).is_some_and(|s| s.subject == name);
+        }
+        subjects
+            .iter()
+            .any(|s| s.subject == name && s.similarity >= self.min_similarity)
+    }
+
+    /// the faces that the policy considers as the labeled one, empty when the image is rejected
+    pub fn labeled_faces<'a>(&self, faces: &'a [RecognizedFace]) -> Vec<&'a RecognizedFace> {
+        match self.multi_face_policy {
+            MultiFacePolicy::AnyFace | MultiFacePolicy::AllFaces => faces.iter().collect(),
+            MultiFacePolicy::LargestFace => faces
+                .iter()
+                .max_by_key(|face| face.r#box.as_ref().map(|b| b.area()).unwrap_or(0))
+                .into_iter()
+                .collect(),
+            MultiFacePolicy::RejectMulti if faces.len() == 1 => faces.iter().collect(),
+            MultiFacePolicy::RejectMulti => Vec::new(),
+        }
+    }
+
+    /// the subjects of the labeled faces
+    pub fn labeled_subjects(&self, faces: &[RecognizedFace]) -> Vec<Subject> {
+        self.labeled_faces(faces)
+            .into_iter()
+            .flat_map(|face| face.subjects.clone())
+            .collect()
+    }
+
+    /// true when the labeled faces of the image count as a successful recognition of the expected name
+    pub fn is_recognized(&self, name: &str, faces: &[RecognizedFace]) -> bool {
+        let labeled = self.labeled_faces(faces);
+        if labeled.is_empty() {
+            return false;
+        }
+        match self.multi_face_policy {
+            MultiFacePolicy::AllFaces => labeled
+                .iter()
+                .all(|face| self.is_success(name, &face.subjects)),
+            _ => labeled
+                .iter()
+                .any(|face| self.is_success(name, &face.subjects)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::FaceBox;
+
+    use super::*;
+
+    fn subjects() -> Vec<Subject> {
+        vec![
+            Subject {
+                subject: "alice".to_string(),
+                similarity: 0.6,
+            },
+            Subject {
+                subject: "bob".to_string(),
+                similarity: 0.9,
+            },
+        ]
+    }
+
+    fn criteria(multi_face_policy: MultiFacePolicy) -> RecognitionCriteria {
+        RecognitionCriteria {
+            multi_face_policy,
+            ..Default::default()
+        }
+    }
+
+    fn face(subject: &str, size: u32) -> RecognizedFace {
+        RecognizedFace {
+            r#box: Some(FaceBox {
+                probability: Some(0.99),
+                x_min: 0,
+                y_min: 0,
+                x_max: size,
+                y_max: size,
+            }),
+            subjects: vec![Subject {
+                subject: subject.to_string(),
+                similarity: 0.9,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_default_accepts_any_similarity() {
+        let criteria = RecognitionCriteria::default();
+        assert!(criteria.is_success("alice", &subjects()));
+        assert!(!criteria.is_success("carol", &subjects()));
+    }
+
+    #[test]
+    fn test_min_similarity() {
+        let criteria = RecognitionCriteria {
+            min_similarity: 0.7,
+            ..Default::default()
+        };
+        assert!(!criteria.is_success("alice", &subjects()));
+        assert!(criteria.is_success("bob", &subjects()));
+
+        let criteria = RecognitionCriteria {
+            min_similarity: 0.95,
+            ..Default::default()
+        };
+        assert!(criteria.prediction(&subjects()).is_none());
+    }
+
+    #[test]
+    fn test_top1_only() {
+        let criteria = RecognitionCriteria {
+            top1_only: true,
+            ..Default::default()
+        };
+        assert!(!criteria.is_success("alice", &subjects()));
+        assert!(criteria.is_success("bob", &subjects()));
+    }
+
+    #[test]
+    fn test_multi_face_policies() {
+        let group = vec![face("bob", 200), face("alice", 100)];
+
+        assert!(criteria(MultiFacePolicy::AnyFace).is_recognized("alice", &group));
+        assert!(!criteria(MultiFacePolicy::LargestFace).is_recognized("alice", &group));
+        assert!(criteria(MultiFacePolicy::LargestFace).is_recognized("bob", &group));
+        assert!(!criteria(MultiFacePolicy::AllFaces).is_recognized("bob", &group));
+        assert!(!criteria(MultiFacePolicy::RejectMulti).is_recognized("bob", &group));
+
+        let single = vec![face("alice", 100)];
+        assert!(criteria(MultiFacePolicy::AllFaces).is_recognized("alice", &single));
+        assert!(criteria(MultiFacePolicy::RejectMulti).is_recognized("alice", &single));
+        assert!(!criteria(MultiFacePolicy::AnyFace).is_recognized("alice", &[]));
+    }
+}
@@ -0,0 +1,402 @@
+use std::{collections::BTreeMap, path::Path, path::PathBuf};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{
+    AugmentedFace, ConfusionMatrix, FaceProcessingResult, FailureFace, MissedFace, RecognizedFace,
+    RejectReason, RejectedFace, Subject, SubjectSummary,
+};
+
+/// The format of the run report
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ReportFormat {
+    /// a single json document
+    Json,
+    /// a csv table, one row per summary, subject, failure, missed, rejected and augmented file
+    Csv,
+    /// json lines, one record per summary, subject, failure, missed, rejected and augmented file
+    Jsonl,
+}
+
+/// Report struct is the serializable view of the final FaceProcessingResult
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub context: String,
+    pub total_count: usize,
+    pub success_count: usize,
+    pub failure_count: usize,
+    pub missed_count: usize,
+    pub rejected_count: usize,
+    /// the counters per subject
+    pub subjects: BTreeMap<String, SubjectSummary>,
+    /// every failure with its candidate subjects, ordered by similarity
+    pub failures: Vec<FailureRecord>,
+    /// every missed file with its error reason
+    pub missed: Vec<MissedFace>,
+    /// every image that was rejected by the pre-flight detection, with its reason
+    pub rejected: Vec<RejectedFace>,
+    /// expected subject -> top predicted subject -> count, only on recognition mode
+    pub confusion_matrix: ConfusionMatrix,
+    /// every trained augmented variant with its transforms and outcome, only with augment on train
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub augmented: Vec<AugmentedFace>,
+}
+
+/// A single failure of the report
+#[derive(Serialize, Debug)]
+pub struct FailureRecord {
+    pub path: PathBuf,
+    /// the subjects that were recognized instead of the expected one, empty for training failures
+    pub candidates: Vec<Subject>,
+    /// every detected face with its box and subjects, empty for training failures
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub faces: Vec<RecognizedFace>,
+}
+
+impl From<&FaceProcessingResult> for Report {
+    fn from(result: &FaceProcessingResult) -> Self {
+        let failures = result
+            .failure_faces
+            .iter()
+            .map(|face| match face {
+                FailureFace::Train(path) => FailureRecord {
+                    path: path.clone(),
+                    candidates: Vec::new(),
+                    faces: Vec::new(),
+                },
+                FailureFace::Recognize(m) => {
+                    let mut candidates = m.subjects.clone();
+                    candidates.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+                    FailureRecord {
+                        path: m.path.clone(),
+                        candidates,
+                        faces: m.faces.clone(),
+                    }
+                }
+            })
+            .collect();
+        Report {
+            context: result.context.clone(),
+            total_count: result.total_count,
+            success_count: result.success_count,
+            failure_count: result.failure_count,
+            missed_count: result.missed_count,
+            rejected_count: result.rejected_count,
+            subjects: result.subjects.clone(),
+            failures,
+            missed: result.missed_faces.clone(),
+            rejected: result.rejected_faces.clone(),
+            confusion_matrix: result.confusion_matrix.clone(),
+            augmented: result.augmented_faces.clone(),
+        }
+    }
+}
+
+/// A single record of the jsonl report
+#[derive(Serialize)]
+#[serde(tag = "record", rename_all = "kebab-case")]
+enum ReportLine<'a> {
+    Summary {
+        context: &'a str,
+        total_count: usize,
+        success_count: usize,
+        failure_count: usize,
+        missed_count: usize,
+        rejected_count: usize,
+    },
+    Subject {
+        subject: &'a str,
+        #[serde(flatten)]
+        summary: &'a SubjectSummary,
+    },
+    Failure(&'a FailureRecord),
+    Missed(&'a MissedFace),
+    Rejected(&'a RejectedFace),
+    Augmented(&'a AugmentedFace),
+    Confusion {
+        expected: &'a str,
+        predicted: &'a str,
+        count: usize,
+    },
+}
+
+/// A single row of the csv report, the columns that are not relevant for the record are left empty
+#[derive(Serialize, Default)]
+struct CsvRow<'a> {
+    record: &'a str,
+    subject: &'a str,
+    /// the top predicted subject of a confusion record
+    predicted: &'a str,
+    path: String,
+    total_count: Option<usize>,
+    success_count: Option<usize>,
+    failure_count: Option<usize>,
+    missed_count: Option<usize>,
+    rejected_count: Option<usize>,
+    /// the candidate subjects as `subject:similarity` separated by `;`
+    candidates: String,
+    reason: &'a str,
+    /// the outcome of an augmented variant
+    outcome: &'a str,
+    /// the dataset image of an augmented variant
+    source: String,
+    /// the transforms of an augmented variant
+    transforms: &'a str,
+}
+
+impl Report {
+    fn lines(&self) -> Vec<ReportLine<'_>> {
+        let mut lines = vec![ReportLine::Summary {
+            context: &self.context,
+            total_count: self.total_count,
+            success_count: self.success_count,
+            failure_count: self.failure_count,
+            missed_count: self.missed_count,
+            rejected_count: self.rejected_count,
+        }];
+        lines.extend(
+            self.subjects
+                .iter()
+                .map(|(subject, summary)| ReportLine::Subject { subject, summary }),
+        );
+        lines.extend(self.failures.iter().map(ReportLine::Failure));
+        lines.extend(self.missed.iter().map(ReportLine::Missed));
+        lines.extend(self.rejected.iter().map(ReportLine::Rejected));
+        lines.extend(self.augmented.iter().map(ReportLine::Augmented));
+        lines.extend(
+            self.confusion_matrix
+                .cells()
+                .map(|(expected, predicted, count)| ReportLine::Confusion {
+                    expected,
+                    predicted,
+                    count,
+                }),
+        );
+        lines
+    }
+
+    fn to_json(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(serde_json::to_vec_pretty(self)?)
+    }
+
+    fn to_jsonl(&self) -> anyhow::Result<Vec<u8>> {
+        let mut content = Vec::new();
+        for line in self.lines() {
+            serde_json::to_writer(&mut content, &line)?;
+            content.push(b'\n');
+        }
+        Ok(content)
+    }
+
+    fn to_csv(&self) -> anyhow::Result<Vec<u8>> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for line in self.lines() {
+            let row = match line {
+                ReportLine::Summary {
+                    context,
+                    total_count,
+                    success_count,
+                    failure_count,
+                    missed_count,
+                    rejected_count,
+                } => CsvRow {
+                    record: "summary",
+                    path: context.to_string(),
+                    total_count: Some(total_count),
+                    success_count: Some(success_count),
+                    failure_count: Some(failure_count),
+                    missed_count: Some(missed_count),
+                    rejected_count: Some(rejected_count),
+                    ..Default::default()
+                },
+                ReportLine::Subject { subject, summary } => CsvRow {
+                    record: "subject",
+                    subject,
+                    total_count: Some(summary.total_count),
+                    success_count: Some(summary.success_count),
+                    failure_count: Some(summary.failure_count),
+                    missed_count: Some(summary.missed_count),
+                    rejected_count: Some(summary.rejected_count),
+                    ..Default::default()
+                },
+                ReportLine::Failure(failure) => CsvRow {
+                    record: "failure",
+                    path: failure.path.display().to_string(),
+                    candidates: failure
+                        .candidates
+                        .iter()
+                        .map(|s| format!("{}:{:.4}", s.subject, s.similarity))
+                        .collect::<Vec<_>>()
+                        .join(";"),
+                    ..Default::default()
+                },
+                ReportLine::Missed(missed) => CsvRow {
+                    record: "missed",
+                    path: missed.path.display().to_string(),
+                    reason: &missed.reason,
+                    ..Default::default()
+                },
+                ReportLine::Rejected(rejected) => CsvRow {
+                    record: "rejected",
+                    path: rejected.path.display().to_string(),
+                    reason: match rejected.reason {
+                        RejectReason::NoFace => "no-face",
+                        RejectReason::MultiFace => "multi-face",
+                    },
+                    ..Default::default()
+                },
+                ReportLine::Augmented(augmented) => CsvRow {
+                    record: "augmented",
+                    path: augmented.path.display().to_string(),
+                    reason: augmented.reason.as_deref().unwrap_or_default(),
+                    outcome: augmented.outcome.as_str(),
+                    source: augmented.source.display().to_string(),
+                    transforms: &augmented.transforms,
+                    ..Default::default()
+                },
+                ReportLine::Confusion {
+                    expected,
+                    predicted,
+                    count,
+                } => CsvRow {
+                    record: "confusion",
+                    subject: expected,
+                    predicted,
+                    total_count: Some(count),
+                    ..Default::default()
+                },
+            };
+            writer.serialize(row)?;
+        }
+        Ok(writer.into_inner()?)
+    }
+}
+
+/// write the report of the result to the given path, in the given format
+pub async fn write_report(
+    result: &FaceProcessingResult,
+    path: &Path,
+    format: ReportFormat,
+) -> anyhow::Result<()> {
+    let report = Report::from(result);
+    let content = match format {
+        ReportFormat::Json => report.to_json()?,
+        ReportFormat::Csv => report.to_csv()?,
+        ReportFormat::Jsonl => report.to_jsonl()?,
+    };
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(path, content).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{FaceWithMetadata, Outcome};
+
+    use super::*;
+
+    fn result() -> FaceProcessingResult {
+        let mut result = FaceProcessingResult::with_context("dataset".to_string());
+        result.total_count = 3;
+        result.success_count = 1;
+        result.failure_count = 1;
+        result.missed_count = 1;
+        result
+            .failure_faces
+            .push(FailureFace::Recognize(FaceWithMetadata {
+                path: PathBuf::from("/dataset/alice/1.jpg"),
+                subjects: vec![
+                    Subject {
+                        subject: "carol".to_string(),
+                        similarity: 0.6,
+                    },
+                    Subject {
+                        subject: "bob".to_string(),
+                        similarity: 0.9,
+                    },
+                ],
+                faces: Vec::new(),
+            }));
+        result.missed_faces.push(MissedFace::new(
+            PathBuf::from("/dataset/alice/2.jpg"),
+            "timeout: request timed out",
+        ));
+        result.rejected_count = 1;
+        result.rejected_faces.push(RejectedFace {
+            path: PathBuf::from("/dataset/alice/3.jpg"),
+            reason: RejectReason::NoFace,
+            probabilities: Vec::new(),
+        });
+        result.augmented_faces.push(AugmentedFace {
+            path: PathBuf::from("/dataset/alice/4_aug_1.jpg"),
+            source: PathBuf::from("/dataset/alice/4.jpg"),
+            transforms: "flip,rotate=-7.3".to_string(),
+            hash: "hash".to_string(),
+            outcome: Outcome::Rejected,
+            reason: Some("no-face".to_string()),
+        });
+        result.summarize_subject("alice");
+        result.confusion_matrix.record("alice", Some("alice"));
+        result.confusion_matrix.record("alice", Some("bob"));
+        result
+    }
+
+    #[test]
+    fn test_failure_candidates_are_ordered_by_similarity() {
+        let report = Report::from(&result());
+        let candidates: Vec<&str> = report.failures[0]
+            .candidates
+            .iter()
+            .map(|s| s.subject.as_str())
+            .collect();
+        assert_eq!(candidates, vec!["bob", "carol"]);
+    }
+
+    #[test]
+    fn test_jsonl_has_a_record_per_line() {
+        let content = String::from_utf8(Report::from(&result()).to_jsonl().unwrap()).unwrap();
+        let records: Vec<String> = content
+            .lines()
+            .map(|line| {
+                let value: serde_json::Value = serde_json::from_str(line).unwrap();
+                value["record"].as_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(
+            records,
+            vec![
+                "summary",
+                "subject",
+                "failure",
+                "missed",
+                "rejected",
+                "augmented",
+                "confusion",
+                "confusion"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_csv_rows() {
+        let content = String::from_utf8(Report::from(&result()).to_csv().unwrap()).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "record,subject,predicted,path,total_count,success_count,failure_count,missed_count,rejected_count,candidates,reason,outcome,source,transforms",
+                "summary,,,dataset,3,1,1,1,1,,,,,",
+                "subject,alice,,,3,1,1,1,1,,,,,",
+                "failure,,,/dataset/alice/1.jpg,,,,,,bob:0.9000;carol:0.6000,,,,",
+                "missed,,,/dataset/alice/2.jpg,,,,,,,timeout: request timed out,,,",
+                "rejected,,,/dataset/alice/3.jpg,,,,,,,no-face,,,",
+                "augmented,,,/dataset/alice/4_aug_1.jpg,,,,,,,no-face,rejected,/dataset/alice/4.jpg,\"flip,rotate=-7.3\"",
+                "confusion,alice,alice,,1,,,,,,,,,",
+                "confusion,alice,bob,,1,,,,,,,,,",
+            ]
+        );
+    }
+}
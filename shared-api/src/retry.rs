@@ -0,0 +1,194 @@
+use std::{
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use tracing::warn;
+
+use crate::ApiError;
+
+// retry configuration options
+#[derive(Debug, clap::Parser, Clone)]
+#[clap(name = "retry-options")]
+pub struct RetryPolicy {
+    /// The maximum number of attempts per request, including the first one
+    /// Only transient errors are retried: connection errors, timeouts, 5xx and 429 responses
+    /// The training requests are retried only after connection errors, as they are not idempotent
+    /// The default value is 3
+    #[clap(long, env = "RETRY_MAX_ATTEMPTS", default_value = "3", value_parser = clap::value_parser!(u32).range(1..))]
+    pub retry_max_attempts: u32,
+
+    /// The delay before the first retry in milliseconds, doubled on each following retry
+    /// The default value is 500
+    #[clap(long, env = "RETRY_BASE_DELAY_MS", default_value = "500")]
+    pub retry_base_delay_ms: u64,
+
+    /// The maximum delay between two attempts in milliseconds
+    /// The default value is 10000
+    #[clap(long, env = "RETRY_MAX_DELAY_MS", default_value = "10000")]
+    pub retry_max_delay_ms: u64,
+
+    /// The random part of each delay, as a fraction of the delay between 0 and 1
+    /// The default value is 0.2, so a delay of 1000ms become a random delay between 800ms and 1200ms
+    #[clap(long, env = "RETRY_JITTER", default_value = "0.2")]
+    pub retry_jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retry_max_attempts: 3,
+            retry_base_delay_ms: 500,
+            retry_max_delay_ms: 10000,
+            retry_jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// run the operation until it succeeds, fails with a permanent error or the attempts are exhausted
+    pub async fn retry<T, F, Fut>(&self, operation: F) -> Result<T, ApiError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, ApiError>>,
+    {
+        self.retry_if(operation, ApiError::is_transient).await
+    }
+
+    /// run the operation until it succeeds, fails with an error that should not be retried
+    /// or the attempts are exhausted
+    pub async fn retry_if<T, F, Fut>(
+        &self,
+        mut operation: F,
+        should_retry: fn(&ApiError) -> bool,
+    ) -> Result<T, ApiError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, ApiError>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation().await {
+                Err(e) if should_retry(&e) && attempt < self.retry_max_attempts => {
+                    let delay = self.delay(attempt);
+                    warn!(
+                        "attempt {} of {} failed with transient error: {}, retry in {:?}",
+                        attempt, self.retry_max_attempts, e, delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// the exponential delay after the given attempt, capped by the max delay and randomized by the jitter
+    fn delay(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .retry_base_delay_ms
+            .saturating_mul(2u64.saturating_pow(attempt - 1))
+            .min(self.retry_max_delay_ms) as f64;
+        let jitter = self.retry_jitter.clamp(0.0, 1.0) * (2.0 * random_fraction() - 1.0);
+        Duration::from_millis((exponential * (1.0 + jitter)) as u64)
+    }
+}
+
+/// random number between 0 and 1, good enough to spread the retries
+fn random_fraction() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random % 10_000) as f64 / 10_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    fn policy(retry_max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            retry_max_attempts,
+            retry_base_delay_ms: 1,
+            retry_max_delay_ms: 2,
+            retry_jitter: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_delay_is_exponential_and_capped() {
+        let policy = RetryPolicy {
+            retry_max_attempts: 10,
+            retry_base_delay_ms: 100,
+            retry_max_delay_ms: 1000,
+            retry_jitter: 0.0,
+        };
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(4), Duration::from_millis(800));
+        assert_eq!(policy.delay(5), Duration::from_millis(1000));
+    }
+
+    #[tokio::test]
+    async fn test_retry_transient_error_until_success() {
+        let attempts = AtomicU32::new(0);
+        let result = policy(3)
+            .retry(|| async {
+                if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                    Err(ApiError::from_status(503, ""))
+                } else {
+                    Ok("done")
+                }
+            })
+            .await;
+        assert_eq!(result, Ok("done"));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_stops_when_attempts_exhausted() {
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), ApiError> = policy(2)
+            .retry(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(ApiError::Connect("refused".to_string()))
+            })
+            .await;
+        assert_eq!(result, Err(ApiError::Connect("refused".to_string())));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_retry_if_retries_only_the_unsent_requests() {
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), ApiError> = policy(5)
+            .retry_if(
+                || async {
+                    if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                        Err(ApiError::Connect("refused".to_string()))
+                    } else {
+                        Err(ApiError::Timeout("timed out".to_string()))
+                    }
+                },
+                ApiError::is_unsent,
+            )
+            .await;
+        assert_eq!(result, Err(ApiError::Timeout("timed out".to_string())));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_retry_does_not_retry_permanent_error() {
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), ApiError> = policy(5)
+            .retry(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(ApiError::from_status(400, "bad request"))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,453 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc::Sender, Mutex};
+use tracing::info;
+
+use crate::{
+    process_files, utils, Configuration, FaceBackend, FaceCollection, FaceProcessingResult,
+    ProgressReporter, StoredFace,
+};
+
+/// The default manifest file name, under the dataset root folder
+pub const MANIFEST_FILE_NAME: &str = ".face-trainer-manifest.json";
+
+/// The page size of listing the remote faces
+const FACES_PAGE_SIZE: u32 = 100;
+
+/// A single face that was uploaded by the sync, mapping the remote image id to the local file
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    pub image_id: String,
+    pub subject: String,
+    pub path: PathBuf,
+    /// sha256 of the file content, so a renamed file is not uploaded again
+    pub hash: String,
+}
+
+/// Manifest maps the remote image ids to the local file hashes,
+/// as the face service does not keep the original file
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// load the manifest, an empty manifest when the file does not exist
+    pub async fn load(path: &Path) -> anyhow::Result<Self> {
+        if !tokio::fs::try_exists(path).await? {
+            return Ok(Manifest::default());
+        }
+        let content = tokio::fs::read(path).await?;
+        Ok(serde_json::from_slice(&content)?)
+    }
+
+    pub async fn save(&self, path: &Path) -> anyhow::Result<()> {
+        tokio::fs::write(path, serde_json::to_vec_pretty(self)?).await?;
+        Ok(())
+    }
+}
+
+/// A local image of a subject folder
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalFace {
+    pub subject: String,
+    pub path: PathBuf,
+    pub hash: String,
+}
+
+/// SyncPlan is the diff between the local dataset and the remote collection
+#[derive(Debug, Default)]
+pub struct SyncPlan {
+    /// local subjects that do not exist remotely
+    pub create_subjects: Vec<String>,
+    /// local images that were not uploaded yet
+    pub upload: Vec<LocalFace>,
+    /// remote faces whose source file or subject folder was removed, or that moved to another subject
+    pub delete_faces: Vec<ManifestEntry>,
+    /// remote subjects that were synced before, have no local folder anymore and hold no unmanaged face
+    pub delete_subjects: Vec<String>,
+    /// local images that are already uploaded
+    pub unchanged: Vec<ManifestEntry>,
+    /// remote faces that were not uploaded by the sync, they are kept as is
+    pub unmanaged: Vec<StoredFace>,
+}
+
+impl SyncPlan {
+    /// compute the plan from the local faces, the remote subjects and faces, and the manifest
+    pub fn new(
+        local: &[LocalFace],
+        remote_subjects: &[String],
+        remote_faces: &[StoredFace],
+        manifest: &Manifest,
+    ) -> Self {
+        let remote_image_ids: BTreeSet<&str> =
+            remote_faces.iter().map(|f| f.image_id.as_str()).collect();
+        // only the manifest entries that still exist remotely are considered uploaded
+        let uploaded: HashMap<(&str, &str), &ManifestEntry> = manifest
+            .entries
+            .iter()
+            .filter(|e| remote_image_ids.contains(e.image_id.as_str()))
+            .map(|e| ((e.subject.as_str(), e.hash.as_str()), e))
+            .collect();
+        let local_keys: BTreeSet<(&str, &str)> = local
+            .iter()
+            .map(|f| (f.subject.as_str(), f.hash.as_str()))
+            .collect();
+        let local_subjects: BTreeSet<&str> = local.iter().map(|f| f.subject.as_str()).collect();
+        let remote_subjects_set: BTreeSet<&str> =
+            remote_subjects.iter().map(|s| s.as_str()).collect();
+        let managed_image_ids: BTreeSet<&str> = manifest
+            .entries
+            .iter()
+            .map(|e| e.image_id.as_str())
+            .collect();
+
+        let mut plan = SyncPlan {
+            create_subjects: local_subjects
+                .iter()
+                .filter(|s| !remote_subjects_set.contains(*s))
+                .map(|s| s.to_string())
+                .collect(),
+            ..Default::default()
+        };
+
+        let mut seen = BTreeSet::new();
+        for face in local {
+            let key = (face.subject.as_str(), face.hash.as_str());
+            // the same content twice in a folder is uploaded once
+            if !seen.insert(key) {
+                continue;
+            }
+            match uploaded.get(&key) {
+                Some(entry) => plan.unchanged.push(ManifestEntry {
+                    path: face.path.clone(),
+                    ..(*entry).clone()
+                }),
+                None => plan.upload.push(face.clone()),
+            }
+        }
+
+        let mut delete_subjects = BTreeSet::new();
+        for entry in uploaded.values() {
+            if local_keys.contains(&(entry.subject.as_str(), entry.hash.as_str())) {
+                continue;
+            }
+            plan.delete_faces.push((*entry).clone());
+            if !local_subjects.contains(entry.subject.as_str()) {
+                delete_subjects.insert(entry.subject.as_str());
+            }
+        }
+        plan.delete_faces
+            .sort_by(|a, b| a.subject.cmp(&b.subject).then(a.path.cmp(&b.path)));
+
+        plan.unmanaged = remote_faces
+            .iter()
+            .filter(|f| !managed_image_ids.contains(f.image_id.as_str()))
+            .cloned()
+            .collect();
+        // a subject that holds faces of others is kept, only the faces of the sync are deleted from it
+        let unmanaged_subjects: BTreeSet<&str> =
+            plan.unmanaged.iter().map(|f| f.subject.as_str()).collect();
+        plan.delete_subjects = delete_subjects
+            .into_iter()
+            .filter(|s| remote_subjects_set.contains(s) && !unmanaged_subjects.contains(s))
+            .map(|s| s.to_string())
+            .collect();
+        plan
+    }
+}
+
+impl Display for SyncPlan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Sync plan: create {} subjects, upload {} faces, delete {} faces, delete {} subjects, {} unchanged, {} unmanaged",
+            self.create_subjects.len(),
+            self.upload.len(),
+            self.delete_faces.len(),
+            self.delete_subjects.len(),
+            self.unchanged.len(),
+            self.unmanaged.len()
+        )?;
+        for subject in self.create_subjects.iter() {
+            writeln!(f, "+ subject: {}", subject)?;
+        }
+        for face in self.upload.iter() {
+            writeln!(f, "+ face: {} {}", face.subject, face.path.display())?;
+        }
+        for entry in self.delete_faces.iter() {
+            writeln!(
+                f,
+                "- face: {} {} ({})",
+                entry.subject,
+                entry.path.display(),
+                entry.image_id
+            )?;
+        }
+        for subject in self.delete_subjects.iter() {
+            writeln!(f, "- subject: {}", subject)?;
+        }
+        Ok(())
+    }
+}
+
+/// scan the dataset, grouped by subject, with the content hash of each image
+async fn scan_local(
+    config: &Configuration,
+    progress_reporter_tx: Sender<ProgressReporter>,
+) -> anyhow::Result<Vec<LocalFace>> {
+    let local = Arc::new(Mutex::new(Vec::new()));
+    let state = local.clone();
+    process_files(config, None, progress_reporter_tx, move |name, files, _| {
+        let state = state.clone();
+        async move {
+            let mut faces = Vec::with_capacity(files.len());
+            for path in files {
+                faces.push(LocalFace {
+                    subject: name.clone(),
+                    hash: utils::file_hash(&path).await?,
+                    path,
+                });
+            }
+            state.lock().await.extend(faces);
+            Ok(())
+        }
+    })
+    .await?;
+    let mut local = Arc::try_unwrap(local)
+        .map_err(|_| anyhow::anyhow!("the dataset scan is still running"))?
+        .into_inner();
+    local.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(local)
+}
+
+/// list all the remote faces, page by page
+async fn list_all_faces(collection: &dyn FaceCollection) -> anyhow::Result<Vec<StoredFace>> {
+    let mut faces = Vec::new();
+    let mut page = 0;
+    loop {
+        let faces_page = collection.list_faces(None, page, FACES_PAGE_SIZE).await?;
+        faces.extend(faces_page.faces);
+        page += 1;
+        if page >= faces_page.total_pages {
+            return Ok(faces);
+        }
+    }
+}
+
+/// make the remote collection mirror the dataset folder,
+/// when dry run is set only the plan is computed
+pub async fn run_sync(
+    config: &Configuration,
+    backend: Arc<dyn FaceBackend>,
+    collection: Arc<dyn FaceCollection>,
+    progress_reporter_tx: Sender<ProgressReporter>,
+) -> anyhow::Result<(SyncPlan, FaceProcessingResult)> {
+    let manifest_path = config.manifest_path();
+    let manifest = Manifest::load(&manifest_path).await?;
+    let local = scan_local(config, progress_reporter_tx.clone()).await?;
+    let remote_subjects = collection.list_subjects().await?;
+    let remote_faces = list_all_faces(collection.as_ref()).await?;
+    let plan = SyncPlan::new(&local, &remote_subjects, &remote_faces, &manifest);
+    let mut result = FaceProcessingResult::with_context(config.dataset_path().to_string());
+    progress_reporter_tx
+        .send(ProgressReporter::Increase(plan.unchanged.len() as u64))
+        .await?;
+    if config.dry_run {
+        return Ok((plan, result));
+    }
+
+    // the faces to delete stay in the manifest until they are deleted, so a failed sync can be run again
+    let mut entries: Vec<ManifestEntry> = plan
+        .unchanged
+        .iter()
+        .chain(plan.delete_faces.iter())
+        .cloned()
+        .collect();
+    let synced = apply_plan(
+        &plan,
+        backend.clone(),
+        collection,
+        &manifest_path,
+        &mut entries,
+        &mut result,
+        progress_reporter_tx,
+    )
+    .await;
+    // the uploaded faces are recorded even when the sync failed, otherwise they would be uploaded again
+    save_manifest(&manifest_path, &entries).await?;
+    backend.flush().await?;
+    synced?;
+    info!(
+        "synced: {} subjects created, {} faces deleted, {} subjects deleted, manifest: {}",
+        plan.create_subjects.len(),
+        plan.delete_faces.len(),
+        plan.delete_subjects.len(),
+        manifest_path.display()
+    );
+    Ok((plan, result))
+}
+
+/// change the remote collection by the plan, every uploaded face is added to the manifest entries
+/// and every deleted face is removed from them
+async fn apply_plan(
+    plan: &SyncPlan,
+    backend: Arc<dyn FaceBackend>,
+    collection: Arc<dyn FaceCollection>,
+    manifest_path: &Path,
+    entries: &mut Vec<ManifestEntry>,
+    result: &mut FaceProcessingResult,
+    progress_reporter_tx: Sender<ProgressReporter>,
+) -> anyhow::Result<()> {
+    for subject in plan.create_subjects.iter() {
+        collection.add_subject(subject).await?;
+    }
+    for entry in plan.delete_faces.iter() {
+        collection.delete_face(&entry.image_id).await?;
+        entries.retain(|e| e.image_id != entry.image_id);
+    }
+    for subject in plan.delete_subjects.iter() {
+        collection.delete_subject(subject).await?;
+    }
+
+    let mut uploads: BTreeMap<&str, Vec<&LocalFace>> = BTreeMap::new();
+    for face in plan.upload.iter() {
+        uploads.entry(face.subject.as_str()).or_default().push(face);
+    }
+    for (subject, faces) in uploads {
+        let hashes: HashMap<&Path, &str> = faces
+            .iter()
+            .map(|f| (f.path.as_path(), f.hash.as_str()))
+            .collect();
+        let mut partial_result = backend
+            .send_to_train(
+                subject,
+                faces.iter().map(|f| f.path.clone()).collect(),
+                progress_reporter_tx.clone(),
+            )
+            .await?;
+        partial_result.summarize_subject(subject);
+        for trained in partial_result.trained_faces.iter() {
+            if let Some(hash) = hashes.get(trained.path.as_path()) {
+                entries.push(ManifestEntry {
+                    image_id: trained.image_id.clone(),
+                    subject: trained.subject.clone(),
+                    path: trained.path.clone(),
+                    hash: hash.to_string(),
+                });
+            }
+        }
+        result.add(partial_result);
+        save_manifest(manifest_path, entries).await?;
+    }
+    Ok(())
+}
+
+/// save the manifest entries, ordered by their path
+async fn save_manifest(path: &Path, entries: &[ManifestEntry]) -> anyhow::Result<()> {
+    let mut entries = entries.to_vec();
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Manifest { entries }.save(path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(subject: &str, path: &str, hash: &str) -> LocalFace {
+        LocalFace {
+            subject: subject.to_string(),
+            path: PathBuf::from(path),
+            hash: hash.to_string(),
+        }
+    }
+
+    fn entry(image_id: &str, subject: &str, path: &str, hash: &str) -> ManifestEntry {
+        ManifestEntry {
+            image_id: image_id.to_string(),
+            subject: subject.to_string(),
+            path: PathBuf::from(path),
+            hash: hash.to_string(),
+        }
+    }
+
+    fn stored(image_id: &str, subject: &str) -> StoredFace {
+        StoredFace {
+            image_id: image_id.to_string(),
+            subject: subject.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_plan() {
+        let local_faces = vec![
+            // renamed, same content
+            local("alice", "/dataset/alice/renamed.jpg", "a1"),
+            // new
+            local("alice", "/dataset/alice/new.jpg", "a3"),
+            // new subject
+            local("carol", "/dataset/carol/1.jpg", "c1"),
+        ];
+        let manifest = Manifest {
+            entries: vec![
+                entry("id-a1", "alice", "/dataset/alice/1.jpg", "a1"),
+                // removed file
+                entry("id-a2", "alice", "/dataset/alice/2.jpg", "a2"),
+                // removed folder
+                entry("id-b1", "bob", "/dataset/bob/1.jpg", "b1"),
+                // deleted remotely, so it is not uploaded anymore
+                entry("id-gone", "alice", "/dataset/alice/gone.jpg", "a3"),
+                // removed folder of a subject that holds other faces too
+                entry("id-e1", "erin", "/dataset/erin/1.jpg", "e1"),
+            ],
+        };
+        let remote_subjects = vec![
+            "alice".to_string(),
+            "bob".to_string(),
+            "dave".to_string(),
+            "erin".to_string(),
+        ];
+        let remote_faces = vec![
+            stored("id-a1", "alice"),
+            stored("id-a2", "alice"),
+            stored("id-b1", "bob"),
+            stored("id-d1", "dave"),
+            stored("id-e1", "erin"),
+            stored("id-e2", "erin"),
+        ];
+
+        let plan = SyncPlan::new(&local_faces, &remote_subjects, &remote_faces, &manifest);
+        assert_eq!(plan.create_subjects, vec!["carol"]);
+        assert_eq!(
+            plan.upload,
+            vec![
+                local("alice", "/dataset/alice/new.jpg", "a3"),
+                local("carol", "/dataset/carol/1.jpg", "c1"),
+            ]
+        );
+        assert_eq!(
+            plan.unchanged,
+            vec![entry("id-a1", "alice", "/dataset/alice/renamed.jpg", "a1")]
+        );
+        assert_eq!(
+            plan.delete_faces,
+            vec![
+                entry("id-a2", "alice", "/dataset/alice/2.jpg", "a2"),
+                entry("id-b1", "bob", "/dataset/bob/1.jpg", "b1"),
+                entry("id-e1", "erin", "/dataset/erin/1.jpg", "e1"),
+            ]
+        );
+        // dave was never synced, and erin holds a face that the sync did not upload, so both are kept
+        assert_eq!(plan.delete_subjects, vec!["bob"]);
+        assert_eq!(
+            plan.unmanaged,
+            vec![stored("id-d1", "dave"), stored("id-e2", "erin")]
+        );
+    }
+}
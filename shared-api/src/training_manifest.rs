@@ -0,0 +1,304 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{self, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+};
+use tracing::{info, warn};
+
+use crate::{utils, AugmentedFace, FaceCollection, TrainedFace};
+
+/// The default training manifest file name, under the working directory
+pub const TRAINING_MANIFEST_FILE_NAME: &str = "face-trainer-training.jsonl";
+
+/// A single face that was added by a training run
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TrainingManifestEntry {
+    pub run_id: String,
+    /// the backend identity, see Configuration::backend_id
+    pub backend: String,
+    pub subject: String,
+    pub path: PathBuf,
+    /// sha256 of the file content
+    pub hash: String,
+    pub image_id: String,
+}
+
+/// TrainingManifest records the image id of every trained face in a jsonl file,
+/// so the faces of a single run can be deleted later
+pub struct TrainingManifest {
+    path: PathBuf,
+    run_id: String,
+    backend: String,
+    writer: Mutex<Option<fs::File>>,
+}
+
+impl TrainingManifest {
+    pub fn new(path: PathBuf, run_id: String, backend: String) -> Self {
+        TrainingManifest {
+            path,
+            run_id,
+            backend,
+            writer: Mutex::new(None),
+        }
+    }
+
+    /// append the trained faces, the file is created on the first face
+    /// The augmented variants are not on the disk, their hash is the hash of their in-memory content
+    pub async fn record(
+        &self,
+        trained_faces: &[TrainedFace],
+        augmented_faces: &[AugmentedFace],
+    ) -> anyhow::Result<()> {
+        if trained_faces.is_empty() {
+            return Ok(());
+        }
+        let mut lines = String::new();
+        for face in trained_faces {
+            let hash = match augmented_faces.iter().find(|a| a.path == face.path) {
+                Some(augmented) => augmented.hash.clone(),
+                None => utils::file_hash(&face.path).await?,
+            };
+            let entry = TrainingManifestEntry {
+                run_id: self.run_id.clone(),
+                backend: self.backend.clone(),
+                subject: face.subject.clone(),
+                path: face.path.clone(),
+                hash,
+                image_id: face.image_id.clone(),
+            };
+            lines.push_str(&serde_json::to_string(&entry)?);
+            lines.push('\n');
+        }
+
+        let mut writer = self.writer.lock().await;
+        if writer.is_none() {
+            if let Some(parent) = self.path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            *writer = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)
+                    .await?,
+            );
+        }
+        let file = writer.as_mut().unwrap();
+        file.write_all(lines.as_bytes()).await?;
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+/// read all the entries of the training manifest
+pub async fn read_training_manifest(path: &Path) -> anyhow::Result<Vec<TrainingManifestEntry>> {
+    let content = fs::read_to_string(path).await?;
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+/// The outcome of a rollback
+#[derive(Debug, Default, PartialEq)]
+pub struct RollbackResult {
+    pub deleted: usize,
+    /// the faces that could not be deleted, they are kept in the manifest to retry the rollback
+    pub failed: usize,
+}
+
+/// delete every face that was added by the given run, and remove them from the manifest
+/// The run must have been trained on the given backend, the image ids of another backend are meaningless
+pub async fn rollback(
+    collection: &dyn FaceCollection,
+    manifest_path: &Path,
+    backend: &str,
+    run_id: &str,
+) -> anyhow::Result<RollbackResult> {
+    let entries = read_training_manifest(manifest_path).await?;
+    if !entries.iter().any(|e| e.run_id == run_id) {
+        anyhow::bail!(
+            "run: {} was not found in the training manifest: {}",
+            run_id,
+            manifest_path.display()
+        );
+    }
+    if let Some(entry) = entries
+        .iter()
+        .find(|e| e.run_id == run_id && e.backend != backend)
+    {
+        anyhow::bail!(
+            "run: {} was trained on the backend: {}, not on the configured backend: {}",
+            run_id,
+            entry.backend,
+            backend
+        );
+    }
+
+    let mut result = RollbackResult::default();
+    let mut kept = Vec::with_capacity(entries.len());
+    for entry in entries {
+        if entry.run_id != run_id {
+            kept.push(entry);
+            continue;
+        }
+        match collection.delete_face(&entry.image_id).await {
+            Ok(_) => {
+                info!(
+                    "deleted face: {} of subject: {} from file: {}",
+                    entry.image_id,
+                    entry.subject,
+                    entry.path.display()
+                );
+                result.deleted += 1;
+            }
+            Err(e) => {
+                warn!(
+                    "failed to delete face: {} of subject: {}: {}",
+                    entry.image_id, entry.subject, e
+                );
+                result.failed += 1;
+                kept.push(entry);
+            }
+        }
+    }
+
+    let mut content = String::new();
+    for entry in kept {
+        content.push_str(&serde_json::to_string(&entry)?);
+        content.push('\n');
+    }
+    fs::write(manifest_path, content).await?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{FacesPage, StoredFace};
+
+    /// a collection that records the deleted image ids, failing on the given one
+    struct DeleteRecorder {
+        fail_on: &'static str,
+        deleted: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl FaceCollection for DeleteRecorder {
+        async fn list_subjects(&self) -> anyhow::Result<Vec<String>> {
+            Err(anyhow!("not supported"))
+        }
+
+        async fn add_subject(&self, _subject: &str) -> anyhow::Result<()> {
+            Err(anyhow!("not supported"))
+        }
+
+        async fn rename_subject(&self, _subject: &str, _new_name: &str) -> anyhow::Result<()> {
+            Err(anyhow!("not supported"))
+        }
+
+        async fn delete_subject(&self, _subject: &str) -> anyhow::Result<()> {
+            Err(anyhow!("not supported"))
+        }
+
+        async fn list_faces(
+            &self,
+            _subject: Option<&str>,
+            _page: u32,
+            _size: u32,
+        ) -> anyhow::Result<FacesPage> {
+            Err(anyhow!("not supported"))
+        }
+
+        async fn delete_face(&self, image_id: &str) -> anyhow::Result<StoredFace> {
+            if image_id == self.fail_on {
+                anyhow::bail!("not found");
+            }
+            self.deleted.lock().unwrap().push(image_id.to_string());
+            Ok(StoredFace {
+                image_id: image_id.to_string(),
+                subject: "alice".to_string(),
+            })
+        }
+
+        async fn delete_subject_faces(&self, _subject: &str) -> anyhow::Result<u64> {
+            Err(anyhow!("not supported"))
+        }
+    }
+
+    async fn record(manifest_path: &Path, run_id: &str, files: &[(&Path, &str)]) {
+        let manifest =
+            TrainingManifest::new(manifest_path.to_path_buf(), run_id.into(), "a".into());
+        let faces: Vec<TrainedFace> = files
+            .iter()
+            .map(|(path, image_id)| TrainedFace {
+                path: path.to_path_buf(),
+                image_id: image_id.to_string(),
+                subject: "alice".to_string(),
+            })
+            .collect();
+        manifest.record(&faces, &[]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rollback_deletes_only_the_faces_of_the_run() {
+        let dir = tempdir().unwrap();
+        let manifest_path = dir.path().join(TRAINING_MANIFEST_FILE_NAME);
+        let image = dir.path().join("1.jpg");
+        std::fs::write(&image, "image").unwrap();
+        record(
+            &manifest_path,
+            "run-1",
+            &[(&image, "id-1"), (&image, "id-2")],
+        )
+        .await;
+        record(&manifest_path, "run-2", &[(&image, "id-3")]).await;
+
+        let collection = DeleteRecorder {
+            fail_on: "id-2",
+            deleted: std::sync::Mutex::new(Vec::new()),
+        };
+        // the faces of the run are not deleted from another backend
+        let error = rollback(&collection, &manifest_path, "b", "run-1")
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "run: run-1 was trained on the backend: a, not on the configured backend: b"
+        );
+        assert!(collection.deleted.lock().unwrap().is_empty());
+
+        let result = rollback(&collection, &manifest_path, "a", "run-1")
+            .await
+            .unwrap();
+        assert_eq!(
+            result,
+            RollbackResult {
+                deleted: 1,
+                failed: 1
+            }
+        );
+        assert_eq!(*collection.deleted.lock().unwrap(), vec!["id-1"]);
+
+        let kept: Vec<String> = read_training_manifest(&manifest_path)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.image_id)
+            .collect();
+        assert_eq!(kept, vec!["id-2", "id-3"]);
+
+        assert!(rollback(&collection, &manifest_path, "a", "run-3")
+            .await
+            .is_err());
+    }
+}
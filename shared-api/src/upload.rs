@@ -0,0 +1,102 @@
+use std::path::Path;
+
+use mime_guess::MimeGuess;
+use reqwest::{multipart::Part, Body};
+use tokio::fs;
+use tokio_util::io::ReaderStream;
+
+use crate::{ApiError, AugmentedImage};
+
+/// The source of a single uploaded image, a dataset file or an in-memory augmented variant
+#[derive(Debug, Clone, Copy)]
+pub enum Upload<'a> {
+    File(&'a Path),
+    Augmented(&'a AugmentedImage),
+}
+
+impl Upload<'_> {
+    /// the path of the file, or the virtual path of the variant
+    pub fn path(&self) -> &Path {
+        match self {
+            Upload::File(path) => path,
+            Upload::Augmented(image) => &image.path,
+        }
+    }
+
+    /// the whole content of the image
+    pub async fn content(&self) -> Result<Vec<u8>, ApiError> {
+        match self {
+            Upload::File(path) => Ok(fs::read(path).await?),
+            Upload::Augmented(image) => Ok(image.content.clone()),
+        }
+    }
+
+    /// a new multipart part of the image, for every attempt
+    pub async fn part(&self) -> Result<Part, ApiError> {
+        match self {
+            Upload::File(path) => file_part(path).await,
+            Upload::Augmented(image) => {
+                let mime = MimeGuess::from_path(&image.path).first_or_octet_stream();
+                Ok(Part::bytes(image.content.clone())
+                    .file_name(
+                        image
+                            .path
+                            .file_name()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .into_owned(),
+                    )
+                    .mime_str(mime.as_ref())?)
+            }
+        }
+    }
+}
+
+/// stream the file content into a multipart part, named by the file name and typed by its extension
+/// The content is read while the request is sent, so the memory does not grow with the image size,
+/// and the length is known upfront so the request still has a content length
+/// A new part is required for every attempt, as the stream is consumed by the request
+pub async fn file_part(file_path: &Path) -> Result<Part, ApiError> {
+    let mime = MimeGuess::from_path(file_path).first_or_octet_stream();
+    let file = fs::File::open(file_path).await?;
+    let length = file.metadata().await?.len();
+
+    let part = Part::stream_with_length(Body::wrap_stream(ReaderStream::new(file)), length)
+        .file_name(
+            file_path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+        )
+        .mime_str(mime.as_ref())?;
+    Ok(part)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_part_of_missing_file() {
+        let error = file_part(Path::new("/not/existing/image.jpg"))
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(error, ApiError::Io(_)));
+    }
+
+    #[tokio::test]
+    async fn test_augmented_upload_is_named_by_its_virtual_path() {
+        let image = AugmentedImage {
+            path: "alice/1_aug_2.jpg".into(),
+            source: "alice/1.jpg".into(),
+            transforms: "flip".to_string(),
+            content: vec![1, 2, 3],
+        };
+        let upload = Upload::Augmented(&image);
+        assert_eq!(upload.path(), Path::new("alice/1_aug_2.jpg"));
+        assert_eq!(upload.content().await.unwrap(), vec![1, 2, 3]);
+        assert!(upload.part().await.is_ok());
+    }
+}
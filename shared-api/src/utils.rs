@@ -2,6 +2,7 @@ use anyhow::anyhow;
 use std::path::{Path, PathBuf};
 
 use anyhow::bail;
+use sha2::{Digest, Sha256};
 use tracing::debug;
 
 pub fn is_image(path: &Path) -> bool {
@@ -36,6 +37,22 @@ pub fn get_directory_name(group: &[Result<PathBuf, std::io::Error>]) -> anyhow::
         Ok(name)
     }
 }
+/// sha256 of the file content, as hex string
+pub async fn file_hash(path: &Path) -> std::io::Result<String> {
+    let content = tokio::fs::read(path).await?;
+    Ok(hex::encode(Sha256::digest(&content)))
+}
+
+/// sha256 of the in-memory content, as hex string
+pub fn content_hash(content: &[u8]) -> String {
+    hex::encode(Sha256::digest(content))
+}
+
+/// sha256 of the text, as hex string
+pub fn text_hash(text: &str) -> String {
+    hex::encode(Sha256::digest(text.as_bytes()))
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs;
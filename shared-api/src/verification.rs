@@ -0,0 +1,525 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use futures::{future, stream, StreamExt, TryStreamExt};
+use serde::Serialize;
+use stream_utils::RecursiveFileStream;
+use tracing::{debug, warn};
+
+use crate::{utils, Configuration, ReportFormat};
+
+// verification options
+#[derive(Debug, clap::Parser, Clone)]
+#[clap(name = "verification-options")]
+pub struct VerificationOptions {
+    /// Optional pairs file in the LFW pairs.txt format, the names are resolved under the dataset path
+    /// When not set, all the pairs within each folder and across the folders are verified
+    #[clap(long, env = "PAIRS_FILE")]
+    pub pairs_file: Option<PathBuf>,
+
+    /// The minimum similarity, between 0 and 1, of a pair to be accepted as the same person
+    /// The default value is 0.9
+    #[clap(long, env = "VERIFICATION_THRESHOLD", default_value = "0.9")]
+    pub verification_threshold: f64,
+
+    /// The maximum number of generated pairs of each kind, same person and different persons
+    /// Only when the pairs file is not set, the pairs are picked evenly across the dataset
+    /// The default value is 1000
+    #[clap(long, env = "MAX_PAIRS", default_value = "1000")]
+    pub max_pairs: usize,
+
+    /// Optional path to save the verification result of each pair
+    #[clap(long, env = "VERIFICATION_OUTPUT")]
+    pub verification_output: Option<PathBuf>,
+
+    /// The format of the verification output: json, csv or jsonl
+    /// The default value is json
+    #[clap(long, env = "VERIFICATION_FORMAT", default_value = "json")]
+    pub verification_format: ReportFormat,
+}
+
+impl Default for VerificationOptions {
+    fn default() -> Self {
+        VerificationOptions {
+            pairs_file: None,
+            verification_threshold: 0.9,
+            max_pairs: 1000,
+            verification_output: None,
+            verification_format: ReportFormat::Json,
+        }
+    }
+}
+
+/// Verifier trait
+/// A face service that compares the faces of two images (1:1), without the trained subjects
+#[async_trait]
+pub trait Verifier: Send + Sync {
+    /// the highest similarity between the faces of the source and the target images,
+    /// None when either image has no face
+    async fn verify(&self, source: &Path, target: &Path) -> anyhow::Result<Option<f64>>;
+}
+
+/// A pair of images to verify, and whether they are of the same person
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct VerificationPair {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub same: bool,
+}
+
+/// The verification of a single pair
+#[derive(Serialize, Debug, Clone)]
+pub struct VerificationSample {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub same: bool,
+    /// None when either image has no face, or the request failed
+    pub similarity: Option<f64>,
+    /// the error of a failed request, the pair is not counted in the accuracy
+    pub error: Option<String>,
+}
+
+/// VerificationResult holds the 1:1 verification of all the pairs at a single threshold
+/// A pair is accepted when its similarity is at or above the threshold, a pair without a face is rejected
+#[derive(Serialize, Debug, Default)]
+pub struct VerificationResult {
+    pub threshold: f64,
+    pub true_accepts: usize,
+    pub false_accepts: usize,
+    pub true_rejects: usize,
+    pub false_rejects: usize,
+    /// the pairs whose request failed
+    pub missed: usize,
+    /// the rate of correct decisions of the verified pairs
+    pub accuracy: f64,
+    pub samples: Vec<VerificationSample>,
+}
+
+impl VerificationResult {
+    pub fn new(samples: Vec<VerificationSample>, threshold: f64) -> Self {
+        let mut result = VerificationResult {
+            threshold,
+            ..Default::default()
+        };
+        for sample in samples.iter() {
+            if sample.error.is_some() {
+                result.missed += 1;
+                continue;
+            }
+            let accepted = sample.similarity.is_some_and(|s| s >= threshold);
+            match (sample.same, accepted) {
+                (true, true) => result.true_accepts += 1,
+                (true, false) => result.false_rejects += 1,
+                (false, true) => result.false_accepts += 1,
+                (false, false) => result.true_rejects += 1,
+            }
+        }
+        let correct = result.true_accepts + result.true_rejects;
+        let verified = samples.len() - result.missed;
+        if verified > 0 {
+            result.accuracy = correct as f64 / verified as f64;
+        }
+        result.samples = samples;
+        result
+    }
+
+    fn to_csv(&self) -> anyhow::Result<Vec<u8>> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for sample in self.samples.iter() {
+            writer.serialize(sample)?;
+        }
+        Ok(writer.into_inner()?)
+    }
+
+    fn to_jsonl(&self) -> anyhow::Result<Vec<u8>> {
+        let mut content = Vec::new();
+        for sample in self.samples.iter() {
+            serde_json::to_writer(&mut content, sample)?;
+            content.push(b'\n');
+        }
+        Ok(content)
+    }
+}
+
+impl Display for VerificationResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Verified {} pairs, accuracy at threshold {:.2}: {:.2}% (true accepts: {}, false accepts: {}, true rejects: {}, false rejects: {}), missed: {}",
+            self.samples.len() - self.missed,
+            self.threshold,
+            self.accuracy * 100.0,
+            self.true_accepts,
+            self.false_accepts,
+            self.true_rejects,
+            self.false_rejects,
+            self.missed
+        )
+    }
+}
+
+/// parse the pairs of the LFW pairs.txt format, the image of name and number n is {root}/{name}/{name}_{n:04}.jpg
+/// `name n1 n2` is a pair of the same person, `name1 n1 name2 n2` is a pair of different persons
+/// the header line of the folds and pairs count is skipped
+pub fn parse_pairs(content: &str, root: &Path) -> anyhow::Result<Vec<VerificationPair>> {
+    let image = |name: &str, number: &str| -> anyhow::Result<PathBuf> {
+        let number: u32 = number
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid image number: {} of: {}", number, name))?;
+        Ok(root.join(name).join(format!("{}_{:04}.jpg", name, number)))
+    };
+    let mut pairs = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [] => continue,
+            header if index == 0 && header.iter().all(|f| f.parse::<u32>().is_ok()) => continue,
+            [name, n1, n2] => pairs.push(VerificationPair {
+                source: image(name, n1)?,
+                target: image(name, n2)?,
+                same: true,
+            }),
+            [name1, n1, name2, n2] => pairs.push(VerificationPair {
+                source: image(name1, n1)?,
+                target: image(name2, n2)?,
+                same: false,
+            }),
+            _ => anyhow::bail!("invalid pair on line {}: {}", index + 1, line),
+        }
+    }
+    Ok(pairs)
+}
+
+/// Picks `count` of `len` enumerated items evenly, the k-th picked item is the item k * len / count,
+/// so the items can be picked while they are enumerated, without collecting all of them
+struct EvenPick {
+    len: usize,
+    count: usize,
+    picked: usize,
+    /// the position of the next enumerated item
+    index: usize,
+}
+
+impl EvenPick {
+    fn new(len: usize, max: usize) -> Self {
+        EvenPick {
+            len,
+            count: len.min(max),
+            picked: 0,
+            index: 0,
+        }
+    }
+
+    /// the position of the next picked item, None when all the items were picked
+    fn next(&self) -> Option<usize> {
+        (self.picked < self.count).then(|| self.picked * self.len / self.count)
+    }
+
+    /// skip the next `size` items when none of them is picked
+    fn skip(&mut self, size: usize) -> bool {
+        if self.next().is_some_and(|next| next < self.index + size) {
+            return false;
+        }
+        self.index += size;
+        true
+    }
+
+    /// the offsets of the picked items of the next `size` items
+    fn block(&mut self, size: usize) -> Vec<usize> {
+        let mut offsets = Vec::new();
+        while let Some(next) = self.next().filter(|next| *next < self.index + size) {
+            offsets.push(next - self.index);
+            self.picked += 1;
+        }
+        self.index += size;
+        offsets
+    }
+}
+
+/// all the pairs within each subject and across the subjects,
+/// up to max_pairs of each kind, picked evenly so all the subjects are represented
+pub fn generate_pairs(
+    subjects: &BTreeMap<String, Vec<PathBuf>>,
+    max_pairs: usize,
+) -> Vec<VerificationPair> {
+    let files: Vec<&Vec<PathBuf>> = subjects.values().collect();
+    // the number of files of the subjects after each subject
+    let mut later_files = vec![0; files.len()];
+    for i in (0..files.len().saturating_sub(1)).rev() {
+        later_files[i] = later_files[i + 1] + files[i + 1].len();
+    }
+    let same_count = files
+        .iter()
+        .map(|f| f.len() * f.len().saturating_sub(1) / 2)
+        .sum();
+    let different_count = files
+        .iter()
+        .zip(later_files.iter())
+        .map(|(f, later)| f.len() * later)
+        .sum();
+    let pair = |source: &PathBuf, target: &PathBuf, same: bool| VerificationPair {
+        source: source.clone(),
+        target: target.clone(),
+        same,
+    };
+
+    let mut same = EvenPick::new(same_count, max_pairs);
+    let mut different = EvenPick::new(different_count, max_pairs);
+    let mut same_pairs = Vec::new();
+    let mut different_pairs = Vec::new();
+    for (i, subject_files) in files.iter().enumerate() {
+        for (j, source) in subject_files.iter().enumerate() {
+            for offset in same.block(subject_files.len() - j - 1) {
+                same_pairs.push(pair(source, &subject_files[j + 1 + offset], true));
+            }
+            if different.skip(later_files[i]) {
+                continue;
+            }
+            for other_files in files.iter().skip(i + 1) {
+                for offset in different.block(other_files.len()) {
+                    different_pairs.push(pair(source, &other_files[offset], false));
+                }
+            }
+        }
+    }
+    same_pairs.extend(different_pairs);
+    same_pairs
+}
+
+/// the images of each subject, walking the dataset like the train and recognize modes,
+/// the subject of an image is the name of its folder, the images directly under the dataset path are skipped
+async fn scan_subjects(dataset_path: &str) -> anyhow::Result<BTreeMap<String, Vec<PathBuf>>> {
+    let files: Vec<PathBuf> = RecursiveFileStream::new(dataset_path)
+        .try_filter(|path| future::ready(path.is_file() && utils::is_image(path)))
+        .try_collect()
+        .await?;
+    let mut subjects: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for file in files {
+        let Some(folder) = file
+            .parent()
+            .filter(|folder| *folder != Path::new(dataset_path))
+        else {
+            continue;
+        };
+        let name = folder
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        subjects.entry(name).or_default().push(file);
+    }
+    subjects.values_mut().for_each(|images| images.sort());
+    Ok(subjects)
+}
+
+/// verify the pairs of the pairs file, or all the generated pairs of the dataset,
+/// a failed request is kept as a missed pair and does not stop the run
+pub async fn run_verify(
+    config: &Configuration,
+    verifier: Arc<dyn Verifier>,
+) -> anyhow::Result<VerificationResult> {
+    let options = &config.verification;
+    let dataset_path = Path::new(config.dataset_path());
+    let pairs = match options.pairs_file {
+        Some(ref pairs_file) => {
+            parse_pairs(&tokio::fs::read_to_string(pairs_file).await?, dataset_path)?
+        }
+        None => generate_pairs(
+            &scan_subjects(config.dataset_path()).await?,
+            options.max_pairs,
+        ),
+    };
+    debug!("verifying {} pairs", pairs.len());
+
+    let samples: Vec<VerificationSample> = stream::iter(pairs)
+        .map(|pair| {
+            let verifier = verifier.clone();
+            async move {
+                let (similarity, error) = match verifier.verify(&pair.source, &pair.target).await {
+                    Ok(similarity) => (similarity, None),
+                    Err(e) => {
+                        warn!(
+                            "failed to verify: {} with: {}: {}",
+                            pair.source.display(),
+                            pair.target.display(),
+                            e
+                        );
+                        (None, Some(e.to_string()))
+                    }
+                };
+                VerificationSample {
+                    source: pair.source,
+                    target: pair.target,
+                    same: pair.same,
+                    similarity,
+                    error,
+                }
+            }
+        })
+        .buffered(config.concurrency)
+        .collect()
+        .await;
+    Ok(VerificationResult::new(
+        samples,
+        options.verification_threshold,
+    ))
+}
+
+/// write the verification to the given path, json keeps the counters, csv and jsonl keep only the pairs
+pub async fn write_verification(
+    result: &VerificationResult,
+    path: &Path,
+    format: ReportFormat,
+) -> anyhow::Result<()> {
+    let content = match format {
+        ReportFormat::Json => serde_json::to_vec_pretty(result)?,
+        ReportFormat::Csv => result.to_csv()?,
+        ReportFormat::Jsonl => result.to_jsonl()?,
+    };
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(path, content).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lfw_pairs() {
+        let content = "10\t300\nAbel_Pacheco\t1\t4\nAbdel_Madi_Shabneh\t1\tDean_Barker\t1\n";
+        let pairs = parse_pairs(content, Path::new("/lfw")).unwrap();
+        assert_eq!(
+            pairs,
+            vec![
+                VerificationPair {
+                    source: PathBuf::from("/lfw/Abel_Pacheco/Abel_Pacheco_0001.jpg"),
+                    target: PathBuf::from("/lfw/Abel_Pacheco/Abel_Pacheco_0004.jpg"),
+                    same: true,
+                },
+                VerificationPair {
+                    source: PathBuf::from("/lfw/Abdel_Madi_Shabneh/Abdel_Madi_Shabneh_0001.jpg"),
+                    target: PathBuf::from("/lfw/Dean_Barker/Dean_Barker_0001.jpg"),
+                    same: false,
+                },
+            ]
+        );
+        assert!(parse_pairs("300\nAbel_Pacheco\t1\n", Path::new("/lfw")).is_err());
+    }
+
+    #[test]
+    fn test_generate_pairs_picks_evenly() {
+        let subjects: BTreeMap<String, Vec<PathBuf>> = [("a", 3), ("b", 1), ("c", 4), ("d", 2)]
+            .into_iter()
+            .map(|(name, count)| {
+                let files = (0..count)
+                    .map(|n| PathBuf::from(format!("{}{}.jpg", name, n)))
+                    .collect();
+                (name.to_string(), files)
+            })
+            .collect();
+        // every pair of each kind, in the order of the enumeration
+        let files: Vec<&PathBuf> = subjects.values().flatten().collect();
+        let subject_of = |path: &PathBuf| path.to_string_lossy()[..1].to_string();
+        let all: Vec<(usize, usize)> = (0..files.len())
+            .flat_map(|i| ((i + 1)..files.len()).map(move |j| (i, j)))
+            .collect();
+        for max_pairs in [1, 3, 7, 100] {
+            let pairs = generate_pairs(&subjects, max_pairs);
+            for same in [true, false] {
+                let expected: Vec<(&PathBuf, &PathBuf)> = all
+                    .iter()
+                    .map(|(i, j)| (files[*i], files[*j]))
+                    .filter(|(source, target)| (subject_of(source) == subject_of(target)) == same)
+                    .collect();
+                let count = expected.len().min(max_pairs);
+                let expected: Vec<_> = (0..count)
+                    .map(|k| expected[k * expected.len() / count])
+                    .collect();
+                let picked: Vec<_> = pairs
+                    .iter()
+                    .filter(|p| p.same == same)
+                    .map(|p| (&p.source, &p.target))
+                    .collect();
+                assert_eq!(picked, expected, "max pairs: {} same: {}", max_pairs, same);
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_pairs_and_accuracy() {
+        let subjects = BTreeMap::from([
+            (
+                "alice".to_string(),
+                vec![PathBuf::from("a1.jpg"), PathBuf::from("a2.jpg")],
+            ),
+            ("bob".to_string(), vec![PathBuf::from("b1.jpg")]),
+        ]);
+        let pairs = generate_pairs(&subjects, 1000);
+        assert_eq!(pairs.iter().filter(|p| p.same).count(), 1);
+        assert_eq!(pairs.iter().filter(|p| !p.same).count(), 2);
+        assert_eq!(generate_pairs(&subjects, 1).len(), 2);
+
+        let similarities = [Some(0.95), Some(0.92), None];
+        let mut samples: Vec<VerificationSample> = pairs
+            .into_iter()
+            .zip(similarities)
+            .map(|(pair, similarity)| VerificationSample {
+                source: pair.source,
+                target: pair.target,
+                same: pair.same,
+                similarity,
+                error: None,
+            })
+            .collect();
+        samples.push(VerificationSample {
+            source: PathBuf::from("a1.jpg"),
+            target: PathBuf::from("b1.jpg"),
+            same: false,
+            similarity: None,
+            error: Some("timeout".to_string()),
+        });
+
+        let result = VerificationResult::new(samples, 0.9);
+        assert_eq!(result.true_accepts, 1);
+        assert_eq!(result.false_accepts, 1);
+        assert_eq!(result.true_rejects, 1);
+        assert_eq!(result.false_rejects, 0);
+        assert_eq!(result.missed, 1);
+        assert!((result.accuracy - 2.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_scan_subjects_of_nested_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in [
+            "root.jpg",
+            "famous/alice/1.jpg",
+            "famous/alice/2.jpg",
+            "bob/1.jpg",
+            "bob/notes.txt",
+        ] {
+            let path = dir.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, file).unwrap();
+        }
+
+        let subjects = scan_subjects(dir.path().to_str().unwrap()).await.unwrap();
+        // a folder without images of its own is not a subject
+        assert_eq!(subjects.keys().collect::<Vec<_>>(), vec!["alice", "bob"]);
+        assert_eq!(
+            subjects["alice"],
+            vec![
+                dir.path().join("famous/alice/1.jpg"),
+                dir.path().join("famous/alice/2.jpg")
+            ]
+        );
+        assert_eq!(subjects["bob"], vec![dir.path().join("bob/1.jpg")]);
+    }
+}